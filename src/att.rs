@@ -2,23 +2,98 @@ use core::convert::TryInto;
 
 use crate::{l2cap::L2capPacket, Data};
 
+/// MTU every connection starts with.
+pub const ATT_DEFAULT_MTU: u16 = 23;
+/// Largest MTU for which a PDU including the L2CAP and ACL headers fits into `Data`.
+pub const ATT_MAX_MTU: u16 = 119;
+
+pub const ATT_EXCHANGE_MTU_REQUEST_OPCODE: u8 = 0x02;
+const ATT_EXCHANGE_MTU_RESPONSE_OPCODE: u8 = 0x03;
+pub const ATT_FIND_INFORMATION_REQUEST_OPCODE: u8 = 0x04;
+const ATT_FIND_INFORMATION_RESPONSE_OPCODE: u8 = 0x05;
+pub const ATT_FIND_BY_TYPE_VALUE_REQUEST_OPCODE: u8 = 0x06;
+const ATT_FIND_BY_TYPE_VALUE_RESPONSE_OPCODE: u8 = 0x07;
 pub const ATT_READ_BY_GROUP_TYPE_REQUEST_OPCODE: u8 = 0x10;
 const ATT_READ_BY_GROUP_TYPE_RESPONSE_OPCODE: u8 = 0x11;
-const ATT_ERROR_RESPONSE_OPCODE: u8 = 0x01;
+pub(crate) const ATT_ERROR_RESPONSE_OPCODE: u8 = 0x01;
 pub const ATT_READ_BY_TYPE_REQUEST_OPCODE: u8 = 0x08;
 const ATT_READ_BY_TYPE_RESPONSE_OPCODE: u8 = 0x09;
 pub const ATT_READ_REQUEST_OPCODE: u8 = 0x0a;
 const ATT_READ_RESPONSE_OPCODE: u8 = 0x0b;
+pub const ATT_READ_BLOB_REQUEST_OPCODE: u8 = 0x0c;
+const ATT_READ_BLOB_RESPONSE_OPCODE: u8 = 0x0d;
+pub const ATT_READ_MULTIPLE_REQUEST_OPCODE: u8 = 0x0e;
+const ATT_READ_MULTIPLE_RESPONSE_OPCODE: u8 = 0x0f;
+pub const ATT_READ_MULTIPLE_VARIABLE_REQUEST_OPCODE: u8 = 0x20;
+const ATT_READ_MULTIPLE_VARIABLE_RESPONSE_OPCODE: u8 = 0x21;
 pub const ATT_WRITE_REQUEST_OPCODE: u8 = 0x12;
 const ATT_WRITE_RESPONSE_OPCODE: u8 = 0x13;
+pub const ATT_PREPARE_WRITE_REQUEST_OPCODE: u8 = 0x16;
+const ATT_PREPARE_WRITE_RESPONSE_OPCODE: u8 = 0x17;
+pub const ATT_EXECUTE_WRITE_REQUEST_OPCODE: u8 = 0x18;
+const ATT_EXECUTE_WRITE_RESPONSE_OPCODE: u8 = 0x19;
+pub const ATT_WRITE_COMMAND_OPCODE: u8 = 0x52;
+pub const ATT_SIGNED_WRITE_COMMAND_OPCODE: u8 = 0xd2;
+const ATT_HANDLE_VALUE_NOTIFICATION_OPCODE: u8 = 0x1b;
+const ATT_HANDLE_VALUE_INDICATION_OPCODE: u8 = 0x1d;
+pub const ATT_HANDLE_VALUE_CONFIRMATION_OPCODE: u8 = 0x1e;
 
-#[derive(Debug, PartialEq)]
+#[derive(Debug, PartialEq, Clone, Copy)]
 pub enum Uuid {
     Uuid16(u16),
     Uuid128([u8; 16]),
 }
 
+/// Bluetooth Base UUID 00000000-0000-1000-8000-00805F9B34FB, 16-bit UUIDs go into bytes 2 and 3.
+const BASE_UUID: [u8; 16] = [
+    0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x10, 0x00, 0x80, 0x00, 0x00, 0x80, 0x5f, 0x9b, 0x34, 0xfb,
+];
+
 impl Uuid {
+    /// Parses a UUID from its little endian on-air representation of 2 or 16 bytes.
+    pub fn from_le_bytes(bytes: &[u8]) -> Option<Uuid> {
+        match bytes.len() {
+            2 => Some(Uuid::Uuid16((bytes[0] as u16) + ((bytes[1] as u16) << 8))),
+            16 => {
+                let mut uuid = [0u8; 16];
+                uuid.copy_from_slice(bytes);
+                uuid.reverse();
+                Some(Uuid::Uuid128(uuid))
+            }
+            _ => None,
+        }
+    }
+
+    /// Returns the 128-bit form, 16-bit UUIDs are expanded using the Bluetooth Base UUID.
+    pub fn to_uuid128(&self) -> [u8; 16] {
+        match self {
+            Uuid::Uuid16(uuid) => {
+                let mut uuid128 = BASE_UUID;
+                uuid128[2] = ((uuid >> 8) & 0xff) as u8;
+                uuid128[3] = (uuid & 0xff) as u8;
+                uuid128
+            }
+            Uuid::Uuid128(uuid) => *uuid,
+        }
+    }
+
+    /// Returns the 16-bit form of UUIDs derived from the Bluetooth Base UUID.
+    pub const fn to_uuid16(&self) -> Option<u16> {
+        match self {
+            Uuid::Uuid16(uuid) => Some(*uuid),
+            Uuid::Uuid128(uuid) => {
+                let mut i = 0;
+                while i < 16 {
+                    if i != 2 && i != 3 && uuid[i] != BASE_UUID[i] {
+                        return None;
+                    }
+                    i += 1;
+                }
+                Some(((uuid[2] as u16) << 8) | uuid[3] as u16)
+            }
+        }
+    }
+
     pub(crate) fn encode(&self) -> Data {
         let mut data = Data::default();
 
@@ -36,46 +111,121 @@ impl Uuid {
     }
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone, Copy, PartialEq)]
 pub enum AttErrorCode {
     /// Attempted to use an `Handle` that isn't valid on this server.
-    InvalidHandle = 0x01,
+    InvalidHandle,
     /// Attribute isn't readable.
-    ReadNotPermitted = 0x02,
+    ReadNotPermitted,
     /// Attribute isn't writable.
-    WriteNotPermitted = 0x03,
+    WriteNotPermitted,
     /// Attribute PDU is invalid.
-    InvalidPdu = 0x04,
+    InvalidPdu,
     /// Authentication needed before attribute can be read/written.
-    InsufficientAuthentication = 0x05,
+    InsufficientAuthentication,
     /// Server doesn't support this operation.
-    RequestNotSupported = 0x06,
+    RequestNotSupported,
     /// Offset was past the end of the attribute.
-    InvalidOffset = 0x07,
+    InvalidOffset,
     /// Authorization needed before attribute can be read/written.
-    InsufficientAuthorization = 0x08,
+    InsufficientAuthorization,
     /// Too many "prepare write" requests have been queued.
-    PrepareQueueFull = 0x09,
+    PrepareQueueFull,
     /// No attribute found within the specified attribute handle range.
-    AttributeNotFound = 0x0A,
+    AttributeNotFound,
     /// Attribute can't be read/written using *Read Key Blob* request.
-    AttributeNotLong = 0x0B,
+    AttributeNotLong,
     /// The encryption key in use is too weak to access an attribute.
-    InsufficientEncryptionKeySize = 0x0C,
+    InsufficientEncryptionKeySize,
     /// Attribute value has an incorrect length for the operation.
-    InvalidAttributeValueLength = 0x0D,
+    InvalidAttributeValueLength,
     /// Request has encountered an "unlikely" error and could not be completed.
-    UnlikelyError = 0x0E,
+    UnlikelyError,
     /// Attribute cannot be read/written without an encrypted connection.
-    InsufficientEncryption = 0x0F,
+    InsufficientEncryption,
     /// Attribute type is an invalid grouping attribute according to a higher-layer spec.
-    UnsupportedGroupType = 0x10,
+    UnsupportedGroupType,
     /// Server didn't have enough resources to complete a request.
-    InsufficientResources = 0x11,
+    InsufficientResources,
+    /// The client isn't aware of a change of the attribute database.
+    DatabaseOutOfSync,
+    /// The value is valid but not allowed in the current state.
+    ValueNotAllowed,
+    /// Application error code in the range 0x80 to 0x9F.
+    Application(u8),
+    /// Any other error code, e.g. the common profile error codes 0xE0 to 0xFF.
+    Other(u8),
+}
+
+impl AttErrorCode {
+    pub fn from_u8(value: u8) -> AttErrorCode {
+        match value {
+            0x01 => AttErrorCode::InvalidHandle,
+            0x02 => AttErrorCode::ReadNotPermitted,
+            0x03 => AttErrorCode::WriteNotPermitted,
+            0x04 => AttErrorCode::InvalidPdu,
+            0x05 => AttErrorCode::InsufficientAuthentication,
+            0x06 => AttErrorCode::RequestNotSupported,
+            0x07 => AttErrorCode::InvalidOffset,
+            0x08 => AttErrorCode::InsufficientAuthorization,
+            0x09 => AttErrorCode::PrepareQueueFull,
+            0x0a => AttErrorCode::AttributeNotFound,
+            0x0b => AttErrorCode::AttributeNotLong,
+            0x0c => AttErrorCode::InsufficientEncryptionKeySize,
+            0x0d => AttErrorCode::InvalidAttributeValueLength,
+            0x0e => AttErrorCode::UnlikelyError,
+            0x0f => AttErrorCode::InsufficientEncryption,
+            0x10 => AttErrorCode::UnsupportedGroupType,
+            0x11 => AttErrorCode::InsufficientResources,
+            0x12 => AttErrorCode::DatabaseOutOfSync,
+            0x13 => AttErrorCode::ValueNotAllowed,
+            0x80..=0x9f => AttErrorCode::Application(value),
+            _ => AttErrorCode::Other(value),
+        }
+    }
+
+    pub fn to_u8(&self) -> u8 {
+        match self {
+            AttErrorCode::InvalidHandle => 0x01,
+            AttErrorCode::ReadNotPermitted => 0x02,
+            AttErrorCode::WriteNotPermitted => 0x03,
+            AttErrorCode::InvalidPdu => 0x04,
+            AttErrorCode::InsufficientAuthentication => 0x05,
+            AttErrorCode::RequestNotSupported => 0x06,
+            AttErrorCode::InvalidOffset => 0x07,
+            AttErrorCode::InsufficientAuthorization => 0x08,
+            AttErrorCode::PrepareQueueFull => 0x09,
+            AttErrorCode::AttributeNotFound => 0x0a,
+            AttErrorCode::AttributeNotLong => 0x0b,
+            AttErrorCode::InsufficientEncryptionKeySize => 0x0c,
+            AttErrorCode::InvalidAttributeValueLength => 0x0d,
+            AttErrorCode::UnlikelyError => 0x0e,
+            AttErrorCode::InsufficientEncryption => 0x0f,
+            AttErrorCode::UnsupportedGroupType => 0x10,
+            AttErrorCode::InsufficientResources => 0x11,
+            AttErrorCode::DatabaseOutOfSync => 0x12,
+            AttErrorCode::ValueNotAllowed => 0x13,
+            AttErrorCode::Application(value) => *value,
+            AttErrorCode::Other(value) => *value,
+        }
+    }
 }
 
 #[derive(Debug)]
 pub enum Att {
+    ExchangeMtuReq {
+        mtu: u16,
+    },
+    FindInformationReq {
+        start: u16,
+        end: u16,
+    },
+    FindByTypeValueReq {
+        start: u16,
+        end: u16,
+        att_type: Uuid,
+        value: Data,
+    },
     ReadByGroupTypeReq {
         start: u16,
         end: u16,
@@ -89,10 +239,88 @@ pub enum Att {
     ReadReq {
         handle: u16,
     },
+    ReadBlobReq {
+        handle: u16,
+        offset: u16,
+    },
+    ReadMultipleReq {
+        handles: Handles,
+    },
+    ReadMultipleVariableReq {
+        handles: Handles,
+    },
     WriteReq {
         handle: u16,
         data: Data,
     },
+    WriteCmd {
+        handle: u16,
+        data: Data,
+    },
+    SignedWriteCmd {
+        handle: u16,
+        data: Data,
+        sign_counter: u32,
+        /// Authentication signature, little endian as received.
+        signature: [u8; 8],
+    },
+    PrepareWriteReq {
+        handle: u16,
+        offset: u16,
+        value: Data,
+    },
+    /// `flags` is 0x00 to cancel and 0x01 to write all prepared values.
+    ExecuteWriteReq {
+        flags: u8,
+    },
+    HandleValueConfirmation,
+    ErrorRsp {
+        /// Opcode of the request which failed.
+        request_opcode: u8,
+        handle: u16,
+        code: AttErrorCode,
+    },
+    ExchangeMtuRsp {
+        mtu: u16,
+    },
+    /// Entries of a handle and a UUID of 2 or 16 bytes.
+    FindInformationRsp {
+        information: AttributeList,
+    },
+    /// Entries of the found handle and the group end handle.
+    FindByTypeValueRsp {
+        handles: AttributeList,
+    },
+    /// Entries of the attribute handle, the group end handle and the value.
+    ReadByGroupTypeRsp {
+        attribute_list: AttributeList,
+    },
+    /// Entries of the attribute handle and the value.
+    ReadByTypeRsp {
+        attribute_list: AttributeList,
+    },
+    ReadRsp {
+        value: Data,
+    },
+    ReadBlobRsp {
+        part: Data,
+    },
+    WriteRsp,
+    /// Echo of the prepared part of the value.
+    PrepareWriteRsp {
+        handle: u16,
+        offset: u16,
+        value: Data,
+    },
+    ExecuteWriteRsp,
+    HandleValueNotification {
+        handle: u16,
+        value: Data,
+    },
+    HandleValueIndication {
+        handle: u16,
+        value: Data,
+    },
 }
 
 #[derive(Debug)]
@@ -103,45 +331,74 @@ pub enum AttParseError {
 }
 
 pub fn parse_att(packet: L2capPacket) -> Result<Att, AttParseError> {
+    if packet.payload.len == 0 {
+        return Err(AttParseError::UnexpectedPayload);
+    }
     let opcode = packet.payload.to_slice()[0];
     let payload = &packet.payload.to_slice()[1..];
 
     match opcode {
-        ATT_READ_BY_GROUP_TYPE_REQUEST_OPCODE => {
+        ATT_EXCHANGE_MTU_REQUEST_OPCODE => {
+            if payload.len() != 2 {
+                return Err(AttParseError::UnexpectedPayload);
+            }
+            let mtu = (payload[0] as u16) + ((payload[1] as u16) << 8);
+
+            Ok(Att::ExchangeMtuReq { mtu })
+        }
+        ATT_FIND_INFORMATION_REQUEST_OPCODE => {
+            if payload.len() != 4 {
+                return Err(AttParseError::UnexpectedPayload);
+            }
             let start_handle = (payload[0] as u16) + ((payload[1] as u16) << 8);
             let end_handle = (payload[2] as u16) + ((payload[3] as u16) << 8);
 
-            let group_type = if payload.len() == 6 {
-                Uuid::Uuid16((payload[4] as u16) + ((payload[5] as u16) << 8))
-            } else if payload.len() == 20 {
-                let uuid = payload[4..21]
-                    .try_into()
-                    .map_err(|_| AttParseError::Other)?;
-                Uuid::Uuid128(uuid)
-            } else {
+            Ok(Att::FindInformationReq {
+                start: start_handle,
+                end: end_handle,
+            })
+        }
+        ATT_FIND_BY_TYPE_VALUE_REQUEST_OPCODE => {
+            if payload.len() < 6 {
                 return Err(AttParseError::UnexpectedPayload);
-            };
+            }
+            let start_handle = (payload[0] as u16) + ((payload[1] as u16) << 8);
+            let end_handle = (payload[2] as u16) + ((payload[3] as u16) << 8);
+            let att_type = Uuid::Uuid16((payload[4] as u16) + ((payload[5] as u16) << 8));
+            let value = Data::new(&payload[6..]);
+
+            Ok(Att::FindByTypeValueReq {
+                start: start_handle,
+                end: end_handle,
+                att_type,
+                value,
+            })
+        }
+        ATT_READ_BY_GROUP_TYPE_REQUEST_OPCODE => {
+            if payload.len() < 4 {
+                return Err(AttParseError::UnexpectedPayload);
+            }
+            let start_handle = (payload[0] as u16) + ((payload[1] as u16) << 8);
+            let end_handle = (payload[2] as u16) + ((payload[3] as u16) << 8);
+
+            let group_type =
+                Uuid::from_le_bytes(&payload[4..]).ok_or(AttParseError::UnexpectedPayload)?;
 
             Ok(Att::ReadByGroupTypeReq {
                 start: start_handle,
                 end: end_handle,
-                group_type: group_type,
+                group_type,
             })
         }
         ATT_READ_BY_TYPE_REQUEST_OPCODE => {
+            if payload.len() < 4 {
+                return Err(AttParseError::UnexpectedPayload);
+            }
             let start_handle = (payload[0] as u16) + ((payload[1] as u16) << 8);
             let end_handle = (payload[2] as u16) + ((payload[3] as u16) << 8);
 
-            let attribute_type = if payload.len() == 6 {
-                Uuid::Uuid16((payload[4] as u16) + ((payload[5] as u16) << 8))
-            } else if payload.len() == 20 {
-                let uuid = payload[4..21]
-                    .try_into()
-                    .map_err(|_| AttParseError::Other)?;
-                Uuid::Uuid128(uuid)
-            } else {
-                return Err(AttParseError::UnexpectedPayload);
-            };
+            let attribute_type =
+                Uuid::from_le_bytes(&payload[4..]).ok_or(AttParseError::UnexpectedPayload)?;
 
             Ok(Att::ReadByTypeReq {
                 start: start_handle,
@@ -150,22 +407,263 @@ pub fn parse_att(packet: L2capPacket) -> Result<Att, AttParseError> {
             })
         }
         ATT_READ_REQUEST_OPCODE => {
+            if payload.len() != 2 {
+                return Err(AttParseError::UnexpectedPayload);
+            }
             let handle = (payload[0] as u16) + ((payload[1] as u16) << 8);
 
             Ok(Att::ReadReq { handle })
         }
+        ATT_READ_BLOB_REQUEST_OPCODE => {
+            if payload.len() != 4 {
+                return Err(AttParseError::UnexpectedPayload);
+            }
+            let handle = (payload[0] as u16) + ((payload[1] as u16) << 8);
+            let offset = (payload[2] as u16) + ((payload[3] as u16) << 8);
+
+            Ok(Att::ReadBlobReq { handle, offset })
+        }
+        ATT_READ_MULTIPLE_REQUEST_OPCODE => Ok(Att::ReadMultipleReq {
+            handles: Handles::parse(payload)?,
+        }),
+        ATT_READ_MULTIPLE_VARIABLE_REQUEST_OPCODE => Ok(Att::ReadMultipleVariableReq {
+            handles: Handles::parse(payload)?,
+        }),
         ATT_WRITE_REQUEST_OPCODE => {
+            if payload.len() < 2 {
+                return Err(AttParseError::UnexpectedPayload);
+            }
             let handle = (payload[0] as u16) + ((payload[1] as u16) << 8);
             let mut data = Data::default();
             data.append(&payload[2..]);
 
             Ok(Att::WriteReq { handle, data })
         }
+        ATT_WRITE_COMMAND_OPCODE => {
+            if payload.len() < 2 {
+                return Err(AttParseError::UnexpectedPayload);
+            }
+            let handle = (payload[0] as u16) + ((payload[1] as u16) << 8);
+            let data = Data::new(&payload[2..]);
+
+            Ok(Att::WriteCmd { handle, data })
+        }
+        ATT_SIGNED_WRITE_COMMAND_OPCODE => {
+            // handle followed by the value and a 12 byte signature
+            if payload.len() < 14 {
+                return Err(AttParseError::UnexpectedPayload);
+            }
+            let signature_start = payload.len() - 12;
+            let handle = (payload[0] as u16) + ((payload[1] as u16) << 8);
+            let data = Data::new(&payload[2..signature_start]);
+            let sign_counter = u32::from_le_bytes(
+                payload[signature_start..signature_start + 4]
+                    .try_into()
+                    .map_err(|_| AttParseError::Other)?,
+            );
+            let signature = payload[signature_start + 4..]
+                .try_into()
+                .map_err(|_| AttParseError::Other)?;
+
+            Ok(Att::SignedWriteCmd {
+                handle,
+                data,
+                sign_counter,
+                signature,
+            })
+        }
+        ATT_PREPARE_WRITE_REQUEST_OPCODE => {
+            if payload.len() < 4 {
+                return Err(AttParseError::UnexpectedPayload);
+            }
+            let handle = (payload[0] as u16) + ((payload[1] as u16) << 8);
+            let offset = (payload[2] as u16) + ((payload[3] as u16) << 8);
+            let value = Data::new(&payload[4..]);
+
+            Ok(Att::PrepareWriteReq {
+                handle,
+                offset,
+                value,
+            })
+        }
+        ATT_EXECUTE_WRITE_REQUEST_OPCODE => {
+            if payload.len() != 1 {
+                return Err(AttParseError::UnexpectedPayload);
+            }
+
+            Ok(Att::ExecuteWriteReq { flags: payload[0] })
+        }
+        ATT_HANDLE_VALUE_CONFIRMATION_OPCODE => Ok(Att::HandleValueConfirmation),
+        ATT_ERROR_RESPONSE_OPCODE => {
+            if payload.len() != 4 {
+                return Err(AttParseError::UnexpectedPayload);
+            }
+            let handle = (payload[1] as u16) + ((payload[2] as u16) << 8);
+
+            Ok(Att::ErrorRsp {
+                request_opcode: payload[0],
+                handle,
+                code: AttErrorCode::from_u8(payload[3]),
+            })
+        }
+        ATT_EXCHANGE_MTU_RESPONSE_OPCODE => {
+            if payload.len() != 2 {
+                return Err(AttParseError::UnexpectedPayload);
+            }
+            let mtu = (payload[0] as u16) + ((payload[1] as u16) << 8);
+
+            Ok(Att::ExchangeMtuRsp { mtu })
+        }
+        ATT_FIND_INFORMATION_RESPONSE_OPCODE => {
+            let entry_len = match payload.first() {
+                Some(0x01) => 4,
+                Some(0x02) => 18,
+                _ => return Err(AttParseError::UnexpectedPayload),
+            };
+
+            Ok(Att::FindInformationRsp {
+                information: AttributeList::parse(entry_len, &payload[1..])?,
+            })
+        }
+        ATT_FIND_BY_TYPE_VALUE_RESPONSE_OPCODE => Ok(Att::FindByTypeValueRsp {
+            handles: AttributeList::parse(4, payload)?,
+        }),
+        ATT_READ_BY_GROUP_TYPE_RESPONSE_OPCODE => {
+            // attribute handle, end group handle and at least a part of the value
+            let entry_len = match payload.first() {
+                Some(len) if *len > 4 => *len as usize,
+                _ => return Err(AttParseError::UnexpectedPayload),
+            };
+
+            Ok(Att::ReadByGroupTypeRsp {
+                attribute_list: AttributeList::parse(entry_len, &payload[1..])?,
+            })
+        }
+        ATT_READ_BY_TYPE_RESPONSE_OPCODE => {
+            // attribute handle and at least a part of the value
+            let entry_len = match payload.first() {
+                Some(len) if *len > 2 => *len as usize,
+                _ => return Err(AttParseError::UnexpectedPayload),
+            };
+
+            Ok(Att::ReadByTypeRsp {
+                attribute_list: AttributeList::parse(entry_len, &payload[1..])?,
+            })
+        }
+        ATT_READ_RESPONSE_OPCODE => Ok(Att::ReadRsp {
+            value: Data::new(payload),
+        }),
+        ATT_READ_BLOB_RESPONSE_OPCODE => Ok(Att::ReadBlobRsp {
+            part: Data::new(payload),
+        }),
+        ATT_WRITE_RESPONSE_OPCODE => Ok(Att::WriteRsp),
+        ATT_PREPARE_WRITE_RESPONSE_OPCODE => {
+            if payload.len() < 4 {
+                return Err(AttParseError::UnexpectedPayload);
+            }
+            let handle = (payload[0] as u16) + ((payload[1] as u16) << 8);
+            let offset = (payload[2] as u16) + ((payload[3] as u16) << 8);
+            let value = Data::new(&payload[4..]);
+
+            Ok(Att::PrepareWriteRsp {
+                handle,
+                offset,
+                value,
+            })
+        }
+        ATT_EXECUTE_WRITE_RESPONSE_OPCODE => Ok(Att::ExecuteWriteRsp),
+        ATT_HANDLE_VALUE_NOTIFICATION_OPCODE => {
+            if payload.len() < 2 {
+                return Err(AttParseError::UnexpectedPayload);
+            }
+            let handle = (payload[0] as u16) + ((payload[1] as u16) << 8);
+            let value = Data::new(&payload[2..]);
+
+            Ok(Att::HandleValueNotification { handle, value })
+        }
+        ATT_HANDLE_VALUE_INDICATION_OPCODE => {
+            if payload.len() < 2 {
+                return Err(AttParseError::UnexpectedPayload);
+            }
+            let handle = (payload[0] as u16) + ((payload[1] as u16) << 8);
+            let value = Data::new(&payload[2..]);
+
+            Ok(Att::HandleValueIndication { handle, value })
+        }
         _ => Err(AttParseError::UnknownOpcode(opcode)),
     }
 }
 
-#[derive(Debug)]
+/// Most handles a Read Multiple request can carry.
+pub const ATT_MAX_READ_MULTIPLE_HANDLES: usize = (ATT_MAX_MTU as usize - 1) / 2;
+
+/// The handles of a Read Multiple request.
+#[derive(Debug, Clone, Copy)]
+pub struct Handles {
+    handles: [u16; ATT_MAX_READ_MULTIPLE_HANDLES],
+    len: usize,
+}
+
+impl Handles {
+    pub fn new(handles: &[u16]) -> Handles {
+        let mut res = Handles {
+            handles: [0; ATT_MAX_READ_MULTIPLE_HANDLES],
+            len: handles.len(),
+        };
+        res.handles[..handles.len()].copy_from_slice(handles);
+        res
+    }
+
+    fn parse(payload: &[u8]) -> Result<Handles, AttParseError> {
+        if payload.len() < 4
+            || !payload.len().is_multiple_of(2)
+            || payload.len() / 2 > ATT_MAX_READ_MULTIPLE_HANDLES
+        {
+            return Err(AttParseError::UnexpectedPayload);
+        }
+
+        let mut handles = Handles::new(&[]);
+        for handle in payload.chunks(2) {
+            handles.handles[handles.len] = (handle[0] as u16) + ((handle[1] as u16) << 8);
+            handles.len += 1;
+        }
+        Ok(handles)
+    }
+
+    pub fn to_slice(&self) -> &[u16] {
+        &self.handles[..self.len]
+    }
+}
+
+/// The entries of a response, which all have the same length.
+#[derive(Debug, Clone, Copy)]
+pub struct AttributeList {
+    entry_len: usize,
+    data: Data,
+}
+
+impl AttributeList {
+    fn parse(entry_len: usize, payload: &[u8]) -> Result<AttributeList, AttParseError> {
+        if payload.is_empty() || !payload.len().is_multiple_of(entry_len) {
+            return Err(AttParseError::UnexpectedPayload);
+        }
+
+        Ok(AttributeList {
+            entry_len,
+            data: Data::new(payload),
+        })
+    }
+
+    pub fn entry_len(&self) -> usize {
+        self.entry_len
+    }
+
+    pub fn entries(&self) -> impl Iterator<Item = &[u8]> {
+        self.data.to_slice().chunks(self.entry_len)
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
 pub struct AttributeData {
     attribute_handle: u16,
     end_group_handle: u16,
@@ -200,7 +698,7 @@ impl AttributeData {
     }
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone, Copy)]
 pub struct AttributePayloadData {
     attribute_handle: u16,
     attribute_value: Data,
@@ -229,7 +727,107 @@ impl AttributePayloadData {
     }
 }
 
-pub fn att_encode_read_by_group_type_response(attribute_list: &[AttributeData]) -> Data {
+#[derive(Debug, Clone, Copy)]
+pub struct AttributeInformation {
+    attribute_handle: u16,
+    attribute_type: Uuid,
+}
+
+impl AttributeInformation {
+    pub fn new(attribute_handle: u16, attribute_type: Uuid) -> AttributeInformation {
+        AttributeInformation {
+            attribute_handle,
+            attribute_type,
+        }
+    }
+
+    pub fn encode(&self) -> Data {
+        let mut data = Data::default();
+        data.append(&[
+            (self.attribute_handle & 0xff) as u8,
+            ((self.attribute_handle >> 8) & 0xff) as u8,
+        ]);
+        data.append(self.attribute_type.encode().to_slice());
+        data
+    }
+}
+
+/// Encodes as many entries as fit into the MTU. All entries need to use the same UUID size, the
+/// encoding stops at the first one that doesn't.
+pub fn att_encode_find_information_response(
+    information_list: &[AttributeInformation],
+    mtu: u16,
+) -> Data {
+    let (format, entry_size) = match information_list[0].attribute_type {
+        Uuid::Uuid16(_) => (0x01, 4),
+        Uuid::Uuid128(_) => (0x02, 18),
+    };
+
+    let mut data = Data::default();
+    data.append(&[ATT_FIND_INFORMATION_RESPONSE_OPCODE]);
+    data.append(&[format]);
+
+    for information in information_list {
+        let encoded = information.encode();
+        if encoded.len != entry_size || data.len + encoded.len > mtu as usize {
+            break;
+        }
+        data.append(encoded.to_slice());
+    }
+
+    data
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct HandlesInformation {
+    found_attribute_handle: u16,
+    group_end_handle: u16,
+}
+
+impl HandlesInformation {
+    pub fn new(found_attribute_handle: u16, group_end_handle: u16) -> HandlesInformation {
+        HandlesInformation {
+            found_attribute_handle,
+            group_end_handle,
+        }
+    }
+
+    pub fn encode(&self) -> Data {
+        let mut data = Data::default();
+        data.append(&[
+            (self.found_attribute_handle & 0xff) as u8,
+            ((self.found_attribute_handle >> 8) & 0xff) as u8,
+        ]);
+        data.append(&[
+            (self.group_end_handle & 0xff) as u8,
+            ((self.group_end_handle >> 8) & 0xff) as u8,
+        ]);
+        data
+    }
+}
+
+/// Encodes as many entries as fit into the MTU.
+pub fn att_encode_find_by_type_value_response(
+    handles_list: &[HandlesInformation],
+    mtu: u16,
+) -> Data {
+    let mut data = Data::default();
+    data.append(&[ATT_FIND_BY_TYPE_VALUE_RESPONSE_OPCODE]);
+
+    for handles in handles_list {
+        let encoded = handles.encode();
+        if data.len + encoded.len > mtu as usize {
+            break;
+        }
+        data.append(encoded.to_slice());
+    }
+
+    data
+}
+
+/// Encodes as many entries as fit into the MTU. All entries need to use the same UUID size, the
+/// encoding stops at the first one that doesn't.
+pub fn att_encode_read_by_group_type_response(attribute_list: &[AttributeData], mtu: u16) -> Data {
     let attribute_data_size = match attribute_list[0].attribute_value {
         Uuid::Uuid16(_) => 6,
         Uuid::Uuid128(_) => 20,
@@ -240,7 +838,11 @@ pub fn att_encode_read_by_group_type_response(attribute_list: &[AttributeData])
     data.append(&[attribute_data_size]);
 
     for att_data in attribute_list {
-        data.append(att_data.encode().to_slice());
+        let encoded = att_data.encode();
+        if encoded.len != attribute_data_size as usize || data.len + encoded.len > mtu as usize {
+            break;
+        }
+        data.append(encoded.to_slice());
     }
 
     data
@@ -251,29 +853,93 @@ pub fn att_encode_error_response(opcode: u8, handle: u16, code: AttErrorCode) ->
     data.append(&[ATT_ERROR_RESPONSE_OPCODE]);
     data.append(&[opcode]);
     data.append(&[(handle & 0xff) as u8, ((handle >> 8) & 0xff) as u8]);
-    data.append(&[code as u8]);
+    data.append(&[code.to_u8()]);
 
     data
 }
 
-pub fn att_encode_read_by_type_response(attribute_list: &[AttributePayloadData]) -> Data {
-    let attribute_data_size = attribute_list[0].len(); // check if empty
+/// Encodes as many entries as fit into the MTU. All entries need to have the same length as the
+/// first one, the encoding stops at the first one that doesn't. A first entry too long for the
+/// MTU is truncated.
+pub fn att_encode_read_by_type_response(attribute_list: &[AttributePayloadData], mtu: u16) -> Data {
+    let max_size = core::cmp::min(mtu as usize - 2, 255);
+    let attribute_data_size = core::cmp::min(attribute_list[0].len(), max_size); // check if empty
 
     let mut data = Data::default();
     data.append(&[ATT_READ_BY_TYPE_RESPONSE_OPCODE]);
     data.append(&[attribute_data_size as u8]);
 
-    for att_data in attribute_list {
-        data.append(att_data.encode().to_slice());
+    for (i, att_data) in attribute_list.iter().enumerate() {
+        if i > 0
+            && (att_data.len() != attribute_data_size
+                || data.len + attribute_data_size > mtu as usize)
+        {
+            break;
+        }
+        data.append(&att_data.encode().to_slice()[..attribute_data_size]);
     }
 
     data
 }
 
-pub fn att_encode_read_response(payload: &Data) -> Data {
+/// The value is truncated to MTU - 1 bytes.
+pub fn att_encode_read_response(payload: &Data, mtu: u16) -> Data {
+    let len = core::cmp::min(payload.len, mtu as usize - 1);
+
     let mut data = Data::default();
     data.append(&[ATT_READ_RESPONSE_OPCODE]);
-    data.append(payload.to_slice());
+    data.append(&payload.to_slice()[..len]);
+
+    data
+}
+
+/// The part of the value starting at the requested offset. It is truncated to MTU - 1 bytes.
+pub fn att_encode_read_blob_response(part: &Data, mtu: u16) -> Data {
+    let len = core::cmp::min(part.len, mtu as usize - 1);
+
+    let mut data = Data::default();
+    data.append(&[ATT_READ_BLOB_RESPONSE_OPCODE]);
+    data.append(&part.to_slice()[..len]);
+
+    data
+}
+
+/// Concatenates the values. The response is truncated to MTU - 1 bytes, values not fitting
+/// anymore aren't taken from the iterator.
+pub fn att_encode_read_multiple_response(values: impl IntoIterator<Item = Data>, mtu: u16) -> Data {
+    let mut data = Data::default();
+    data.append(&[ATT_READ_MULTIPLE_RESPONSE_OPCODE]);
+
+    for value in values {
+        let len = core::cmp::min(value.len, mtu as usize - data.len);
+        data.append(&value.to_slice()[..len]);
+        if data.len == mtu as usize {
+            break;
+        }
+    }
+
+    data
+}
+
+/// Encodes each value with its length. The response is truncated to MTU - 1 bytes, values not
+/// fitting anymore aren't taken from the iterator.
+pub fn att_encode_read_multiple_variable_response(
+    values: impl IntoIterator<Item = Data>,
+    mtu: u16,
+) -> Data {
+    let mut data = Data::default();
+    data.append(&[ATT_READ_MULTIPLE_VARIABLE_RESPONSE_OPCODE]);
+
+    for value in values {
+        let length = (value.len as u16).to_le_bytes();
+        let len = core::cmp::min(length.len(), mtu as usize - data.len);
+        data.append(&length[..len]);
+        let len = core::cmp::min(value.len, mtu as usize - data.len);
+        data.append(&value.to_slice()[..len]);
+        if data.len == mtu as usize {
+            break;
+        }
+    }
 
     data
 }
@@ -284,3 +950,179 @@ pub fn att_encode_write_response() -> Data {
 
     data
 }
+
+/// Echoes the prepared part of the value.
+pub fn att_encode_prepare_write_response(handle: u16, offset: u16, value: &Data) -> Data {
+    let mut data = Data::default();
+    data.append(&[ATT_PREPARE_WRITE_RESPONSE_OPCODE]);
+    data.append(&[(handle & 0xff) as u8, ((handle >> 8) & 0xff) as u8]);
+    data.append(&[(offset & 0xff) as u8, ((offset >> 8) & 0xff) as u8]);
+    data.append(value.to_slice());
+
+    data
+}
+
+pub fn att_encode_execute_write_response() -> Data {
+    let mut data = Data::default();
+    data.append(&[ATT_EXECUTE_WRITE_RESPONSE_OPCODE]);
+
+    data
+}
+
+/// The value is truncated to MTU - 3 bytes.
+pub fn att_encode_handle_value_notification(handle: u16, value: &Data, mtu: u16) -> Data {
+    let len = core::cmp::min(value.len, mtu as usize - 3);
+
+    let mut data = Data::default();
+    data.append(&[ATT_HANDLE_VALUE_NOTIFICATION_OPCODE]);
+    data.append(&[(handle & 0xff) as u8, ((handle >> 8) & 0xff) as u8]);
+    data.append(&value.to_slice()[..len]);
+
+    data
+}
+
+/// The value is truncated to MTU - 3 bytes.
+pub fn att_encode_handle_value_indication(handle: u16, value: &Data, mtu: u16) -> Data {
+    let len = core::cmp::min(value.len, mtu as usize - 3);
+
+    let mut data = Data::default();
+    data.append(&[ATT_HANDLE_VALUE_INDICATION_OPCODE]);
+    data.append(&[(handle & 0xff) as u8, ((handle >> 8) & 0xff) as u8]);
+    data.append(&value.to_slice()[..len]);
+
+    data
+}
+
+pub fn att_encode_exchange_mtu_response(mtu: u16) -> Data {
+    let mut data = Data::default();
+    data.append(&[ATT_EXCHANGE_MTU_RESPONSE_OPCODE]);
+    data.append(&[(mtu & 0xff) as u8, ((mtu >> 8) & 0xff) as u8]);
+
+    data
+}
+
+pub fn att_encode_exchange_mtu_request(mtu: u16) -> Data {
+    let mut data = Data::default();
+    data.append(&[ATT_EXCHANGE_MTU_REQUEST_OPCODE]);
+    data.append(&[(mtu & 0xff) as u8, ((mtu >> 8) & 0xff) as u8]);
+
+    data
+}
+
+pub fn att_encode_find_information_request(start: u16, end: u16) -> Data {
+    let mut data = Data::default();
+    data.append(&[ATT_FIND_INFORMATION_REQUEST_OPCODE]);
+    data.append(&[(start & 0xff) as u8, ((start >> 8) & 0xff) as u8]);
+    data.append(&[(end & 0xff) as u8, ((end >> 8) & 0xff) as u8]);
+
+    data
+}
+
+/// The attribute type is always a 16-bit UUID, the value is truncated to fit the default MTU.
+pub fn att_encode_find_by_type_value_request(
+    start: u16,
+    end: u16,
+    att_type: u16,
+    value: &Data,
+) -> Data {
+    let len = core::cmp::min(value.len, ATT_DEFAULT_MTU as usize - 7);
+
+    let mut data = Data::default();
+    data.append(&[ATT_FIND_BY_TYPE_VALUE_REQUEST_OPCODE]);
+    data.append(&[(start & 0xff) as u8, ((start >> 8) & 0xff) as u8]);
+    data.append(&[(end & 0xff) as u8, ((end >> 8) & 0xff) as u8]);
+    data.append(&[(att_type & 0xff) as u8, ((att_type >> 8) & 0xff) as u8]);
+    data.append(&value.to_slice()[..len]);
+
+    data
+}
+
+pub fn att_encode_read_by_group_type_request(start: u16, end: u16, group_type: Uuid) -> Data {
+    let mut data = Data::default();
+    data.append(&[ATT_READ_BY_GROUP_TYPE_REQUEST_OPCODE]);
+    data.append(&[(start & 0xff) as u8, ((start >> 8) & 0xff) as u8]);
+    data.append(&[(end & 0xff) as u8, ((end >> 8) & 0xff) as u8]);
+    data.append(group_type.encode().to_slice());
+
+    data
+}
+
+pub fn att_encode_read_by_type_request(start: u16, end: u16, attribute_type: Uuid) -> Data {
+    let mut data = Data::default();
+    data.append(&[ATT_READ_BY_TYPE_REQUEST_OPCODE]);
+    data.append(&[(start & 0xff) as u8, ((start >> 8) & 0xff) as u8]);
+    data.append(&[(end & 0xff) as u8, ((end >> 8) & 0xff) as u8]);
+    data.append(attribute_type.encode().to_slice());
+
+    data
+}
+
+pub fn att_encode_read_request(handle: u16) -> Data {
+    let mut data = Data::default();
+    data.append(&[ATT_READ_REQUEST_OPCODE]);
+    data.append(&[(handle & 0xff) as u8, ((handle >> 8) & 0xff) as u8]);
+
+    data
+}
+
+pub fn att_encode_read_blob_request(handle: u16, offset: u16) -> Data {
+    let mut data = Data::default();
+    data.append(&[ATT_READ_BLOB_REQUEST_OPCODE]);
+    data.append(&[(handle & 0xff) as u8, ((handle >> 8) & 0xff) as u8]);
+    data.append(&[(offset & 0xff) as u8, ((offset >> 8) & 0xff) as u8]);
+
+    data
+}
+
+/// The value is truncated to MTU - 3 bytes.
+pub fn att_encode_write_request(handle: u16, value: &Data, mtu: u16) -> Data {
+    let len = core::cmp::min(value.len, mtu as usize - 3);
+
+    let mut data = Data::default();
+    data.append(&[ATT_WRITE_REQUEST_OPCODE]);
+    data.append(&[(handle & 0xff) as u8, ((handle >> 8) & 0xff) as u8]);
+    data.append(&value.to_slice()[..len]);
+
+    data
+}
+
+/// The value is truncated to MTU - 3 bytes.
+pub fn att_encode_write_command(handle: u16, value: &Data, mtu: u16) -> Data {
+    let len = core::cmp::min(value.len, mtu as usize - 3);
+
+    let mut data = Data::default();
+    data.append(&[ATT_WRITE_COMMAND_OPCODE]);
+    data.append(&[(handle & 0xff) as u8, ((handle >> 8) & 0xff) as u8]);
+    data.append(&value.to_slice()[..len]);
+
+    data
+}
+
+/// The part of the value is truncated to MTU - 5 bytes.
+pub fn att_encode_prepare_write_request(handle: u16, offset: u16, part: &Data, mtu: u16) -> Data {
+    let len = core::cmp::min(part.len, mtu as usize - 5);
+
+    let mut data = Data::default();
+    data.append(&[ATT_PREPARE_WRITE_REQUEST_OPCODE]);
+    data.append(&[(handle & 0xff) as u8, ((handle >> 8) & 0xff) as u8]);
+    data.append(&[(offset & 0xff) as u8, ((offset >> 8) & 0xff) as u8]);
+    data.append(&part.to_slice()[..len]);
+
+    data
+}
+
+/// `flags` is 0x00 to cancel and 0x01 to write all prepared values.
+pub fn att_encode_execute_write_request(flags: u8) -> Data {
+    let mut data = Data::default();
+    data.append(&[ATT_EXECUTE_WRITE_REQUEST_OPCODE]);
+    data.append(&[flags]);
+
+    data
+}
+
+pub fn att_encode_handle_value_confirmation() -> Data {
+    let mut data = Data::default();
+    data.append(&[ATT_HANDLE_VALUE_CONFIRMATION_OPCODE]);
+
+    data
+}
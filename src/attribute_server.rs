@@ -1,22 +1,76 @@
+use core::iter::once;
+
 use crate::{
     acl::{encode_acl_packet, BoundaryFlag, HostBroadcastFlag},
     att::{
-        att_encode_error_response, att_encode_read_by_group_type_response,
-        att_encode_read_by_type_response, att_encode_read_response, att_encode_write_response,
-        parse_att, Att, AttErrorCode, AttParseError, AttributeData, AttributePayloadData, Uuid,
+        att_encode_error_response, att_encode_exchange_mtu_response,
+        att_encode_execute_write_response, att_encode_find_by_type_value_response,
+        att_encode_find_information_response, att_encode_handle_value_indication,
+        att_encode_handle_value_notification, att_encode_prepare_write_response,
+        att_encode_read_blob_response, att_encode_read_by_group_type_response,
+        att_encode_read_by_type_response, att_encode_read_multiple_response,
+        att_encode_read_multiple_variable_response, att_encode_read_response,
+        att_encode_write_response, parse_att, Att, AttErrorCode, AttParseError, AttributeData,
+        AttributeInformation, AttributePayloadData, Handles, HandlesInformation, Uuid,
+        ATT_DEFAULT_MTU, ATT_EXECUTE_WRITE_REQUEST_OPCODE, ATT_FIND_BY_TYPE_VALUE_REQUEST_OPCODE,
+        ATT_FIND_INFORMATION_REQUEST_OPCODE, ATT_HANDLE_VALUE_CONFIRMATION_OPCODE, ATT_MAX_MTU,
+        ATT_PREPARE_WRITE_REQUEST_OPCODE, ATT_READ_BLOB_REQUEST_OPCODE,
         ATT_READ_BY_GROUP_TYPE_REQUEST_OPCODE, ATT_READ_BY_TYPE_REQUEST_OPCODE,
+        ATT_READ_MULTIPLE_REQUEST_OPCODE, ATT_READ_MULTIPLE_VARIABLE_REQUEST_OPCODE,
+        ATT_READ_REQUEST_OPCODE, ATT_SIGNED_WRITE_COMMAND_OPCODE, ATT_WRITE_COMMAND_OPCODE,
+        ATT_WRITE_REQUEST_OPCODE,
     },
+    crypto::{aes_cmac, AesCmac},
+    event::{ErrorCode, EventType, LeMetaEvent},
     l2cap::{encode_l2cap, parse_l2cap, L2capParseError},
-    Ble, Data,
+    Ble, Data, Error, PollResult,
 };
 
 const PRIMARY_SERVICE_UUID16: Uuid = Uuid::Uuid16(0x2800);
+const SECONDARY_SERVICE_UUID16: Uuid = Uuid::Uuid16(0x2801);
+const INCLUDE_UUID16: Uuid = Uuid::Uuid16(0x2802);
 const CHARACTERISTIC_UUID16: Uuid = Uuid::Uuid16(0x2803);
+const CLIENT_CHARACTERISTIC_CONFIGURATION_UUID16: Uuid = Uuid::Uuid16(0x2902);
+const EXTENDED_PROPERTIES_UUID16: Uuid = Uuid::Uuid16(0x2900);
+const USER_DESCRIPTION_UUID16: Uuid = Uuid::Uuid16(0x2901);
+const PRESENTATION_FORMAT_UUID16: Uuid = Uuid::Uuid16(0x2904);
+const VALID_RANGE_UUID16: Uuid = Uuid::Uuid16(0x2906);
+
+/// Characteristic property announcing a Characteristic Extended Properties descriptor.
+const CHARACTERISTIC_EXTENDED_PROPERTIES: u8 = 0x80;
+
+/// Last handle of the built-in services of `AttributeServer::new_with_generic_services`, the
+/// handles of the application's services start after it.
+pub const GENERIC_SERVICES_END_HANDLE: u16 = GENERIC_ATTRIBUTES.len() as u16;
+
+/// Number of client characteristic configurations stored per connection.
+const MAX_CCCDS: usize = 8;
+
+/// Most entries a Read By Type or Read By Group Type response can carry.
+const MAX_RESPONSE_ENTRIES: usize = (ATT_MAX_MTU as usize - 2) / 6;
+
+/// Most entries a Find Information response can carry.
+const MAX_INFORMATION_ENTRIES: usize = (ATT_MAX_MTU as usize - 2) / 4;
+
+/// Number of Prepare Write requests queued per connection.
+const MAX_PREPARED_WRITES: usize = 8;
+
+const CCCD_NOTIFY: u16 = 0x0001;
+const CCCD_INDICATE: u16 = 0x0002;
+
+/// Client features the server supports in the Client Supported Features characteristic.
+const CLIENT_FEATURE_ROBUST_CACHING: u8 = 0x01;
+const SUPPORTED_CLIENT_FEATURES: u8 = CLIENT_FEATURE_ROBUST_CACHING;
 
 #[derive(Debug)]
 pub enum AttributeServerError {
     L2capError(L2capParseError),
     AttError(AttParseError),
+    BleError(Error),
+    /// The handle isn't the value handle of a characteristic supporting the operation.
+    InvalidHandle(u16),
+    /// A previous indication wasn't confirmed by the client yet.
+    IndicationPending,
 }
 
 impl From<L2capParseError> for AttributeServerError {
@@ -31,40 +85,499 @@ impl From<AttParseError> for AttributeServerError {
     }
 }
 
-pub struct AttributeServer<'a> {
+impl From<Error> for AttributeServerError {
+    fn from(err: Error) -> Self {
+        AttributeServerError::BleError(err)
+    }
+}
+
+/// Describes the request a read or write callback is called for.
+#[derive(Debug, Clone, Copy)]
+pub struct AccessContext {
+    pub connection_handle: u16,
+    /// Opcode of the request, e.g. `ATT_READ_BLOB_REQUEST_OPCODE` or
+    /// `ATT_EXECUTE_WRITE_REQUEST_OPCODE` for values written with Prepare Write requests.
+    pub opcode: u8,
+    /// Offset the client reads from. The read callback returns the value starting at this
+    /// offset, so values can be longer than `Data`.
+    pub offset: u16,
+}
+
+/// Security state of a connection, checked against the permissions of the characteristics.
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct ConnectionSecurity {
+    pub encrypted: bool,
+    /// The encryption key was generated with MITM protection.
+    pub authenticated: bool,
+    /// The application granted the peer access to characteristics requiring authorization.
+    pub authorized: bool,
+    /// Size of the encryption key in bytes. 0 while unknown, accesses requiring encryption fail
+    /// with `AttErrorCode::InsufficientEncryptionKeySize` until the application sets it.
+    pub key_size: u8,
+}
+
+/// Contents of the built-in Generic Access service, see
+/// `AttributeServer::new_with_generic_services`.
+#[derive(Debug, Clone, Copy)]
+pub struct GenericAccess<'a> {
+    /// Name of the device, UTF-8 encoded. Only the first 128 bytes are served.
+    pub device_name: &'a str,
+    /// Appearance value from the Bluetooth SIG Assigned Numbers, e.g. 0x0540 for a generic sensor.
+    pub appearance: u16,
+    pub preferred_connection_parameters: PreferredConnectionParameters,
+}
+
+impl<'a> GenericAccess<'a> {
+    pub fn new(device_name: &'a str, appearance: u16) -> GenericAccess<'a> {
+        GenericAccess {
+            device_name,
+            appearance,
+            preferred_connection_parameters: PreferredConnectionParameters::default(),
+        }
+    }
+}
+
+/// Value of the Peripheral Preferred Connection Parameters characteristic. 0xFFFF means no
+/// specific preference.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct PreferredConnectionParameters {
+    /// Minimum connection interval in units of 1.25 ms.
+    pub min_connection_interval: u16,
+    /// Maximum connection interval in units of 1.25 ms.
+    pub max_connection_interval: u16,
+    pub peripheral_latency: u16,
+    /// Supervision timeout in units of 10 ms.
+    pub supervision_timeout: u16,
+}
+
+impl Default for PreferredConnectionParameters {
+    fn default() -> Self {
+        PreferredConnectionParameters {
+            min_connection_interval: 0xffff,
+            max_connection_interval: 0xffff,
+            peripheral_latency: 0xffff,
+            supervision_timeout: 0xffff,
+        }
+    }
+}
+
+impl PreferredConnectionParameters {
+    fn encode(&self) -> Data {
+        let mut data = Data::new(&self.min_connection_interval.to_le_bytes());
+        data.append(&self.max_connection_interval.to_le_bytes());
+        data.append(&self.peripheral_latency.to_le_bytes());
+        data.append(&self.supervision_timeout.to_le_bytes());
+        data
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+struct PreparedWrite {
+    handle: u16,
+    offset: u16,
+    value: Data,
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Connection {
+    handle: u16,
+    mtu: u16,
+    /// CCCD handle and value. Unused entries have handle 0.
+    cccd_values: [(u16, u16); MAX_CCCDS],
+    indication_pending: bool,
+    /// Parts of values queued by Prepare Write requests, in the order they were received.
+    prepared_writes: [PreparedWrite; MAX_PREPARED_WRITES],
+    prepared_writes_count: usize,
+    /// CSRK of the peer, least significant octet first.
+    signing_key: Option<[u8; 16]>,
+    /// Sign counter of the last accepted Signed Write Command.
+    sign_counter: Option<u32>,
+    security: ConnectionSecurity,
+    /// Value of the Client Supported Features characteristic written by the client.
+    client_supported_features: u8,
+}
+
+impl Connection {
+    fn new(handle: u16) -> Connection {
+        Connection {
+            handle,
+            mtu: ATT_DEFAULT_MTU,
+            cccd_values: [(0, 0); MAX_CCCDS],
+            indication_pending: false,
+            prepared_writes: [PreparedWrite {
+                handle: 0,
+                offset: 0,
+                value: Data::default(),
+            }; MAX_PREPARED_WRITES],
+            prepared_writes_count: 0,
+            signing_key: None,
+            sign_counter: None,
+            security: ConnectionSecurity::default(),
+            client_supported_features: 0,
+        }
+    }
+
+    fn cccd_value(&self, cccd_handle: u16) -> u16 {
+        self.cccd_values
+            .iter()
+            .find(|(handle, _)| *handle == cccd_handle)
+            .map_or(0, |(_, value)| *value)
+    }
+
+    /// Stores the value of a CCCD. Returns false if there is no free entry left.
+    fn set_cccd_value(&mut self, cccd_handle: u16, value: u16) -> bool {
+        let entry = self
+            .cccd_values
+            .iter()
+            .position(|(handle, _)| *handle == cccd_handle)
+            .or_else(|| self.cccd_values.iter().position(|(handle, _)| *handle == 0));
+
+        match entry {
+            Some(index) => {
+                self.cccd_values[index] = (cccd_handle, value);
+                true
+            }
+            None => false,
+        }
+    }
+}
+
+/// GATT server answering the ATT requests received by `ble`.
+///
+/// State like the MTU, subscriptions and queued writes is kept for up to `MAX_CONNECTIONS`
+/// connections at the same time. Requests on further connections are answered with
+/// `AttErrorCode::InsufficientResources`.
+pub struct AttributeServer<'a, const MAX_CONNECTIONS: usize = 1> {
     ble: &'a mut Ble<'a>,
     services: &'a mut [Service<'a>],
+    connections: [Option<Connection>; MAX_CONNECTIONS],
+    mtu: u16,
+    generic_access: Option<GenericAccess<'a>>,
+    /// Value of the Database Hash characteristic, least significant octet first.
+    database_hash: [u8; 16],
 }
 
 impl<'a> AttributeServer<'a> {
     pub fn new(ble: &'a mut Ble<'a>, services: &'a mut [Service<'a>]) -> AttributeServer<'a> {
-        let mut current_handle = 1;
+        AttributeServer::new_with_max_connections(ble, services, None)
+    }
+
+    /// Creates a server which also provides the Generic Access (0x1800) and Generic Attribute
+    /// (0x1801) services at the start of the handle space, before the given services.
+    pub fn new_with_generic_services(
+        ble: &'a mut Ble<'a>,
+        services: &'a mut [Service<'a>],
+        generic_access: GenericAccess<'a>,
+    ) -> AttributeServer<'a> {
+        AttributeServer::new_with_max_connections(ble, services, Some(generic_access))
+    }
+}
+
+impl<'a, const MAX_CONNECTIONS: usize> AttributeServer<'a, MAX_CONNECTIONS> {
+    /// Creates a server which keeps state for up to `MAX_CONNECTIONS` connections, e.g.
+    /// `AttributeServer::<4>::new_with_max_connections(&mut ble, services, None)`.
+    ///
+    /// The generic services are provided if `generic_access` is given, see
+    /// `new_with_generic_services`.
+    pub fn new_with_max_connections(
+        ble: &'a mut Ble<'a>,
+        services: &'a mut [Service<'a>],
+        generic_access: Option<GenericAccess<'a>>,
+    ) -> AttributeServer<'a, MAX_CONNECTIONS> {
+        let mut current_handle = match generic_access {
+            Some(_) => GENERIC_SERVICES_END_HANDLE + 1,
+            None => 1,
+        };
+        let service_count = services.len();
         for service in services.iter_mut() {
             service.start_handle = current_handle;
-            service.end_handle = current_handle + 2;
-            service.characteristics_handle = current_handle + 2;
-            current_handle += 3;
+            // include declarations follow the service declaration
+            current_handle += service
+                .includes
+                .iter()
+                .filter(|index| **index < service_count)
+                .count() as u16;
+            for characteristic in service.characteristics.iter_mut() {
+                characteristic.declaration_handle = current_handle + 1;
+                characteristic.value_handle = current_handle + 2;
+                current_handle += 2;
+
+                if characteristic.properties & (ATT_NOTIFY | ATT_INDICATE) != 0 {
+                    characteristic.cccd_handle = current_handle + 1;
+                    current_handle += 1;
+                }
+
+                for descriptor in characteristic.descriptors.iter_mut() {
+                    descriptor.handle = current_handle + 1;
+                    current_handle += 1;
+                }
+            }
+            service.end_handle = current_handle;
+            current_handle += 1;
+        }
+        let mut server = AttributeServer {
+            ble,
+            services,
+            connections: [None; MAX_CONNECTIONS],
+            mtu: ATT_DEFAULT_MTU,
+            generic_access,
+            database_hash: [0u8; 16],
+        };
+        // the table can't change after this, the handles are assigned only here
+        server.database_hash = server.calculate_database_hash();
+        server
+    }
+
+    /// Sets the receive MTU offered to clients in the MTU exchange.
+    ///
+    /// The value is clamped to `ATT_DEFAULT_MTU..=ATT_MAX_MTU`.
+    pub fn set_mtu(&mut self, mtu: u16) {
+        self.mtu = mtu.clamp(ATT_DEFAULT_MTU, ATT_MAX_MTU);
+    }
+
+    /// Handles of the connections currently known to the server.
+    pub fn connection_handles(&self) -> impl Iterator<Item = u16> + '_ {
+        self.connections
+            .iter()
+            .flatten()
+            .map(|connection| connection.handle)
+    }
+
+    /// Security state of the given connection.
+    pub fn security(&self, connection_handle: u16) -> Option<ConnectionSecurity> {
+        self.connection(connection_handle)
+            .map(|connection| connection.security)
+    }
+
+    /// Sets the security state of the given connection.
+    ///
+    /// Encryption is tracked from the Encryption Change events seen by `do_work`, whether the key
+    /// is authenticated, its size and the authorization need to be provided by the application.
+    pub fn set_security(&mut self, connection_handle: u16, security: ConnectionSecurity) {
+        if self.has_connection_slot(connection_handle) {
+            self.connection_mut(connection_handle).security = security;
+        }
+    }
+
+    /// Sets the Connection Signature Resolving Key the peer of the given connection signs its
+    /// writes with, in the byte order distributed during pairing.
+    ///
+    /// Signed Write Commands are ignored while no key is set. The key is forgotten on disconnect.
+    pub fn set_signing_key(&mut self, connection_handle: u16, csrk: [u8; 16]) {
+        if self.has_connection_slot(connection_handle) {
+            let connection = self.connection_mut(connection_handle);
+            connection.signing_key = Some(csrk);
+            connection.sign_counter = None;
+        }
+    }
+
+    /// Sends a notification for the characteristic with the given value handle to all
+    /// connections which enabled notifications.
+    pub fn notify(&mut self, handle: u16, data: &Data) -> Result<(), AttributeServerError> {
+        let cccd_handle = self.cccd_handle(handle, ATT_NOTIFY)?;
+
+        for index in 0..MAX_CONNECTIONS {
+            if let Some(connection_handle) = self.connection_handle_at(index) {
+                self.notify_subscribed(connection_handle, cccd_handle, handle, data);
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Sends a notification for the characteristic with the given value handle on the given
+    /// connection only, if it enabled notifications.
+    pub fn notify_connection(
+        &mut self,
+        connection_handle: u16,
+        handle: u16,
+        data: &Data,
+    ) -> Result<(), AttributeServerError> {
+        let cccd_handle = self.cccd_handle(handle, ATT_NOTIFY)?;
+
+        self.notify_subscribed(connection_handle, cccd_handle, handle, data);
+
+        Ok(())
+    }
+
+    /// Sends an indication for the characteristic with the given value handle to all
+    /// connections which enabled indications.
+    ///
+    /// Only one indication can be outstanding per connection. Until the client confirmed it
+    /// (which is handled by `do_work`) this returns `AttributeServerError::IndicationPending`
+    /// without sending anything.
+    pub fn indicate(&mut self, handle: u16, data: &Data) -> Result<(), AttributeServerError> {
+        let cccd_handle = self.cccd_handle(handle, ATT_INDICATE)?;
+
+        if self.is_indication_pending() {
+            return Err(AttributeServerError::IndicationPending);
+        }
+
+        for index in 0..MAX_CONNECTIONS {
+            if let Some(connection_handle) = self.connection_handle_at(index) {
+                self.indicate_subscribed(connection_handle, cccd_handle, handle, data);
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Sends an indication for the characteristic with the given value handle on the given
+    /// connection only, if it enabled indications.
+    ///
+    /// Returns `AttributeServerError::IndicationPending` while this connection didn't confirm
+    /// the previous indication, other connections don't matter.
+    pub fn indicate_connection(
+        &mut self,
+        connection_handle: u16,
+        handle: u16,
+        data: &Data,
+    ) -> Result<(), AttributeServerError> {
+        let cccd_handle = self.cccd_handle(handle, ATT_INDICATE)?;
+
+        if matches!(self.connection(connection_handle), Some(connection) if connection.indication_pending)
+        {
+            return Err(AttributeServerError::IndicationPending);
         }
-        AttributeServer { ble, services }
+        self.indicate_subscribed(connection_handle, cccd_handle, handle, data);
+
+        Ok(())
+    }
+
+    /// Indicates a change of the attributes in the given handle range with the Service Changed
+    /// characteristic to all connections which enabled it.
+    ///
+    /// Fails with `AttributeServerError::InvalidHandle` if the server was created without the
+    /// generic services.
+    pub fn indicate_service_changed(
+        &mut self,
+        start: u16,
+        end: u16,
+    ) -> Result<(), AttributeServerError> {
+        let mut data = Data::new(&start.to_le_bytes());
+        data.append(&end.to_le_bytes());
+        self.indicate(GenericAttribute::ServiceChanged.handle(), &data)
+    }
+
+    /// Returns true while an indication wasn't confirmed by all connections.
+    pub fn is_indication_pending(&self) -> bool {
+        self.connections.iter().any(
+            |connection| matches!(connection, Some(connection) if connection.indication_pending),
+        )
+    }
+
+    fn notify_subscribed(
+        &mut self,
+        connection_handle: u16,
+        cccd_handle: u16,
+        handle: u16,
+        data: &Data,
+    ) {
+        let mtu = match self.connection(connection_handle) {
+            Some(connection) if connection.cccd_value(cccd_handle) & CCCD_NOTIFY != 0 => {
+                connection.mtu
+            }
+            _ => return,
+        };
+        self.write_att(
+            connection_handle,
+            att_encode_handle_value_notification(handle, data, mtu),
+        );
+    }
+
+    fn indicate_subscribed(
+        &mut self,
+        connection_handle: u16,
+        cccd_handle: u16,
+        handle: u16,
+        data: &Data,
+    ) {
+        let mtu = match self.connection(connection_handle) {
+            Some(connection) if connection.cccd_value(cccd_handle) & CCCD_INDICATE != 0 => {
+                connection.mtu
+            }
+            _ => return,
+        };
+        self.connection_mut(connection_handle).indication_pending = true;
+        self.write_att(
+            connection_handle,
+            att_encode_handle_value_indication(handle, data, mtu),
+        );
     }
 
     pub fn do_work(&mut self) -> Result<(), AttributeServerError> {
-        let packet = self.ble.poll();
+        let packet = self.ble.poll()?;
 
         match packet {
             None => Ok(()),
             Some(packet) => match packet {
-                crate::PollResult::Event(_) => Ok(()),
-                crate::PollResult::AsyncData(packet) => {
+                PollResult::Event(event) => {
+                    self.handle_event(event);
+                    Ok(())
+                }
+                PollResult::AsyncData(packet) => {
+                    let connection_handle = packet.handle;
                     let l2cap_packet = parse_l2cap(packet)?;
-                    let packet = parse_att(l2cap_packet)?;
+                    let opcode = l2cap_packet.payload.to_slice().first().copied();
+
+                    if !self.has_connection_slot(connection_handle) {
+                        if let Some(opcode) = opcode.filter(|opcode| is_request(*opcode)) {
+                            self.write_att(
+                                connection_handle,
+                                att_encode_error_response(
+                                    opcode,
+                                    0,
+                                    AttErrorCode::InsufficientResources,
+                                ),
+                            );
+                        }
+                        return Ok(());
+                    }
+                    self.ensure_connection(connection_handle);
+                    let packet = match parse_att(l2cap_packet) {
+                        Ok(packet) => packet,
+                        Err(err) => {
+                            return self.handle_att_parse_error(connection_handle, opcode, err)
+                        }
+                    };
                     match packet {
+                        Att::ExchangeMtuReq { mtu } => {
+                            self.handle_exchange_mtu_req(connection_handle, mtu);
+                            Ok(())
+                        }
+
+                        Att::FindInformationReq { start, end } => {
+                            self.handle_find_information_req(connection_handle, start, end);
+                            Ok(())
+                        }
+
+                        Att::FindByTypeValueReq {
+                            start,
+                            end,
+                            att_type,
+                            value,
+                        } => {
+                            self.handle_find_by_type_value_req(
+                                connection_handle,
+                                start,
+                                end,
+                                att_type,
+                                value,
+                            );
+                            Ok(())
+                        }
+
                         Att::ReadByGroupTypeReq {
                             start,
                             end,
                             group_type,
                         } => {
-                            self.handle_read_by_group_type_req(start, end, group_type);
+                            self.handle_read_by_group_type_req(
+                                connection_handle,
+                                start,
+                                end,
+                                group_type,
+                            );
                             Ok(())
                         }
 
@@ -73,155 +586,2157 @@ impl<'a> AttributeServer<'a> {
                             end,
                             attribute_type,
                         } => {
-                            self.handle_read_by_type_req(start, end, attribute_type);
+                            self.handle_read_by_type_req(
+                                connection_handle,
+                                start,
+                                end,
+                                attribute_type,
+                            );
                             Ok(())
                         }
 
                         Att::ReadReq { handle } => {
-                            self.handle_read_req(handle);
+                            self.handle_read_req(connection_handle, handle);
+                            Ok(())
+                        }
+
+                        Att::ReadBlobReq { handle, offset } => {
+                            self.handle_read_blob_req(connection_handle, handle, offset);
+                            Ok(())
+                        }
+
+                        Att::ReadMultipleReq { handles } => {
+                            self.handle_read_multiple_req(
+                                connection_handle,
+                                ATT_READ_MULTIPLE_REQUEST_OPCODE,
+                                handles,
+                            );
+                            Ok(())
+                        }
+
+                        Att::ReadMultipleVariableReq { handles } => {
+                            self.handle_read_multiple_req(
+                                connection_handle,
+                                ATT_READ_MULTIPLE_VARIABLE_REQUEST_OPCODE,
+                                handles,
+                            );
                             Ok(())
                         }
 
                         Att::WriteReq { handle, data } => {
-                            self.handle_write_req(handle, data);
+                            self.handle_write_req(connection_handle, handle, data);
+                            Ok(())
+                        }
+
+                        Att::WriteCmd { handle, data } => {
+                            self.handle_write_cmd(connection_handle, handle, data);
+                            Ok(())
+                        }
+
+                        Att::SignedWriteCmd {
+                            handle,
+                            data,
+                            sign_counter,
+                            signature,
+                        } => {
+                            self.handle_signed_write_cmd(
+                                connection_handle,
+                                handle,
+                                data,
+                                sign_counter,
+                                signature,
+                            );
+                            Ok(())
+                        }
+
+                        Att::PrepareWriteReq {
+                            handle,
+                            offset,
+                            value,
+                        } => {
+                            self.handle_prepare_write_req(connection_handle, handle, offset, value);
+                            Ok(())
+                        }
+
+                        Att::ExecuteWriteReq { flags } => {
+                            self.handle_execute_write_req(connection_handle, flags);
+                            Ok(())
+                        }
+
+                        Att::HandleValueConfirmation => {
+                            self.connection_mut(connection_handle).indication_pending = false;
                             Ok(())
                         }
+
+                        // responses, notifications and indications are meant for a client on
+                        // the same connection
+                        Att::ErrorRsp { .. }
+                        | Att::ExchangeMtuRsp { .. }
+                        | Att::FindInformationRsp { .. }
+                        | Att::FindByTypeValueRsp { .. }
+                        | Att::ReadByGroupTypeRsp { .. }
+                        | Att::ReadByTypeRsp { .. }
+                        | Att::ReadRsp { .. }
+                        | Att::ReadBlobRsp { .. }
+                        | Att::WriteRsp
+                        | Att::PrepareWriteRsp { .. }
+                        | Att::ExecuteWriteRsp
+                        | Att::HandleValueNotification { .. }
+                        | Att::HandleValueIndication { .. } => Ok(()),
                     }
                 }
             },
         }
     }
 
-    fn handle_read_by_group_type_req(&mut self, start: u16, end: u16, group_type: Uuid) {
-        if group_type == PRIMARY_SERVICE_UUID16 {
-            // TODO respond with all finds - not just one
-            for service in self.services.iter() {
-                if service.start_handle >= start && service.end_handle <= end {
-                    let attribute_list = [AttributeData::new(
-                        service.start_handle,
-                        service.end_handle,
-                        group_type,
-                    )];
-                    self.write_att(att_encode_read_by_group_type_response(&attribute_list));
-                    return;
+    fn handle_event(&mut self, event: EventType) {
+        match event {
+            EventType::LeMeta(LeMetaEvent::ConnectionComplete {
+                status: ErrorCode::Okay,
+                handle,
+                ..
+            })
+            | EventType::LeMeta(LeMetaEvent::EnhancedConnectionComplete {
+                status: ErrorCode::Okay,
+                handle,
+                ..
+            }) if self.has_connection_slot(handle) => {
+                self.connection_mut(handle);
+            }
+            EventType::EncryptionChange {
+                status: ErrorCode::Okay,
+                handle,
+                enabled,
+            } if self.has_connection_slot(handle) => {
+                self.connection_mut(handle).security.encrypted = enabled;
+            }
+            EventType::DisconnectComplete {
+                handle,
+                status: ErrorCode::Okay,
+                ..
+            } => {
+                for connection in self.connections.iter_mut() {
+                    if matches!(connection, Some(connection) if connection.handle == handle) {
+                        *connection = None;
+                    }
                 }
             }
+            _ => (),
         }
+    }
 
-        // respond with error
-        self.write_att(att_encode_error_response(
-            ATT_READ_BY_GROUP_TYPE_REQUEST_OPCODE,
-            start,
-            AttErrorCode::AttributeNotFound,
-        ));
-    }
-
-    fn handle_read_by_type_req(&mut self, start: u16, end: u16, attribute_type: Uuid) {
-        if attribute_type == CHARACTERISTIC_UUID16 {
-            // TODO respond with all finds - not just one
-            for service in self.services.iter() {
-                if service.start_handle >= start && service.end_handle <= end {
-                    let mut data = Data::new(&[
-                        service.permissions,
-                        // 2 byte handle pointing to characteristic value
-                        (service.characteristics_handle & 0xff) as u8,
-                        ((service.characteristics_handle & 0xff00) >> 8) as u8,
-                        // UUID of characteristic value
-                    ]);
-                    data.append((&service.uuid).encode().to_slice());
-
-                    let attribute_list =
-                        [AttributePayloadData::new(service.start_handle + 1, data)];
-                    self.write_att(att_encode_read_by_type_response(&attribute_list));
+    fn connection(&self, handle: u16) -> Option<&Connection> {
+        self.connections
+            .iter()
+            .flatten()
+            .find(|connection| connection.handle == handle)
+    }
 
-                    return;
-                }
+    /// Handle of the connection kept in the given slot.
+    fn connection_handle_at(&self, index: usize) -> Option<u16> {
+        self.connections[index]
+            .as_ref()
+            .map(|connection| connection.handle)
+    }
+
+    /// Returns true if the connection is known or there is a free slot to keep its state.
+    fn has_connection_slot(&self, handle: u16) -> bool {
+        self.connections.iter().any(|connection| match connection {
+            Some(connection) => connection.handle == handle,
+            None => true,
+        })
+    }
+
+    /// Starts keeping the state of the given connection if it isn't known yet.
+    ///
+    /// Callers check `has_connection_slot` first, see `connection_mut`.
+    fn ensure_connection(&mut self, handle: u16) {
+        self.connection_mut(handle);
+    }
+
+    /// Returns the state of the given connection, creating it if the connection wasn't known yet.
+    ///
+    /// Callers check `has_connection_slot` for connections which might not be known, if all
+    /// slots are in use the first one is reused.
+    fn connection_mut(&mut self, handle: u16) -> &mut Connection {
+        let index = self
+            .connections
+            .iter()
+            .position(
+                |connection| matches!(connection, Some(connection) if connection.handle == handle),
+            )
+            .or_else(|| {
+                self.connections
+                    .iter()
+                    .position(|connection| connection.is_none())
+            })
+            .unwrap_or(0);
+
+        let connection = &mut self.connections[index];
+        if !matches!(connection, Some(connection) if connection.handle == handle) {
+            *connection = Some(Connection::new(handle));
+        }
+        connection.as_mut().unwrap()
+    }
+
+    /// Answers requests which couldn't be parsed, the client would wait for a response
+    /// otherwise. Everything else is reported to the caller.
+    fn handle_att_parse_error(
+        &mut self,
+        connection_handle: u16,
+        opcode: Option<u8>,
+        err: AttParseError,
+    ) -> Result<(), AttributeServerError> {
+        match opcode {
+            Some(opcode) if is_request(opcode) => {
+                let code = match err {
+                    AttParseError::UnknownOpcode(_) => AttErrorCode::RequestNotSupported,
+                    _ => AttErrorCode::InvalidPdu,
+                };
+                self.write_att(
+                    connection_handle,
+                    att_encode_error_response(opcode, 0, code),
+                );
+                Ok(())
             }
+            _ => Err(err.into()),
         }
+    }
 
-        // respond with error
-        self.write_att(att_encode_error_response(
-            ATT_READ_BY_TYPE_REQUEST_OPCODE,
-            start,
-            AttErrorCode::AttributeNotFound,
-        ));
-    }
-
-    fn handle_read_req(&mut self, handle: u16) {
-        let mut answer = None;
-        for service in self.services.iter_mut() {
-            if service.characteristics_handle == handle {
-                answer = Some((*service.read_function)());
+    fn handle_exchange_mtu_req(&mut self, connection_handle: u16, client_mtu: u16) {
+        let server_mtu = self.mtu;
+        self.connection_mut(connection_handle).mtu = client_mtu.clamp(ATT_DEFAULT_MTU, server_mtu);
+        self.write_att(
+            connection_handle,
+            att_encode_exchange_mtu_response(server_mtu),
+        );
+    }
+
+    fn handle_find_information_req(&mut self, connection_handle: u16, start: u16, end: u16) {
+        if start == 0 || start > end {
+            self.write_att(
+                connection_handle,
+                att_encode_error_response(
+                    ATT_FIND_INFORMATION_REQUEST_OPCODE,
+                    start,
+                    AttErrorCode::InvalidHandle,
+                ),
+            );
+            return;
+        }
+
+        let mtu = self.connection_mtu(connection_handle);
+
+        let mut information_list =
+            [AttributeInformation::new(0, Uuid::Uuid16(0)); MAX_INFORMATION_ENTRIES];
+        let mut count = 0;
+        for attribute in self.attributes() {
+            if count == MAX_INFORMATION_ENTRIES {
                 break;
             }
+
+            if attribute.handle() >= start && attribute.handle() <= end {
+                information_list[count] =
+                    AttributeInformation::new(attribute.handle(), attribute.attribute_type());
+                count += 1;
+            }
+        }
+
+        if count > 0 {
+            self.write_att(
+                connection_handle,
+                att_encode_find_information_response(&information_list[..count], mtu),
+            );
+            return;
         }
 
-        if let Some(answer) = answer {
-            self.write_att(att_encode_read_response(&answer));
+        self.write_att(
+            connection_handle,
+            att_encode_error_response(
+                ATT_FIND_INFORMATION_REQUEST_OPCODE,
+                start,
+                AttErrorCode::AttributeNotFound,
+            ),
+        );
+    }
+
+    fn handle_find_by_type_value_req(
+        &mut self,
+        connection_handle: u16,
+        start: u16,
+        end: u16,
+        att_type: Uuid,
+        value: Data,
+    ) {
+        if start == 0 || start > end {
+            self.write_att(
+                connection_handle,
+                att_encode_error_response(
+                    ATT_FIND_BY_TYPE_VALUE_REQUEST_OPCODE,
+                    start,
+                    AttErrorCode::InvalidHandle,
+                ),
+            );
             return;
         }
 
-        panic!("should create a reasonable error instead of panic");
+        let mtu = self.connection_mtu(connection_handle);
+
+        // only primary services can be found by their value
+        let uuid =
+            Uuid::from_le_bytes(value.to_slice()).filter(|_| att_type == PRIMARY_SERVICE_UUID16);
+        if let Some(uuid) = uuid {
+            let mut handles_list = [HandlesInformation::new(0, 0); MAX_INFORMATION_ENTRIES];
+            let mut count = 0;
+            for attribute in self.attributes() {
+                if count == MAX_INFORMATION_ENTRIES {
+                    break;
+                }
+
+                if let Some((end_handle, service_uuid)) = attribute.service() {
+                    if attribute.attribute_type() == PRIMARY_SERVICE_UUID16
+                        && attribute.handle() >= start
+                        && attribute.handle() <= end
+                        && service_uuid.to_uuid128() == uuid.to_uuid128()
+                    {
+                        handles_list[count] =
+                            HandlesInformation::new(attribute.handle(), end_handle);
+                        count += 1;
+                    }
+                }
+            }
+
+            if count > 0 {
+                self.write_att(
+                    connection_handle,
+                    att_encode_find_by_type_value_response(&handles_list[..count], mtu),
+                );
+                return;
+            }
+        }
+
+        self.write_att(
+            connection_handle,
+            att_encode_error_response(
+                ATT_FIND_BY_TYPE_VALUE_REQUEST_OPCODE,
+                start,
+                AttErrorCode::AttributeNotFound,
+            ),
+        );
     }
 
-    fn handle_write_req(&mut self, handle: u16, data: Data) {
-        let mut found = false;
-        for service in self.services.iter_mut() {
-            if service.characteristics_handle == handle {
-                (*service.write_function)(data);
-                found = true;
+    fn handle_read_by_group_type_req(
+        &mut self,
+        connection_handle: u16,
+        start: u16,
+        end: u16,
+        group_type: Uuid,
+    ) {
+        let code = if start == 0 || start > end {
+            Some(AttErrorCode::InvalidHandle)
+        } else if group_type != PRIMARY_SERVICE_UUID16 && group_type != SECONDARY_SERVICE_UUID16 {
+            Some(AttErrorCode::UnsupportedGroupType)
+        } else {
+            None
+        };
+
+        if let Some(code) = code {
+            self.write_att(
+                connection_handle,
+                att_encode_error_response(ATT_READ_BY_GROUP_TYPE_REQUEST_OPCODE, start, code),
+            );
+            return;
+        }
+
+        let mtu = self.connection_mtu(connection_handle);
+
+        let mut attribute_list = [AttributeData::new(0, 0, Uuid::Uuid16(0)); MAX_RESPONSE_ENTRIES];
+        let mut count = 0;
+        for attribute in self.attributes() {
+            if count == MAX_RESPONSE_ENTRIES {
                 break;
             }
+
+            if let Some((end_handle, uuid)) = attribute.service() {
+                if attribute.attribute_type() == group_type
+                    && attribute.handle() >= start
+                    && attribute.handle() <= end
+                {
+                    attribute_list[count] =
+                        AttributeData::new(attribute.handle(), end_handle, uuid);
+                    count += 1;
+                }
+            }
         }
 
-        if found {
-            self.write_att(att_encode_write_response());
+        if count > 0 {
+            self.write_att(
+                connection_handle,
+                att_encode_read_by_group_type_response(&attribute_list[..count], mtu),
+            );
             return;
         }
 
-        panic!("should create a reasonable error instead of panic");
+        // respond with error
+        self.write_att(
+            connection_handle,
+            att_encode_error_response(
+                ATT_READ_BY_GROUP_TYPE_REQUEST_OPCODE,
+                start,
+                AttErrorCode::AttributeNotFound,
+            ),
+        );
     }
 
-    fn write_att(&mut self, data: Data) {
-        let res = encode_l2cap(data);
-        let res = encode_acl_packet(
-            0x0000,
-            BoundaryFlag::FirstAutoFlushable,
-            HostBroadcastFlag::NoBroadcast,
-            res,
+    fn handle_read_by_type_req(
+        &mut self,
+        connection_handle: u16,
+        start: u16,
+        end: u16,
+        attribute_type: Uuid,
+    ) {
+        if start == 0 || start > end {
+            self.write_att(
+                connection_handle,
+                att_encode_error_response(
+                    ATT_READ_BY_TYPE_REQUEST_OPCODE,
+                    start,
+                    AttErrorCode::InvalidHandle,
+                ),
+            );
+            return;
+        }
+
+        let mtu = self.connection_mtu(connection_handle);
+
+        let mut handles = [0u16; MAX_RESPONSE_ENTRIES];
+        let mut count = 0;
+        for attribute in self.attributes() {
+            if count == MAX_RESPONSE_ENTRIES {
+                break;
+            }
+
+            if attribute.attribute_type().to_uuid128() == attribute_type.to_uuid128()
+                && attribute.handle() >= start
+                && attribute.handle() <= end
+            {
+                handles[count] = attribute.handle();
+                count += 1;
+            }
+        }
+
+        if count == 0 {
+            self.write_att(
+                connection_handle,
+                att_encode_error_response(
+                    ATT_READ_BY_TYPE_REQUEST_OPCODE,
+                    start,
+                    AttErrorCode::AttributeNotFound,
+                ),
+            );
+            return;
+        }
+
+        let context = AccessContext {
+            connection_handle,
+            opcode: ATT_READ_BY_TYPE_REQUEST_OPCODE,
+            offset: 0,
+        };
+        let mut attribute_list =
+            [AttributePayloadData::new(0, Data::default()); MAX_RESPONSE_ENTRIES];
+        let mut listed = 0;
+        for handle in handles[..count].iter() {
+            // all entries have the length of the first one - don't read a value that can't fit
+            if listed > 0 && 2 + (listed + 1) * attribute_list[0].len() > mtu as usize {
+                break;
+            }
+
+            match self
+                .check_readable(connection_handle, *handle)
+                .and_then(|_| self.read_value(context, *handle))
+            {
+                Ok(value) => {
+                    // a value is truncated to what fits next to its handle
+                    let len = core::cmp::min(value.len, mtu as usize - 4);
+                    if listed > 0 && 2 + len != attribute_list[0].len() {
+                        break;
+                    }
+                    attribute_list[listed] =
+                        AttributePayloadData::new(*handle, Data::new(&value.to_slice()[..len]));
+                    listed += 1;
+                }
+                // only an error for the first attribute is reported
+                Err(code) if listed == 0 => {
+                    self.write_att(
+                        connection_handle,
+                        att_encode_error_response(ATT_READ_BY_TYPE_REQUEST_OPCODE, *handle, code),
+                    );
+                    return;
+                }
+                Err(_) => break,
+            }
+        }
+
+        self.write_att(
+            connection_handle,
+            att_encode_read_by_type_response(&attribute_list[..listed], mtu),
         );
-        self.ble.write_bytes(res.to_slice());
     }
-}
-
-pub const ATT_READABLE: u8 = 0x02;
-pub const ATT_WRITEABLE: u8 = 0x08;
 
-pub struct Service<'a> {
-    pub uuid: Uuid,
-    pub permissions: u8,
-    pub read_function: &'a mut dyn FnMut() -> Data,
-    pub write_function: &'a mut dyn FnMut(Data),
-    start_handle: u16,
-    end_handle: u16,
-    characteristics_handle: u16,
-}
+    fn handle_read_req(&mut self, connection_handle: u16, handle: u16) {
+        let context = AccessContext {
+            connection_handle,
+            opcode: ATT_READ_REQUEST_OPCODE,
+            offset: 0,
+        };
+        let answer = self
+            .check_readable(connection_handle, handle)
+            .and_then(|_| self.read_value(context, handle));
 
-impl<'a> Service<'a> {
-    pub fn new(
-        uuid: Uuid,
-        permissions: u8,
-        read_function: &'a mut dyn FnMut() -> Data,
-        write_function: &'a mut dyn FnMut(Data),
-    ) -> Service<'a> {
-        Service {
-            uuid,
-            permissions,
-            read_function,
-            write_function,
-            start_handle: 0,
-            end_handle: 0,
-            characteristics_handle: 0,
+        match answer {
+            Ok(answer) => {
+                let mtu = self.connection_mtu(connection_handle);
+                self.write_att(connection_handle, att_encode_read_response(&answer, mtu));
+            }
+            Err(code) => self.write_att(
+                connection_handle,
+                att_encode_error_response(ATT_READ_REQUEST_OPCODE, handle, code),
+            ),
         }
     }
-}
+
+    fn handle_read_blob_req(&mut self, connection_handle: u16, handle: u16, offset: u16) {
+        let mtu = self.connection_mtu(connection_handle);
+
+        let context = AccessContext {
+            connection_handle,
+            opcode: ATT_READ_BLOB_REQUEST_OPCODE,
+            offset,
+        };
+        // values fitting into a Read Response can't be read in parts, this is only known for
+        // callbacks when reading from the start
+        let part = self
+            .check_readable(connection_handle, handle)
+            .and_then(
+                |_| match self.read_internal_value(connection_handle, handle) {
+                    Some(value) if value.len < mtu as usize => Err(AttErrorCode::AttributeNotLong),
+                    Some(value) if offset as usize > value.len => Err(AttErrorCode::InvalidOffset),
+                    Some(value) => Ok(value.subdata_from(offset as usize)),
+                    None => self.read_callback(context, handle),
+                },
+            )
+            .and_then(|part| {
+                if offset == 0 && part.len < mtu as usize {
+                    Err(AttErrorCode::AttributeNotLong)
+                } else {
+                    Ok(part)
+                }
+            });
+
+        match part {
+            Err(code) => self.write_att(
+                connection_handle,
+                att_encode_error_response(ATT_READ_BLOB_REQUEST_OPCODE, handle, code),
+            ),
+            Ok(part) => {
+                self.write_att(connection_handle, att_encode_read_blob_response(&part, mtu))
+            }
+        }
+    }
+
+    /// Answers Read Multiple and Read Multiple Variable requests, the values are only read if
+    /// all handles are readable.
+    fn handle_read_multiple_req(&mut self, connection_handle: u16, opcode: u8, handles: Handles) {
+        let mtu = self.connection_mtu(connection_handle);
+
+        for handle in handles.to_slice() {
+            if let Err(code) = self.check_readable(connection_handle, *handle) {
+                self.write_att(
+                    connection_handle,
+                    att_encode_error_response(opcode, *handle, code),
+                );
+                return;
+            }
+        }
+
+        let context = AccessContext {
+            connection_handle,
+            opcode,
+            offset: 0,
+        };
+        let mut error = None;
+        let values =
+            handles
+                .to_slice()
+                .iter()
+                .map_while(|handle| match self.read_value(context, *handle) {
+                    Ok(value) => Some(value),
+                    Err(code) => {
+                        error = Some((*handle, code));
+                        None
+                    }
+                });
+        let response = if opcode == ATT_READ_MULTIPLE_VARIABLE_REQUEST_OPCODE {
+            att_encode_read_multiple_variable_response(values, mtu)
+        } else {
+            att_encode_read_multiple_response(values, mtu)
+        };
+
+        match error {
+            Some((handle, code)) => self.write_att(
+                connection_handle,
+                att_encode_error_response(opcode, handle, code),
+            ),
+            None => self.write_att(connection_handle, response),
+        }
+    }
+
+    fn handle_write_req(&mut self, connection_handle: u16, handle: u16, data: Data) {
+        if let Err(code) = self.check_writeable(connection_handle, handle) {
+            self.write_att(
+                connection_handle,
+                att_encode_error_response(ATT_WRITE_REQUEST_OPCODE, handle, code),
+            );
+            return;
+        }
+
+        if self.is_cccd_handle(handle) {
+            self.handle_cccd_write(connection_handle, handle, data);
+            return;
+        }
+
+        let context = AccessContext {
+            connection_handle,
+            opcode: ATT_WRITE_REQUEST_OPCODE,
+            offset: 0,
+        };
+        match self.write_value(context, handle, data) {
+            Ok(()) => self.write_att(connection_handle, att_encode_write_response()),
+            Err(code) => self.write_att(
+                connection_handle,
+                att_encode_error_response(ATT_WRITE_REQUEST_OPCODE, handle, code),
+            ),
+        }
+    }
+
+    /// Commands are never answered, invalid ones are dropped.
+    fn handle_write_cmd(&mut self, connection_handle: u16, handle: u16, data: Data) {
+        let security = self.connection_security(connection_handle);
+        let context = AccessContext {
+            connection_handle,
+            opcode: ATT_WRITE_COMMAND_OPCODE,
+            offset: 0,
+        };
+        if let Some(characteristic) = self.characteristic_mut(handle) {
+            if characteristic.properties & ATT_WRITE_WITHOUT_RESPONSE != 0
+                && characteristic.check_access(true, &security).is_ok()
+            {
+                (*characteristic.write_function)(context, data).ok();
+            }
+        }
+    }
+
+    /// Writes are only accepted with a valid signature and a sign counter larger than the
+    /// previous one.
+    fn handle_signed_write_cmd(
+        &mut self,
+        connection_handle: u16,
+        handle: u16,
+        data: Data,
+        sign_counter: u32,
+        signature: [u8; 8],
+    ) {
+        let connection = self.connection_mut(connection_handle);
+        // the signature takes the place of an encrypted connection, the other requirements of
+        // the permissions still apply
+        let security = ConnectionSecurity {
+            encrypted: true,
+            ..connection.security
+        };
+        let signing_key = match connection.signing_key {
+            Some(signing_key) => signing_key,
+            None => return,
+        };
+        if matches!(connection.sign_counter, Some(previous) if sign_counter <= previous)
+            || !verify_signature(&signing_key, handle, &data, sign_counter, &signature)
+        {
+            return;
+        }
+
+        let context = AccessContext {
+            connection_handle,
+            opcode: ATT_SIGNED_WRITE_COMMAND_OPCODE,
+            offset: 0,
+        };
+        let mut accepted = false;
+        if let Some(characteristic) = self.characteristic_mut(handle) {
+            if characteristic.properties & ATT_SIGNED_WRITE != 0
+                && characteristic.check_access(true, &security).is_ok()
+            {
+                accepted = (*characteristic.write_function)(context, data).is_ok();
+            }
+        }
+
+        // a rejected value doesn't use up the sign counter
+        if accepted {
+            self.connection_mut(connection_handle).sign_counter = Some(sign_counter);
+        }
+    }
+
+    fn handle_prepare_write_req(
+        &mut self,
+        connection_handle: u16,
+        handle: u16,
+        offset: u16,
+        value: Data,
+    ) {
+        let code = if let Err(code) = self.check_writeable(connection_handle, handle) {
+            Some(code)
+        } else if !self.attributes().any(|attribute| {
+            attribute.handle() == handle
+                && matches!(
+                    attribute,
+                    Attribute::CharacteristicValue(_) | Attribute::Descriptor(_)
+                )
+        }) {
+            // only values of the application's characteristics and descriptors can be written
+            // in parts
+            Some(AttErrorCode::WriteNotPermitted)
+        } else {
+            let connection = self.connection_mut(connection_handle);
+            if connection.prepared_writes_count == MAX_PREPARED_WRITES {
+                Some(AttErrorCode::PrepareQueueFull)
+            } else {
+                connection.prepared_writes[connection.prepared_writes_count] = PreparedWrite {
+                    handle,
+                    offset,
+                    value,
+                };
+                connection.prepared_writes_count += 1;
+                None
+            }
+        };
+
+        match code {
+            Some(code) => self.write_att(
+                connection_handle,
+                att_encode_error_response(ATT_PREPARE_WRITE_REQUEST_OPCODE, handle, code),
+            ),
+            None => self.write_att(
+                connection_handle,
+                att_encode_prepare_write_response(handle, offset, &value),
+            ),
+        }
+    }
+
+    /// Writes the reassembled values of all queued Prepare Write requests. Offsets, lengths and
+    /// permissions of all of them are checked before the first value is written. A failing write
+    /// callback stops the execution, values written before it are kept.
+    fn handle_execute_write_req(&mut self, connection_handle: u16, flags: u8) {
+        let connection = self.connection_mut(connection_handle);
+        let prepared_writes = connection.prepared_writes;
+        let prepared_writes = &prepared_writes[..connection.prepared_writes_count];
+        connection.prepared_writes_count = 0;
+
+        if flags != 0x01 {
+            self.write_att(connection_handle, att_encode_execute_write_response());
+            return;
+        }
+
+        // reassemble the values per handle, in the order of their first part
+        let mut values = [(0u16, Data::default()); MAX_PREPARED_WRITES];
+        let mut count = 0;
+        for prepared_write in prepared_writes {
+            let index = match values[..count]
+                .iter()
+                .position(|(handle, _)| *handle == prepared_write.handle)
+            {
+                Some(index) => index,
+                None => {
+                    values[count].0 = prepared_write.handle;
+                    count += 1;
+                    count - 1
+                }
+            };
+
+            let value = &mut values[index].1;
+            let offset = prepared_write.offset as usize;
+            let end = offset + prepared_write.value.len;
+            let code = if offset > value.len {
+                Some(AttErrorCode::InvalidOffset)
+            } else if end > value.data.len() {
+                Some(AttErrorCode::InvalidAttributeValueLength)
+            } else {
+                None
+            };
+
+            if let Some(code) = code {
+                self.write_att(
+                    connection_handle,
+                    att_encode_error_response(
+                        ATT_EXECUTE_WRITE_REQUEST_OPCODE,
+                        prepared_write.handle,
+                        code,
+                    ),
+                );
+                return;
+            }
+
+            value.data[offset..end].copy_from_slice(prepared_write.value.to_slice());
+            value.len = core::cmp::max(value.len, end);
+        }
+
+        // the security of the connection might have changed since the values were queued
+        for (handle, _) in values[..count].iter() {
+            if let Err(code) = self.check_writeable(connection_handle, *handle) {
+                self.write_att(
+                    connection_handle,
+                    att_encode_error_response(ATT_EXECUTE_WRITE_REQUEST_OPCODE, *handle, code),
+                );
+                return;
+            }
+        }
+
+        let context = AccessContext {
+            connection_handle,
+            opcode: ATT_EXECUTE_WRITE_REQUEST_OPCODE,
+            offset: 0,
+        };
+        for (handle, value) in values[..count].iter() {
+            if let Err(code) = self.write_value(context, *handle, *value) {
+                self.write_att(
+                    connection_handle,
+                    att_encode_error_response(ATT_EXECUTE_WRITE_REQUEST_OPCODE, *handle, code),
+                );
+                return;
+            }
+        }
+
+        self.write_att(connection_handle, att_encode_execute_write_response());
+    }
+
+    fn handle_cccd_write(&mut self, connection_handle: u16, handle: u16, data: Data) {
+        if data.len != 2 {
+            self.write_att(
+                connection_handle,
+                att_encode_error_response(
+                    ATT_WRITE_REQUEST_OPCODE,
+                    handle,
+                    AttErrorCode::InvalidAttributeValueLength,
+                ),
+            );
+            return;
+        }
+
+        // only keep the bits the characteristic supports
+        let properties = self
+            .attributes()
+            .find(|attribute| attribute.handle() == handle)
+            .map_or(0, |attribute| match attribute {
+                Attribute::ClientCharacteristicConfiguration(characteristic) => {
+                    characteristic.properties
+                }
+                Attribute::Generic(GenericAttribute::ServiceChangedConfiguration) => {
+                    GenericAttribute::ServiceChanged.properties()
+                }
+                _ => 0,
+            });
+        let mut allowed = 0;
+        if properties & ATT_NOTIFY != 0 {
+            allowed |= CCCD_NOTIFY;
+        }
+        if properties & ATT_INDICATE != 0 {
+            allowed |= CCCD_INDICATE;
+        }
+        let value = u16::from_le_bytes([data.data[0], data.data[1]]) & allowed;
+
+        if self
+            .connection_mut(connection_handle)
+            .set_cccd_value(handle, value)
+        {
+            self.write_att(connection_handle, att_encode_write_response());
+        } else {
+            self.write_att(
+                connection_handle,
+                att_encode_error_response(
+                    ATT_WRITE_REQUEST_OPCODE,
+                    handle,
+                    AttErrorCode::InsufficientResources,
+                ),
+            );
+        }
+    }
+
+    /// Checks if the attribute exists and can be read on the given connection.
+    fn check_readable(&self, connection_handle: u16, handle: u16) -> Result<(), AttErrorCode> {
+        let security = self.connection_security(connection_handle);
+        match self
+            .attributes()
+            .find(|attribute| attribute.handle() == handle)
+        {
+            None => Err(AttErrorCode::InvalidHandle),
+            Some(Attribute::CharacteristicValue(characteristic)) => {
+                characteristic.check_access(false, &security)
+            }
+            Some(Attribute::Descriptor(descriptor)) => descriptor.check_access(false, &security),
+            Some(Attribute::Generic(GenericAttribute::ServiceChanged)) => {
+                Err(AttErrorCode::ReadNotPermitted)
+            }
+            Some(_) => Ok(()),
+        }
+    }
+
+    /// Checks if the attribute exists and can be written on the given connection.
+    fn check_writeable(&self, connection_handle: u16, handle: u16) -> Result<(), AttErrorCode> {
+        let security = self.connection_security(connection_handle);
+        match self
+            .attributes()
+            .find(|attribute| attribute.handle() == handle)
+        {
+            None => Err(AttErrorCode::InvalidHandle),
+            Some(Attribute::CharacteristicValue(characteristic)) => {
+                characteristic.check_access(true, &security)
+            }
+            Some(Attribute::Descriptor(descriptor)) => descriptor.check_access(true, &security),
+            Some(Attribute::ClientCharacteristicConfiguration(_))
+            | Some(Attribute::Generic(GenericAttribute::ServiceChangedConfiguration))
+            | Some(Attribute::Generic(GenericAttribute::ClientSupportedFeatures)) => Ok(()),
+            Some(_) => Err(AttErrorCode::WriteNotPermitted),
+        }
+    }
+
+    fn connection_security(&self, connection_handle: u16) -> ConnectionSecurity {
+        self.security(connection_handle).unwrap_or_default()
+    }
+
+    /// Reads the value of an attribute.
+    fn read_value(&mut self, context: AccessContext, handle: u16) -> Result<Data, AttErrorCode> {
+        match self.read_internal_value(context.connection_handle, handle) {
+            Some(value) => Ok(value),
+            None => self.read_callback(context, handle),
+        }
+    }
+
+    /// Returns the complete value of attributes not served by callbacks.
+    fn read_internal_value(&mut self, connection_handle: u16, handle: u16) -> Option<Data> {
+        let declaration_value = self
+            .attributes()
+            .find(|attribute| attribute.handle() == handle)
+            .and_then(|attribute| attribute.declaration_value());
+        if declaration_value.is_some() {
+            return declaration_value;
+        }
+
+        if let Some(generic_access) = self.generic_access {
+            if handle == GenericAttribute::DeviceName.handle() {
+                let name = generic_access.device_name.as_bytes();
+                return Some(Data::new(&name[..core::cmp::min(name.len(), 128)]));
+            } else if handle == GenericAttribute::Appearance.handle() {
+                return Some(Data::new(&generic_access.appearance.to_le_bytes()));
+            } else if handle == GenericAttribute::PreferredConnectionParameters.handle() {
+                return Some(generic_access.preferred_connection_parameters.encode());
+            } else if handle == GenericAttribute::DatabaseHash.handle() {
+                return Some(Data::new(&self.database_hash));
+            } else if handle == GenericAttribute::ClientSupportedFeatures.handle() {
+                let features = self
+                    .connection_mut(connection_handle)
+                    .client_supported_features;
+                return Some(Data::new(&[features]));
+            }
+        }
+
+        if self.is_cccd_handle(handle) {
+            let value = self.connection_mut(connection_handle).cccd_value(handle);
+            return Some(Data::new(&value.to_le_bytes()));
+        }
+
+        match self.descriptor_mut(handle) {
+            Some(Descriptor {
+                value: DescriptorValue::Fixed(value),
+                ..
+            }) => Some(*value),
+            _ => None,
+        }
+    }
+
+    /// Calls the read callback of a characteristic or descriptor.
+    fn read_callback(&mut self, context: AccessContext, handle: u16) -> Result<Data, AttErrorCode> {
+        if let Some(descriptor) = self.descriptor_mut(handle) {
+            if let DescriptorValue::Functions { read_function, .. } = &mut descriptor.value {
+                return (*read_function)(context);
+            }
+        }
+
+        match self.characteristic_mut(handle) {
+            Some(characteristic) => (*characteristic.read_function)(context),
+            None => Err(AttErrorCode::InvalidHandle),
+        }
+    }
+
+    /// Writes the value of a characteristic.
+    fn write_value(
+        &mut self,
+        context: AccessContext,
+        handle: u16,
+        data: Data,
+    ) -> Result<(), AttErrorCode> {
+        if self.generic_access.is_some()
+            && handle == GenericAttribute::ClientSupportedFeatures.handle()
+        {
+            return self.write_client_supported_features(context.connection_handle, data);
+        }
+
+        if let Some(descriptor) = self.descriptor_mut(handle) {
+            return match &mut descriptor.value {
+                DescriptorValue::Fixed(_) => Err(AttErrorCode::WriteNotPermitted),
+                DescriptorValue::Functions { write_function, .. } => {
+                    (*write_function)(context, data)
+                }
+            };
+        }
+
+        match self.characteristic_mut(handle) {
+            Some(characteristic) => (*characteristic.write_function)(context, data),
+            None => Err(AttErrorCode::InvalidHandle),
+        }
+    }
+
+    /// Calculates the hash of the attribute table as described in Core Vol 3 Part G 7.3.
+    fn calculate_database_hash(&self) -> [u8; 16] {
+        let mut cmac = AesCmac::new(&[0u8; 16]);
+        for attribute in self.attributes() {
+            let attribute_type = match attribute.attribute_type() {
+                Uuid::Uuid16(attribute_type) => attribute_type,
+                Uuid::Uuid128(_) => continue,
+            };
+
+            match attribute_type {
+                // services, includes, characteristics and extended properties with their value
+                0x2800..=0x2803 | 0x2900 => {
+                    cmac.update(&attribute.handle().to_le_bytes());
+                    cmac.update(&attribute_type.to_le_bytes());
+                    let value = match attribute {
+                        Attribute::Descriptor(descriptor) => descriptor.fixed_value(),
+                        _ => attribute.declaration_value(),
+                    };
+                    if let Some(value) = value {
+                        cmac.update(value.to_slice());
+                    }
+                }
+                // descriptors whose value isn't part of the hash
+                0x2901..=0x2905 => {
+                    cmac.update(&attribute.handle().to_le_bytes());
+                    cmac.update(&attribute_type.to_le_bytes());
+                }
+                _ => (),
+            }
+        }
+
+        let mut hash = cmac.finalize();
+        hash.reverse();
+        hash
+    }
+
+    /// Enables the client features in the bits set, features can't be disabled again.
+    fn write_client_supported_features(
+        &mut self,
+        connection_handle: u16,
+        data: Data,
+    ) -> Result<(), AttErrorCode> {
+        let connection = self.connection_mut(connection_handle);
+        let features = data.to_slice().first().copied().unwrap_or(0) & SUPPORTED_CLIENT_FEATURES;
+        if features & connection.client_supported_features != connection.client_supported_features {
+            return Err(AttErrorCode::ValueNotAllowed);
+        }
+        connection.client_supported_features = features;
+        Ok(())
+    }
+
+    fn connection_mtu(&mut self, connection_handle: u16) -> u16 {
+        self.connection_mut(connection_handle).mtu
+    }
+
+    fn is_cccd_handle(&self, handle: u16) -> bool {
+        self.attributes().any(|attribute| {
+            attribute.handle() == handle
+                && attribute.attribute_type() == CLIENT_CHARACTERISTIC_CONFIGURATION_UUID16
+        })
+    }
+
+    /// Returns the CCCD handle of the characteristic with the given value handle if it has
+    /// the given property.
+    fn cccd_handle(&self, value_handle: u16, property: u8) -> Result<u16, AttributeServerError> {
+        if self.generic_access.is_some()
+            && value_handle == GenericAttribute::ServiceChanged.handle()
+            && GenericAttribute::ServiceChanged.properties() & property != 0
+        {
+            return Ok(GenericAttribute::ServiceChangedConfiguration.handle());
+        }
+
+        self.characteristics()
+            .find(|characteristic| {
+                characteristic.value_handle == value_handle
+                    && characteristic.properties & property != 0
+            })
+            .map(|characteristic| characteristic.cccd_handle)
+            .ok_or(AttributeServerError::InvalidHandle(value_handle))
+    }
+
+    /// All attributes in handle order.
+    fn attributes(&self) -> impl Iterator<Item = Attribute<'_, 'a>> {
+        let generic_attributes = match self.generic_access {
+            Some(_) => &GENERIC_ATTRIBUTES[..],
+            None => &[],
+        };
+        let generic_attributes = generic_attributes.iter().copied().map(Attribute::Generic);
+
+        let services = &*self.services;
+        generic_attributes.chain(services.iter().flat_map(move |service| {
+            let includes = service
+                .includes
+                .iter()
+                .filter_map(move |index| services.get(*index))
+                .zip(service.start_handle + 1..)
+                .map(|(included, handle)| Attribute::Include(handle, included));
+
+            once(Attribute::Service(service)).chain(includes).chain(
+                service.characteristics.iter().flat_map(|characteristic| {
+                    once(Attribute::CharacteristicDeclaration(characteristic))
+                        .chain(once(Attribute::CharacteristicValue(characteristic)))
+                        .chain((characteristic.cccd_handle != 0).then_some(
+                            Attribute::ClientCharacteristicConfiguration(characteristic),
+                        ))
+                        .chain(characteristic.descriptors.iter().map(Attribute::Descriptor))
+                }),
+            )
+        }))
+    }
+
+    fn characteristics(&self) -> impl Iterator<Item = &Characteristic<'a>> {
+        self.services
+            .iter()
+            .flat_map(|service| service.characteristics.iter())
+    }
+
+    /// Finds the characteristic with the given value handle.
+    fn characteristic_mut(&mut self, value_handle: u16) -> Option<&mut Characteristic<'a>> {
+        self.services
+            .iter_mut()
+            .flat_map(|service| service.characteristics.iter_mut())
+            .find(|characteristic| characteristic.value_handle == value_handle)
+    }
+
+    /// Finds the descriptor with the given handle.
+    fn descriptor_mut(&mut self, handle: u16) -> Option<&mut Descriptor<'a>> {
+        self.services
+            .iter_mut()
+            .flat_map(|service| service.characteristics.iter_mut())
+            .flat_map(|characteristic| characteristic.descriptors.iter_mut())
+            .find(|descriptor| descriptor.handle == handle)
+    }
+
+    fn write_att(&mut self, connection_handle: u16, data: Data) {
+        let res = encode_l2cap(data);
+        let res = encode_acl_packet(
+            connection_handle,
+            BoundaryFlag::FirstAutoFlushable,
+            HostBroadcastFlag::NoBroadcast,
+            res,
+        );
+        self.ble.write_bytes(res.to_slice());
+    }
+}
+
+/// Returns true for the opcodes of requests, which the client expects a response for.
+fn is_request(opcode: u8) -> bool {
+    // commands have bit 6 set, responses, notifications and indications are odd
+    opcode & 0x40 == 0 && opcode & 0x01 == 0 && opcode != ATT_HANDLE_VALUE_CONFIRMATION_OPCODE
+}
+
+/// Checks the signature of a Signed Write Command as described in Core Vol 3 Part H 2.4.5.
+fn verify_signature(
+    signing_key: &[u8; 16],
+    handle: u16,
+    data: &Data,
+    sign_counter: u32,
+    signature: &[u8; 8],
+) -> bool {
+    let mut message = Data::new(&[ATT_SIGNED_WRITE_COMMAND_OPCODE]);
+    message.append(&handle.to_le_bytes());
+    message.append(data.to_slice());
+    message.append(&sign_counter.to_le_bytes());
+    // CMAC works on the most significant octet first
+    message.data[..message.len].reverse();
+    let mut key = *signing_key;
+    key.reverse();
+
+    let mac = aes_cmac(&key, message.to_slice());
+
+    // the signature is the 64 most significant bits of the MAC
+    let mut expected = [0u8; 8];
+    expected.copy_from_slice(&mac[..8]);
+    expected.reverse();
+    expected == *signature
+}
+
+/// An entry of the attribute table.
+#[derive(Clone, Copy)]
+enum Attribute<'s, 'a> {
+    Service(&'s Service<'a>),
+    /// Handle of the include declaration and the included service.
+    Include(u16, &'s Service<'a>),
+    CharacteristicDeclaration(&'s Characteristic<'a>),
+    CharacteristicValue(&'s Characteristic<'a>),
+    ClientCharacteristicConfiguration(&'s Characteristic<'a>),
+    Descriptor(&'s Descriptor<'a>),
+    Generic(GenericAttribute),
+}
+
+impl<'s, 'a> Attribute<'s, 'a> {
+    fn handle(&self) -> u16 {
+        match self {
+            Attribute::Generic(attribute) => attribute.handle(),
+            Attribute::Descriptor(descriptor) => descriptor.handle,
+            Attribute::Include(handle, _) => *handle,
+            Attribute::Service(service) => service.start_handle,
+            Attribute::CharacteristicDeclaration(characteristic) => {
+                characteristic.declaration_handle
+            }
+            Attribute::CharacteristicValue(characteristic) => characteristic.value_handle,
+            Attribute::ClientCharacteristicConfiguration(characteristic) => {
+                characteristic.cccd_handle
+            }
+        }
+    }
+
+    fn attribute_type(&self) -> Uuid {
+        match self {
+            Attribute::Generic(attribute) => attribute.attribute_type(),
+            Attribute::Descriptor(descriptor) => descriptor.uuid,
+            Attribute::Include(_, _) => INCLUDE_UUID16,
+            Attribute::Service(service) if service.secondary => SECONDARY_SERVICE_UUID16,
+            Attribute::Service(_) => PRIMARY_SERVICE_UUID16,
+            Attribute::CharacteristicDeclaration(_) => CHARACTERISTIC_UUID16,
+            Attribute::CharacteristicValue(characteristic) => characteristic.uuid,
+            Attribute::ClientCharacteristicConfiguration(_) => {
+                CLIENT_CHARACTERISTIC_CONFIGURATION_UUID16
+            }
+        }
+    }
+
+    /// End handle and UUID if this is a service declaration.
+    fn service(&self) -> Option<(u16, Uuid)> {
+        match self {
+            Attribute::Service(service) => Some((service.end_handle, service.uuid)),
+            Attribute::Generic(GenericAttribute::GapService) => Some((
+                GenericAttribute::PreferredConnectionParameters.handle(),
+                GENERIC_ACCESS_SERVICE_UUID16,
+            )),
+            Attribute::Generic(GenericAttribute::GattService) => Some((
+                GenericAttribute::DatabaseHash.handle(),
+                GENERIC_ATTRIBUTE_SERVICE_UUID16,
+            )),
+            _ => None,
+        }
+    }
+
+    /// Value of a service, include or characteristic declaration.
+    fn declaration_value(&self) -> Option<Data> {
+        if let Some((_, uuid)) = self.service() {
+            return Some(uuid.encode());
+        }
+
+        match self {
+            Attribute::Include(_, service) => {
+                let mut data = Data::new(&service.start_handle.to_le_bytes());
+                data.append(&service.end_handle.to_le_bytes());
+                // 128-bit UUIDs are left out and need to be read from the service declaration
+                if let Uuid::Uuid16(_) = service.uuid {
+                    data.append(service.uuid.encode().to_slice());
+                }
+                Some(data)
+            }
+            Attribute::CharacteristicDeclaration(characteristic) => {
+                Some(characteristic.declaration_value())
+            }
+            Attribute::Generic(attribute) => attribute.declaration_value(),
+            _ => None,
+        }
+    }
+}
+
+const GENERIC_ACCESS_SERVICE_UUID16: Uuid = Uuid::Uuid16(0x1800);
+const GENERIC_ATTRIBUTE_SERVICE_UUID16: Uuid = Uuid::Uuid16(0x1801);
+const DEVICE_NAME_UUID16: Uuid = Uuid::Uuid16(0x2a00);
+const APPEARANCE_UUID16: Uuid = Uuid::Uuid16(0x2a01);
+const PREFERRED_CONNECTION_PARAMETERS_UUID16: Uuid = Uuid::Uuid16(0x2a04);
+const SERVICE_CHANGED_UUID16: Uuid = Uuid::Uuid16(0x2a05);
+const CLIENT_SUPPORTED_FEATURES_UUID16: Uuid = Uuid::Uuid16(0x2b29);
+const DATABASE_HASH_UUID16: Uuid = Uuid::Uuid16(0x2b2a);
+
+/// Attributes of the built-in Generic Access and Generic Attribute services, the discriminant
+/// is the handle.
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum GenericAttribute {
+    GapService = 1,
+    DeviceNameDeclaration,
+    DeviceName,
+    AppearanceDeclaration,
+    Appearance,
+    PreferredConnectionParametersDeclaration,
+    PreferredConnectionParameters,
+    GattService,
+    ServiceChangedDeclaration,
+    ServiceChanged,
+    ServiceChangedConfiguration,
+    ClientSupportedFeaturesDeclaration,
+    ClientSupportedFeatures,
+    DatabaseHashDeclaration,
+    DatabaseHash,
+}
+
+/// The built-in attributes in handle order.
+const GENERIC_ATTRIBUTES: [GenericAttribute; 15] = [
+    GenericAttribute::GapService,
+    GenericAttribute::DeviceNameDeclaration,
+    GenericAttribute::DeviceName,
+    GenericAttribute::AppearanceDeclaration,
+    GenericAttribute::Appearance,
+    GenericAttribute::PreferredConnectionParametersDeclaration,
+    GenericAttribute::PreferredConnectionParameters,
+    GenericAttribute::GattService,
+    GenericAttribute::ServiceChangedDeclaration,
+    GenericAttribute::ServiceChanged,
+    GenericAttribute::ServiceChangedConfiguration,
+    GenericAttribute::ClientSupportedFeaturesDeclaration,
+    GenericAttribute::ClientSupportedFeatures,
+    GenericAttribute::DatabaseHashDeclaration,
+    GenericAttribute::DatabaseHash,
+];
+
+impl GenericAttribute {
+    fn handle(self) -> u16 {
+        self as u16
+    }
+
+    fn attribute_type(self) -> Uuid {
+        match self {
+            GenericAttribute::GapService | GenericAttribute::GattService => PRIMARY_SERVICE_UUID16,
+            GenericAttribute::DeviceNameDeclaration
+            | GenericAttribute::AppearanceDeclaration
+            | GenericAttribute::PreferredConnectionParametersDeclaration
+            | GenericAttribute::ServiceChangedDeclaration
+            | GenericAttribute::ClientSupportedFeaturesDeclaration
+            | GenericAttribute::DatabaseHashDeclaration => CHARACTERISTIC_UUID16,
+            GenericAttribute::DeviceName => DEVICE_NAME_UUID16,
+            GenericAttribute::Appearance => APPEARANCE_UUID16,
+            GenericAttribute::PreferredConnectionParameters => {
+                PREFERRED_CONNECTION_PARAMETERS_UUID16
+            }
+            GenericAttribute::ServiceChanged => SERVICE_CHANGED_UUID16,
+            GenericAttribute::ServiceChangedConfiguration => {
+                CLIENT_CHARACTERISTIC_CONFIGURATION_UUID16
+            }
+            GenericAttribute::ClientSupportedFeatures => CLIENT_SUPPORTED_FEATURES_UUID16,
+            GenericAttribute::DatabaseHash => DATABASE_HASH_UUID16,
+        }
+    }
+
+    /// Properties of a characteristic value.
+    fn properties(self) -> u8 {
+        match self {
+            GenericAttribute::DeviceName
+            | GenericAttribute::Appearance
+            | GenericAttribute::PreferredConnectionParameters
+            | GenericAttribute::DatabaseHash => ATT_READABLE,
+            GenericAttribute::ServiceChanged => ATT_INDICATE,
+            GenericAttribute::ClientSupportedFeatures => ATT_READABLE | ATT_WRITEABLE,
+            _ => 0,
+        }
+    }
+
+    /// Value of a characteristic declaration, the value follows its declaration.
+    fn declaration_value(self) -> Option<Data> {
+        let value = GENERIC_ATTRIBUTES
+            .iter()
+            .copied()
+            .find(|attribute| attribute.handle() == self.handle() + 1)
+            .filter(|_| self.attribute_type() == CHARACTERISTIC_UUID16)?;
+
+        let mut data = Data::new(&[value.properties()]);
+        data.append(&value.handle().to_le_bytes());
+        data.append(value.attribute_type().encode().to_slice());
+        Some(data)
+    }
+}
+
+pub const ATT_READABLE: u8 = 0x02;
+pub const ATT_WRITE_WITHOUT_RESPONSE: u8 = 0x04;
+pub const ATT_WRITEABLE: u8 = 0x08;
+pub const ATT_NOTIFY: u8 = 0x10;
+pub const ATT_INDICATE: u8 = 0x20;
+/// Writes signed with the peer's CSRK, see `AttributeServer::set_signing_key`.
+pub const ATT_SIGNED_WRITE: u8 = 0x40;
+
+/// Access permissions of a characteristic value. They are checked independently of the
+/// properties announced to the client.
+pub const PERMISSION_READ: u8 = 0x01;
+pub const PERMISSION_WRITE: u8 = 0x02;
+/// Reading requires an encrypted connection.
+pub const PERMISSION_READ_ENCRYPTED: u8 = 0x04;
+/// Writing requires an encrypted connection.
+pub const PERMISSION_WRITE_ENCRYPTED: u8 = 0x08;
+/// Reading requires a connection encrypted with an authenticated key.
+pub const PERMISSION_READ_AUTHENTICATED: u8 = 0x10;
+/// Writing requires a connection encrypted with an authenticated key.
+pub const PERMISSION_WRITE_AUTHENTICATED: u8 = 0x20;
+/// Reading requires the application to authorize the peer.
+pub const PERMISSION_READ_AUTHORIZED: u8 = 0x40;
+/// Writing requires the application to authorize the peer.
+pub const PERMISSION_WRITE_AUTHORIZED: u8 = 0x80;
+
+pub struct Service<'a> {
+    pub uuid: Uuid,
+    pub characteristics: &'a mut [Characteristic<'a>],
+    /// Secondary services are only meant to be included by other services.
+    /// `Service::new` creates a primary service.
+    pub secondary: bool,
+    /// Indices of the services included by this one, into the slice of services passed to
+    /// `AttributeServer::new`. Indices outside of it are ignored.
+    pub includes: &'a [usize],
+    start_handle: u16,
+    end_handle: u16,
+}
+
+impl<'a> Service<'a> {
+    pub fn new(uuid: Uuid, characteristics: &'a mut [Characteristic<'a>]) -> Service<'a> {
+        Service {
+            uuid,
+            characteristics,
+            secondary: false,
+            includes: &[],
+            start_handle: 0,
+            end_handle: 0,
+        }
+    }
+}
+
+pub struct Characteristic<'a> {
+    pub uuid: Uuid,
+    /// Characteristic properties like `ATT_READABLE` and `ATT_WRITEABLE`.
+    ///
+    /// Characteristics with `ATT_NOTIFY` or `ATT_INDICATE` get a Client Characteristic
+    /// Configuration descriptor.
+    pub properties: u8,
+    /// Access permissions like `PERMISSION_READ` and `PERMISSION_WRITE_ENCRYPTED`.
+    ///
+    /// `Characteristic::new` allows reading and writing as announced by the properties.
+    pub permissions: u8,
+    /// Minimum encryption key size in bytes for accesses requiring encryption.
+    pub min_key_size: u8,
+    /// Returns the value starting at `AccessContext::offset`, which is only non-zero for Read Blob
+    /// requests. Errors like `AttErrorCode::InvalidOffset` are sent to the client as ATT Error
+    /// Response.
+    pub read_function: &'a mut dyn FnMut(AccessContext) -> Result<Data, AttErrorCode>,
+    /// Receives the complete value. Errors are sent to the client as ATT Error Response.
+    pub write_function: &'a mut dyn FnMut(AccessContext, Data) -> Result<(), AttErrorCode>,
+    /// Descriptors following the value and the Client Characteristic Configuration descriptor.
+    /// `Characteristic::new` starts without any.
+    pub descriptors: &'a mut [Descriptor<'a>],
+    declaration_handle: u16,
+    value_handle: u16,
+    cccd_handle: u16,
+}
+
+impl<'a> Characteristic<'a> {
+    pub fn new(
+        uuid: Uuid,
+        properties: u8,
+        read_function: &'a mut dyn FnMut(AccessContext) -> Result<Data, AttErrorCode>,
+        write_function: &'a mut dyn FnMut(AccessContext, Data) -> Result<(), AttErrorCode>,
+    ) -> Characteristic<'a> {
+        let mut permissions = 0;
+        if properties & ATT_READABLE != 0 {
+            permissions |= PERMISSION_READ;
+        }
+        if properties & (ATT_WRITE_WITHOUT_RESPONSE | ATT_WRITEABLE | ATT_SIGNED_WRITE) != 0 {
+            permissions |= PERMISSION_WRITE;
+        }
+
+        Characteristic {
+            uuid,
+            properties,
+            permissions,
+            min_key_size: 7,
+            read_function,
+            write_function,
+            descriptors: &mut [],
+            declaration_handle: 0,
+            value_handle: 0,
+            cccd_handle: 0,
+        }
+    }
+
+    /// Handle of the characteristic value. Assigned by `AttributeServer::new`.
+    pub fn value_handle(&self) -> u16 {
+        self.value_handle
+    }
+
+    /// Handle of the Client Characteristic Configuration descriptor or 0 if there is none.
+    pub fn cccd_handle(&self) -> u16 {
+        self.cccd_handle
+    }
+
+    /// Checks the permissions for reading or writing the value on a connection with the given
+    /// security.
+    fn check_access(&self, write: bool, security: &ConnectionSecurity) -> Result<(), AttErrorCode> {
+        check_permissions(self.permissions, self.min_key_size, write, security)
+    }
+
+    fn declaration_value(&self) -> Data {
+        // announced whenever there's an Extended Properties descriptor
+        let extended_properties = self
+            .descriptors
+            .iter()
+            .any(|descriptor| descriptor.uuid == EXTENDED_PROPERTIES_UUID16);
+        let properties = if extended_properties {
+            self.properties | CHARACTERISTIC_EXTENDED_PROPERTIES
+        } else {
+            self.properties
+        };
+
+        let mut data = Data::new(&[
+            properties,
+            // 2 byte handle pointing to characteristic value
+            (self.value_handle & 0xff) as u8,
+            ((self.value_handle & 0xff00) >> 8) as u8,
+            // UUID of characteristic value
+        ]);
+        data.append(self.uuid.encode().to_slice());
+        data
+    }
+}
+
+/// Checks access permissions like `PERMISSION_READ_ENCRYPTED` against the security of a
+/// connection.
+fn check_permissions(
+    permissions: u8,
+    min_key_size: u8,
+    write: bool,
+    security: &ConnectionSecurity,
+) -> Result<(), AttErrorCode> {
+    let (allowed, encrypted, authenticated, authorized, not_permitted) = if write {
+        (
+            PERMISSION_WRITE,
+            PERMISSION_WRITE_ENCRYPTED,
+            PERMISSION_WRITE_AUTHENTICATED,
+            PERMISSION_WRITE_AUTHORIZED,
+            AttErrorCode::WriteNotPermitted,
+        )
+    } else {
+        (
+            PERMISSION_READ,
+            PERMISSION_READ_ENCRYPTED,
+            PERMISSION_READ_AUTHENTICATED,
+            PERMISSION_READ_AUTHORIZED,
+            AttErrorCode::ReadNotPermitted,
+        )
+    };
+    let needs_encryption = permissions & (encrypted | authenticated) != 0;
+
+    if permissions & allowed == 0 {
+        Err(not_permitted)
+    } else if permissions & authenticated != 0 && !(security.encrypted && security.authenticated) {
+        Err(AttErrorCode::InsufficientAuthentication)
+    } else if needs_encryption && !security.encrypted {
+        Err(AttErrorCode::InsufficientEncryption)
+    } else if needs_encryption && security.key_size < min_key_size {
+        Err(AttErrorCode::InsufficientEncryptionKeySize)
+    } else if permissions & authorized != 0 && !security.authorized {
+        Err(AttErrorCode::InsufficientAuthorization)
+    } else {
+        Ok(())
+    }
+}
+
+/// Value of the Characteristic Extended Properties descriptor: Reliable Write is supported.
+pub const EXTENDED_PROPERTY_RELIABLE_WRITE: u16 = 0x0001;
+/// Value of the Characteristic Extended Properties descriptor: the User Description is writable.
+pub const EXTENDED_PROPERTY_WRITABLE_AUXILIARIES: u16 = 0x0002;
+
+/// A descriptor of a characteristic, see `Characteristic::descriptors`.
+pub struct Descriptor<'a> {
+    pub uuid: Uuid,
+    /// Access permissions like `PERMISSION_READ` and `PERMISSION_WRITE_ENCRYPTED`.
+    pub permissions: u8,
+    /// Minimum encryption key size in bytes for accesses requiring encryption.
+    pub min_key_size: u8,
+    value: DescriptorValue<'a>,
+    handle: u16,
+}
+
+enum DescriptorValue<'a> {
+    Fixed(Data),
+    Functions {
+        read_function: &'a mut dyn FnMut(AccessContext) -> Result<Data, AttErrorCode>,
+        write_function: &'a mut dyn FnMut(AccessContext, Data) -> Result<(), AttErrorCode>,
+    },
+}
+
+impl<'a> Descriptor<'a> {
+    /// A descriptor whose value is provided by the callbacks, with the same semantics as the
+    /// callbacks of a `Characteristic`.
+    pub fn new(
+        uuid: Uuid,
+        permissions: u8,
+        read_function: &'a mut dyn FnMut(AccessContext) -> Result<Data, AttErrorCode>,
+        write_function: &'a mut dyn FnMut(AccessContext, Data) -> Result<(), AttErrorCode>,
+    ) -> Descriptor<'a> {
+        Descriptor {
+            uuid,
+            permissions,
+            min_key_size: 7,
+            value: DescriptorValue::Functions {
+                read_function,
+                write_function,
+            },
+            handle: 0,
+        }
+    }
+
+    /// A read-only descriptor with a constant value.
+    pub fn new_fixed(uuid: Uuid, value: Data) -> Descriptor<'a> {
+        Descriptor {
+            uuid,
+            permissions: PERMISSION_READ,
+            min_key_size: 7,
+            value: DescriptorValue::Fixed(value),
+            handle: 0,
+        }
+    }
+
+    /// Characteristic Extended Properties (0x2900) with bits like
+    /// `EXTENDED_PROPERTY_RELIABLE_WRITE`.
+    pub fn extended_properties(properties: u16) -> Descriptor<'a> {
+        Descriptor::new_fixed(
+            EXTENDED_PROPERTIES_UUID16,
+            Data::new(&properties.to_le_bytes()),
+        )
+    }
+
+    /// Characteristic User Description (0x2901). Only the first 128 bytes are served.
+    pub fn user_description(description: &str) -> Descriptor<'a> {
+        let description = description.as_bytes();
+        Descriptor::new_fixed(
+            USER_DESCRIPTION_UUID16,
+            Data::new(&description[..core::cmp::min(description.len(), 128)]),
+        )
+    }
+
+    /// Characteristic Presentation Format (0x2904). `format` and `unit` are values from the
+    /// Bluetooth SIG Assigned Numbers, e.g. 0x04 for uint8 and 0x27ad for percentage.
+    pub fn presentation_format(
+        format: u8,
+        exponent: i8,
+        unit: u16,
+        namespace: u8,
+        description: u16,
+    ) -> Descriptor<'a> {
+        let mut value = Data::new(&[format, exponent as u8]);
+        value.append(&unit.to_le_bytes());
+        value.append(&[namespace]);
+        value.append(&description.to_le_bytes());
+        Descriptor::new_fixed(PRESENTATION_FORMAT_UUID16, value)
+    }
+
+    /// Valid Range (0x2906) with the lower and upper inclusive bounds in the format of the
+    /// characteristic value.
+    pub fn valid_range(lower: &[u8], upper: &[u8]) -> Descriptor<'a> {
+        let mut value = Data::new(lower);
+        value.append(upper);
+        Descriptor::new_fixed(VALID_RANGE_UUID16, value)
+    }
+
+    /// Handle of the descriptor. Assigned by `AttributeServer::new`.
+    pub fn handle(&self) -> u16 {
+        self.handle
+    }
+
+    fn check_access(&self, write: bool, security: &ConnectionSecurity) -> Result<(), AttErrorCode> {
+        check_permissions(self.permissions, self.min_key_size, write, security)
+    }
+
+    fn fixed_value(&self) -> Option<Data> {
+        match self.value {
+            DescriptorValue::Fixed(value) => Some(value),
+            DescriptorValue::Functions { .. } => None,
+        }
+    }
+}
+
+/// Declares the services for `AttributeServer::new` together with constants for their handles.
+///
+/// The handles are laid out at compile time the same way `AttributeServer` assigns them at
+/// runtime, so the constants can be used with `notify`, `indicate` and the callbacks' context.
+/// The macro expands to statements: one constant per service (its declaration handle),
+/// characteristic (its value handle), CCCD and descriptor, and `let services` bound to the
+/// `&mut` slice of services.
+///
+/// ```
+/// # use ble_hci::{att::Uuid, attribute_server::*, gatt_server, Ble, Data, HciConnector};
+/// # struct Connector;
+/// # impl HciConnector for Connector {
+/// #     fn read(&self) -> Option<u8> { None }
+/// #     fn write(&self, _data: u8) {}
+/// #     fn millis(&self) -> u64 { 0 }
+/// # }
+/// # let connector = Connector;
+/// # let mut ble = Ble::new(&connector);
+/// let mut rf1 = |_| Ok(Data::new(&[100]));
+/// let mut wf1 = |_, _data: Data| Ok(());
+/// let mut rf2 = |_| Ok(Data::new(&[0x01]));
+/// let mut wf2 = |_, _data: Data| Ok(());
+/// let mut rf3 = |_| Ok(Data::new(&[0x00]));
+/// let mut wf3 = |_, _data: Data| Ok(());
+///
+/// gatt_server! {
+///     let services = [
+///         service BATTERY_SERVICE(Uuid::Uuid16(0x180f)) {
+///             characteristic BATTERY_LEVEL(Uuid::Uuid16(0x2a19), ATT_READABLE | ATT_NOTIFY, &mut rf1, &mut wf1) {
+///                 cccd BATTERY_LEVEL_CCCD;
+///                 user_description BATTERY_LEVEL_DESCRIPTION: "Battery level";
+///                 presentation_format BATTERY_LEVEL_FORMAT: (0x04, 0, 0x27ad, 0x01, 0x0000);
+///             }
+///         }
+///         secondary_service INCLUDED_SERVICE(Uuid::Uuid16(0x1805)) {
+///             characteristic TIME(Uuid::Uuid16(0x2a2b), ATT_READABLE, &mut rf2, &mut wf2) {
+///                 descriptor TIME_SETTING(Uuid::Uuid16(0x3000), PERMISSION_READ, &mut rf3, &mut wf3);
+///             }
+///         }
+///         service OTHER_SERVICE(Uuid::Uuid16(0x1812)) includes [1] {}
+///     ];
+/// }
+/// assert_eq!(BATTERY_LEVEL, 3);
+/// assert_eq!(BATTERY_LEVEL_CCCD, 4);
+/// assert_eq!(TIME_SETTING, 10);
+///
+/// let mut server = AttributeServer::new(&mut ble, services);
+/// server.notify(BATTERY_LEVEL, &Data::new(&[99])).unwrap();
+/// ```
+///
+/// Start with `first_handle: GENERIC_SERVICES_END_HANDLE + 1,` before `let` when using
+/// `AttributeServer::new_with_generic_services`.
+///
+/// Characteristics take the arguments of `Characteristic::new`, the properties need to be a
+/// constant expression. Their body lists the descriptors: `cccd` comes first and is required
+/// exactly for characteristics which notify or indicate, followed by `extended_properties`,
+/// `user_description`, `presentation_format`, `valid_range` (each at most once except
+/// `presentation_format`) and custom descriptors. These take the arguments of `Descriptor::new`
+/// as `descriptor NAME(uuid, permissions, read, write);` or a fixed value as
+/// `descriptor NAME(uuid): value;`, the UUID needs to be a constant expression and can't be
+/// the one of a descriptor with its own entry. `includes` lists constant indices of other
+/// services in the list. Violations fail to compile.
+#[macro_export]
+macro_rules! gatt_server {
+    (let $services:ident = [$($body:tt)*];) => {
+        $crate::gatt_server!(first_handle: 1, let $services = [$($body)*];);
+    };
+    (first_handle: $first:expr, let $services:ident = [$($body:tt)*];) => {
+        $crate::gatt_server!(@service $services [] [] (0usize) ($first) $($body)*);
+    };
+
+    // all services are built
+    (@service $services:ident [$($built:ident)*] [$(($owner:expr, $included:expr))*]
+        ($count:expr) ($next:expr)
+    ) => {
+        $(
+            const _: () = assert!(
+                $included < $count && $included != $owner,
+                "services can only include other services of the list"
+            );
+        )*
+        let $services = &mut [$($built),*];
+    };
+
+    // the service declaration is followed by the include declarations
+    (@service $services:ident [$($built:ident)*] [$($included:tt)*] ($index:expr) ($next:expr)
+        $(#[$meta:meta])* $kind:ident $name:ident($uuid:expr)
+        $(includes [$($include:expr),* $(,)?])? {
+            $($characteristics:tt)*
+        }
+        $($rest:tt)*
+    ) => {
+        $(#[$meta])*
+        const $name: u16 = $next;
+        $crate::gatt_server!(@characteristic
+            ($services [$($built)*] [$($included)* $($(($index, $include))*)?] ($index) [$($rest)*])
+            ($kind $uuid, [$($($include),*)?])
+            []
+            ($name + 1 $($(+ { let _ = $include; 1 })*)?)
+            $($characteristics)*
+        );
+    };
+
+    // all characteristics of a service are built
+    (@characteristic
+        ($services:ident [$($built:ident)*] [$($included:tt)*] ($index:expr) [$($rest:tt)*])
+        ($kind:ident $uuid:expr, [$($include:expr),*])
+        [$($characteristic:ident)*]
+        ($next:expr)
+    ) => {
+        let characteristics: &mut [$crate::attribute_server::Characteristic] =
+            &mut [$($characteristic),*];
+        let mut service = $crate::attribute_server::Service::new($uuid, characteristics);
+        service.secondary = $crate::gatt_server!(@secondary $kind);
+        service.includes = &[$($include),*];
+        $crate::gatt_server!(@service $services [$($built)* service] [$($included)*]
+            ($index + 1) ($next) $($rest)*);
+    };
+
+    // the characteristic declaration is followed by the value
+    (@characteristic $service_state:tt $service:tt [$($built:ident)*] ($next:expr)
+        $(#[$meta:meta])*
+        characteristic $name:ident($uuid:expr, $properties:expr, $read:expr, $write:expr $(,)?) {
+            $($descriptors:tt)*
+        }
+        $($rest:tt)*
+    ) => {
+        $(#[$meta])*
+        const $name: u16 = $next + 1;
+        $crate::gatt_server!(@descriptor $service_state $service [$($built)*] [$($rest)*]
+            ($name, $uuid, $properties, $read, $write)
+            (0, 0, 0, 0)
+            []
+            ($name + 1)
+            $($descriptors)*
+        );
+    };
+
+    // all descriptors of a characteristic are built
+    (@descriptor $service_state:tt $service:tt [$($built:ident)*] [$($rest:tt)*]
+        ($name:ident, $uuid:expr, $properties:expr, $read:expr, $write:expr)
+        ($cccds:expr, $extended_properties:expr, $user_descriptions:expr, $valid_ranges:expr)
+        [$($descriptor:expr),*]
+        ($next:expr)
+    ) => {
+        const _: () = assert!(
+            (($properties)
+                & ($crate::attribute_server::ATT_NOTIFY | $crate::attribute_server::ATT_INDICATE)
+                != 0)
+                == ($cccds == 1),
+            "characteristics need one cccd exactly if they notify or indicate"
+        );
+        const _: () = assert!(
+            $extended_properties < 2 && $user_descriptions < 2 && $valid_ranges < 2,
+            "characteristics can't have the same descriptor twice"
+        );
+        let descriptors: &mut [$crate::attribute_server::Descriptor] = &mut [$($descriptor),*];
+        let mut characteristic =
+            $crate::attribute_server::Characteristic::new($uuid, $properties, $read, $write);
+        characteristic.descriptors = descriptors;
+        $crate::gatt_server!(@characteristic $service_state $service [$($built)* characteristic]
+            ($next) $($rest)*);
+    };
+
+    // the CCCD comes first, it's created by the attribute server
+    (@descriptor $service_state:tt $service:tt $built:tt $rest:tt $characteristic:tt
+        ($cccds:expr, $extended_properties:expr, $user_descriptions:expr, $valid_ranges:expr)
+        []
+        ($next:expr)
+        $(#[$meta:meta])* cccd $name:ident;
+        $($descriptors:tt)*
+    ) => {
+        $(#[$meta])*
+        const $name: u16 = $next;
+        $crate::gatt_server!(@descriptor $service_state $service $built $rest $characteristic
+            ($cccds + 1, $extended_properties, $user_descriptions, $valid_ranges)
+            []
+            ($name + 1)
+            $($descriptors)*
+        );
+    };
+
+    (@descriptor $service_state:tt $service:tt $built:tt $rest:tt $characteristic:tt
+        ($cccds:expr, $extended_properties:expr, $user_descriptions:expr, $valid_ranges:expr)
+        [$($descriptor:expr),*]
+        ($next:expr)
+        $(#[$meta:meta])* extended_properties $name:ident: $value:expr;
+        $($descriptors:tt)*
+    ) => {
+        $(#[$meta])*
+        const $name: u16 = $next;
+        $crate::gatt_server!(@descriptor $service_state $service $built $rest $characteristic
+            ($cccds, $extended_properties + 1, $user_descriptions, $valid_ranges)
+            [$($descriptor,)* $crate::attribute_server::Descriptor::extended_properties($value)]
+            ($name + 1)
+            $($descriptors)*
+        );
+    };
+
+    (@descriptor $service_state:tt $service:tt $built:tt $rest:tt $characteristic:tt
+        ($cccds:expr, $extended_properties:expr, $user_descriptions:expr, $valid_ranges:expr)
+        [$($descriptor:expr),*]
+        ($next:expr)
+        $(#[$meta:meta])* user_description $name:ident: $value:expr;
+        $($descriptors:tt)*
+    ) => {
+        $(#[$meta])*
+        const $name: u16 = $next;
+        $crate::gatt_server!(@descriptor $service_state $service $built $rest $characteristic
+            ($cccds, $extended_properties, $user_descriptions + 1, $valid_ranges)
+            [$($descriptor,)* $crate::attribute_server::Descriptor::user_description($value)]
+            ($name + 1)
+            $($descriptors)*
+        );
+    };
+
+    (@descriptor $service_state:tt $service:tt $built:tt $rest:tt $characteristic:tt
+        $counts:tt
+        [$($descriptor:expr),*]
+        ($next:expr)
+        $(#[$meta:meta])* presentation_format $name:ident: ($($format:expr),* $(,)?);
+        $($descriptors:tt)*
+    ) => {
+        $(#[$meta])*
+        const $name: u16 = $next;
+        $crate::gatt_server!(@descriptor $service_state $service $built $rest $characteristic
+            $counts
+            [
+                $($descriptor,)*
+                $crate::attribute_server::Descriptor::presentation_format($($format),*)
+            ]
+            ($name + 1)
+            $($descriptors)*
+        );
+    };
+
+    (@descriptor $service_state:tt $service:tt $built:tt $rest:tt $characteristic:tt
+        ($cccds:expr, $extended_properties:expr, $user_descriptions:expr, $valid_ranges:expr)
+        [$($descriptor:expr),*]
+        ($next:expr)
+        $(#[$meta:meta])* valid_range $name:ident: ($lower:expr, $upper:expr $(,)?);
+        $($descriptors:tt)*
+    ) => {
+        $(#[$meta])*
+        const $name: u16 = $next;
+        $crate::gatt_server!(@descriptor $service_state $service $built $rest $characteristic
+            ($cccds, $extended_properties, $user_descriptions, $valid_ranges + 1)
+            [$($descriptor,)* $crate::attribute_server::Descriptor::valid_range($lower, $upper)]
+            ($name + 1)
+            $($descriptors)*
+        );
+    };
+
+    (@descriptor $service_state:tt $service:tt $built:tt $rest:tt $characteristic:tt
+        $counts:tt
+        [$($descriptor:expr),*]
+        ($next:expr)
+        $(#[$meta:meta])*
+        descriptor $name:ident($uuid:expr, $permissions:expr, $read:expr, $write:expr $(,)?);
+        $($descriptors:tt)*
+    ) => {
+        $crate::gatt_server!(@custom_descriptor $uuid);
+        $(#[$meta])*
+        const $name: u16 = $next;
+        $crate::gatt_server!(@descriptor $service_state $service $built $rest $characteristic
+            $counts
+            [
+                $($descriptor,)*
+                $crate::attribute_server::Descriptor::new($uuid, $permissions, $read, $write)
+            ]
+            ($name + 1)
+            $($descriptors)*
+        );
+    };
+
+    (@descriptor $service_state:tt $service:tt $built:tt $rest:tt $characteristic:tt
+        $counts:tt
+        [$($descriptor:expr),*]
+        ($next:expr)
+        $(#[$meta:meta])* descriptor $name:ident($uuid:expr): $value:expr;
+        $($descriptors:tt)*
+    ) => {
+        $crate::gatt_server!(@custom_descriptor $uuid);
+        $(#[$meta])*
+        const $name: u16 = $next;
+        $crate::gatt_server!(@descriptor $service_state $service $built $rest $characteristic
+            $counts
+            [$($descriptor,)* $crate::attribute_server::Descriptor::new_fixed($uuid, $value)]
+            ($name + 1)
+            $($descriptors)*
+        );
+    };
+
+    // descriptors with their own entries can't be declared as custom ones, which would bypass
+    // the checks above
+    (@custom_descriptor $uuid:expr) => {
+        const _: () = assert!(
+            !matches!(
+                $crate::att::Uuid::to_uuid16(&$uuid),
+                Some(0x2900) | Some(0x2901) | Some(0x2902) | Some(0x2906)
+            ),
+            "use the cccd, extended_properties, user_description and valid_range entries"
+        );
+    };
+
+    (@secondary service) => {
+        false
+    };
+    (@secondary secondary_service) => {
+        true
+    };
+}
+
+/// Declarations `gatt_server!` rejects at compile time.
+///
+/// A characteristic which notifies without a CCCD:
+///
+/// ```compile_fail,E0080
+/// # use ble_hci::{att::Uuid, attribute_server::*, gatt_server, Data};
+/// # let mut rf = |_| Ok(Data::default());
+/// # let mut wf = |_, _data: Data| Ok(());
+/// gatt_server! {
+///     let services = [
+///         service S(Uuid::Uuid16(0x180f)) {
+///             characteristic C(Uuid::Uuid16(0x2a19), ATT_READABLE | ATT_NOTIFY, &mut rf, &mut wf) {}
+///         }
+///     ];
+/// }
+/// ```
+///
+/// A characteristic which indicates without a CCCD:
+///
+/// ```compile_fail,E0080
+/// # use ble_hci::{att::Uuid, attribute_server::*, gatt_server, Data};
+/// # let mut rf = |_| Ok(Data::default());
+/// # let mut wf = |_, _data: Data| Ok(());
+/// gatt_server! {
+///     let services = [
+///         service S(Uuid::Uuid16(0x180f)) {
+///             characteristic C(Uuid::Uuid16(0x2a19), ATT_READABLE | ATT_INDICATE, &mut rf, &mut wf) {}
+///         }
+///     ];
+/// }
+/// ```
+///
+/// Two Characteristic Extended Properties descriptors:
+///
+/// ```compile_fail,E0080
+/// # use ble_hci::{att::Uuid, attribute_server::*, gatt_server, Data};
+/// # let mut rf = |_| Ok(Data::default());
+/// # let mut wf = |_, _data: Data| Ok(());
+/// gatt_server! {
+///     let services = [
+///         service S(Uuid::Uuid16(0x180f)) {
+///             characteristic C(Uuid::Uuid16(0x2a19), ATT_READABLE, &mut rf, &mut wf) {
+///                 extended_properties E1: EXTENDED_PROPERTY_RELIABLE_WRITE;
+///                 extended_properties E2: EXTENDED_PROPERTY_RELIABLE_WRITE;
+///             }
+///         }
+///     ];
+/// }
+/// ```
+///
+/// Two Characteristic User Description descriptors:
+///
+/// ```compile_fail,E0080
+/// # use ble_hci::{att::Uuid, attribute_server::*, gatt_server, Data};
+/// # let mut rf = |_| Ok(Data::default());
+/// # let mut wf = |_, _data: Data| Ok(());
+/// gatt_server! {
+///     let services = [
+///         service S(Uuid::Uuid16(0x180f)) {
+///             characteristic C(Uuid::Uuid16(0x2a19), ATT_READABLE, &mut rf, &mut wf) {
+///                 user_description D1: "one";
+///                 user_description D2: "two";
+///             }
+///         }
+///     ];
+/// }
+/// ```
+///
+/// Two Valid Range descriptors:
+///
+/// ```compile_fail,E0080
+/// # use ble_hci::{att::Uuid, attribute_server::*, gatt_server, Data};
+/// # let mut rf = |_| Ok(Data::default());
+/// # let mut wf = |_, _data: Data| Ok(());
+/// gatt_server! {
+///     let services = [
+///         service S(Uuid::Uuid16(0x180f)) {
+///             characteristic C(Uuid::Uuid16(0x2a19), ATT_READABLE, &mut rf, &mut wf) {
+///                 valid_range R1: (&[0], &[100]);
+///                 valid_range R2: (&[0], &[100]);
+///             }
+///         }
+///     ];
+/// }
+/// ```
+///
+/// A custom descriptor with the UUID of the CCCD:
+///
+/// ```compile_fail,E0080
+/// # use ble_hci::{att::Uuid, attribute_server::*, gatt_server, Data};
+/// # let mut rf = |_| Ok(Data::default());
+/// # let mut wf = |_, _data: Data| Ok(());
+/// gatt_server! {
+///     let services = [
+///         service S(Uuid::Uuid16(0x180f)) {
+///             characteristic C(Uuid::Uuid16(0x2a19), ATT_READABLE, &mut rf, &mut wf) {
+///                 descriptor D(Uuid::Uuid16(0x2902)): Data::new(&[0x01, 0x00]);
+///             }
+///         }
+///     ];
+/// }
+/// ```
+///
+/// A custom descriptor with the 128-bit form of the Characteristic User Description UUID:
+///
+/// ```compile_fail,E0080
+/// # use ble_hci::{att::Uuid, attribute_server::*, gatt_server, Data};
+/// # let mut rf = |_| Ok(Data::default());
+/// # let mut wf = |_, _data: Data| Ok(());
+/// # let mut rf2 = |_| Ok(Data::default());
+/// # let mut wf2 = |_, _data: Data| Ok(());
+/// gatt_server! {
+///     let services = [
+///         service S(Uuid::Uuid16(0x180f)) {
+///             characteristic C(Uuid::Uuid16(0x2a19), ATT_READABLE, &mut rf, &mut wf) {
+///                 descriptor D(
+///                     Uuid::Uuid128([
+///                         0x00, 0x00, 0x29, 0x01, 0x00, 0x00, 0x10, 0x00,
+///                         0x80, 0x00, 0x00, 0x80, 0x5f, 0x9b, 0x34, 0xfb,
+///                     ]),
+///                     PERMISSION_READ,
+///                     &mut rf2,
+///                     &mut wf2,
+///                 );
+///             }
+///         }
+///     ];
+/// }
+/// ```
+///
+/// An include of a service which isn't in the list:
+///
+/// ```compile_fail,E0080
+/// # use ble_hci::{att::Uuid, gatt_server};
+/// gatt_server! {
+///     let services = [
+///         service S(Uuid::Uuid16(0x180f)) includes [1] {}
+///     ];
+/// }
+/// ```
+///
+/// A service including itself:
+///
+/// ```compile_fail,E0080
+/// # use ble_hci::{att::Uuid, gatt_server};
+/// gatt_server! {
+///     let services = [
+///         service S(Uuid::Uuid16(0x180f)) includes [0] {}
+///     ];
+/// }
+/// ```
+#[cfg(doctest)]
+struct GattServerViolations;
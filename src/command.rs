@@ -46,9 +46,141 @@ impl CommandHeader {
     }
 }
 
+/// Advertising event type as used by LE Set Advertising Parameters.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum AdvertisingType {
+    /// Connectable and scannable undirected advertising (ADV_IND).
+    AdvInd = 0x00,
+    /// Connectable high duty cycle directed advertising (ADV_DIRECT_IND).
+    AdvDirectIndHighDuty = 0x01,
+    /// Scannable undirected advertising (ADV_SCAN_IND).
+    AdvScanInd = 0x02,
+    /// Non connectable undirected advertising (ADV_NONCONN_IND).
+    AdvNonconnInd = 0x03,
+    /// Connectable low duty cycle directed advertising (ADV_DIRECT_IND).
+    AdvDirectIndLowDuty = 0x04,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum OwnAddressType {
+    Public = 0x00,
+    Random = 0x01,
+    /// Resolvable private address, falling back to the public address.
+    ResolvablePrivateOrPublic = 0x02,
+    /// Resolvable private address, falling back to the random address.
+    ResolvablePrivateOrRandom = 0x03,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum PeerAddressType {
+    Public = 0x00,
+    Random = 0x01,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum AdvertisingFilterPolicy {
+    /// Process scan and connection requests from all devices.
+    All = 0x00,
+    /// Process connection requests from all devices and scan requests only from the filter accept list.
+    FilterScanRequests = 0x01,
+    /// Process scan requests from all devices and connection requests only from the filter accept list.
+    FilterConnectionRequests = 0x02,
+    /// Process scan and connection requests only from the filter accept list.
+    FilterScanAndConnectionRequests = 0x03,
+}
+
+pub const ADVERTISING_CHANNEL_37: u8 = 0b001;
+pub const ADVERTISING_CHANNEL_38: u8 = 0b010;
+pub const ADVERTISING_CHANNEL_39: u8 = 0b100;
+pub const ADVERTISING_CHANNEL_ALL: u8 =
+    ADVERTISING_CHANNEL_37 | ADVERTISING_CHANNEL_38 | ADVERTISING_CHANNEL_39;
+
+/// Smallest and largest advertising interval allowed by the spec in units of 0.625 ms.
+pub const ADVERTISING_INTERVAL_MIN: u16 = 0x0020;
+pub const ADVERTISING_INTERVAL_MAX: u16 = 0x4000;
+
+#[derive(Debug, PartialEq)]
+pub enum AdvertisingParametersError {
+    /// An interval is outside of `ADVERTISING_INTERVAL_MIN..=ADVERTISING_INTERVAL_MAX`.
+    IntervalOutOfRange,
+    /// `interval_min` is greater than `interval_max`.
+    IntervalMinGreaterThanMax,
+    /// The channel map is empty or uses reserved bits.
+    InvalidChannelMap,
+}
+
+/// Parameters for the LE Set Advertising Parameters command.
+///
+/// Intervals are given in units of 0.625 ms.
+#[derive(Debug, Clone, Copy)]
+pub struct AdvertisingParameters {
+    pub interval_min: u16,
+    pub interval_max: u16,
+    pub advertising_type: AdvertisingType,
+    pub own_address_type: OwnAddressType,
+    pub peer_address_type: PeerAddressType,
+    /// Only used for directed advertising. Little endian like all addresses on the wire.
+    pub peer_address: [u8; 6],
+    pub channel_map: u8,
+    pub filter_policy: AdvertisingFilterPolicy,
+}
+
+impl AdvertisingParameters {
+    /// Checks the parameters against the ranges allowed by the spec.
+    ///
+    /// The intervals are not checked for high duty cycle directed advertising since the
+    /// controller ignores them in that case.
+    pub fn validate(&self) -> Result<(), AdvertisingParametersError> {
+        if self.advertising_type != AdvertisingType::AdvDirectIndHighDuty {
+            let range = ADVERTISING_INTERVAL_MIN..=ADVERTISING_INTERVAL_MAX;
+            if !range.contains(&self.interval_min) || !range.contains(&self.interval_max) {
+                return Err(AdvertisingParametersError::IntervalOutOfRange);
+            }
+
+            if self.interval_min > self.interval_max {
+                return Err(AdvertisingParametersError::IntervalMinGreaterThanMax);
+            }
+        }
+
+        if self.channel_map == 0 || self.channel_map & !ADVERTISING_CHANNEL_ALL != 0 {
+            return Err(AdvertisingParametersError::InvalidChannelMap);
+        }
+
+        Ok(())
+    }
+
+    pub fn encode(&self) -> [u8; 15] {
+        let mut data = [0u8; 15];
+        data[0..2].copy_from_slice(&self.interval_min.to_le_bytes());
+        data[2..4].copy_from_slice(&self.interval_max.to_le_bytes());
+        data[4] = self.advertising_type as u8;
+        data[5] = self.own_address_type as u8;
+        data[6] = self.peer_address_type as u8;
+        data[7..13].copy_from_slice(&self.peer_address);
+        data[13] = self.channel_map;
+        data[14] = self.filter_policy as u8;
+        data
+    }
+}
+
+impl Default for AdvertisingParameters {
+    fn default() -> Self {
+        AdvertisingParameters {
+            interval_min: 0x0190,
+            interval_max: 0x0320,
+            advertising_type: AdvertisingType::AdvInd,
+            own_address_type: OwnAddressType::Public,
+            peer_address_type: PeerAddressType::Random,
+            peer_address: [0u8; 6],
+            channel_map: ADVERTISING_CHANNEL_37,
+            filter_policy: AdvertisingFilterPolicy::All,
+        }
+    }
+}
+
 pub enum Command {
     Reset,
-    LeSetAdvertisingParameters,
+    LeSetAdvertisingParameters(AdvertisingParameters),
     LeSetAdvertisingData { data: Data },
     LeSetAdvertiseEnable(bool),
 }
@@ -61,13 +193,12 @@ pub fn create_command_data(command: Command) -> Data {
             CommandHeader::from_ogf_ocf(CONTROLLER_OGF, RESET_OCF, 0x00).write_into(&mut data[1..]);
             Data::new(&data)
         }
-        Command::LeSetAdvertisingParameters => {
+        Command::LeSetAdvertisingParameters(ref params) => {
             let mut data = [0u8; 4 + 0xf];
             data[0] = 0x01;
             CommandHeader::from_ogf_ocf(LE_OGF, SET_ADVERTISING_PARAMETERS_OCF, 0x0f)
                 .write_into(&mut data[1..]);
-            // TODO create this - not hardcoded
-            data[4..].copy_from_slice(&[0x90, 1, 0x20, 3, 0, 0, 1, 0, 0, 0, 0, 0, 0, 1, 0]);
+            data[4..].copy_from_slice(&params.encode());
             Data::new(&data)
         }
         Command::LeSetAdvertisingData { ref data } => {
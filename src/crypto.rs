@@ -0,0 +1,173 @@
+//! AES-128 and AES-CMAC (RFC 4493) as used by the Bluetooth security functions.
+//!
+//! Keys, blocks and MACs are in the byte order of FIPS-197 and RFC 4493, the most significant
+//! octet first. Values sent over the air by Bluetooth are usually little endian and need to be
+//! reversed before and after.
+
+const SBOX: [u8; 256] = [
+    0x63, 0x7c, 0x77, 0x7b, 0xf2, 0x6b, 0x6f, 0xc5, 0x30, 0x01, 0x67, 0x2b, 0xfe, 0xd7, 0xab, 0x76,
+    0xca, 0x82, 0xc9, 0x7d, 0xfa, 0x59, 0x47, 0xf0, 0xad, 0xd4, 0xa2, 0xaf, 0x9c, 0xa4, 0x72, 0xc0,
+    0xb7, 0xfd, 0x93, 0x26, 0x36, 0x3f, 0xf7, 0xcc, 0x34, 0xa5, 0xe5, 0xf1, 0x71, 0xd8, 0x31, 0x15,
+    0x04, 0xc7, 0x23, 0xc3, 0x18, 0x96, 0x05, 0x9a, 0x07, 0x12, 0x80, 0xe2, 0xeb, 0x27, 0xb2, 0x75,
+    0x09, 0x83, 0x2c, 0x1a, 0x1b, 0x6e, 0x5a, 0xa0, 0x52, 0x3b, 0xd6, 0xb3, 0x29, 0xe3, 0x2f, 0x84,
+    0x53, 0xd1, 0x00, 0xed, 0x20, 0xfc, 0xb1, 0x5b, 0x6a, 0xcb, 0xbe, 0x39, 0x4a, 0x4c, 0x58, 0xcf,
+    0xd0, 0xef, 0xaa, 0xfb, 0x43, 0x4d, 0x33, 0x85, 0x45, 0xf9, 0x02, 0x7f, 0x50, 0x3c, 0x9f, 0xa8,
+    0x51, 0xa3, 0x40, 0x8f, 0x92, 0x9d, 0x38, 0xf5, 0xbc, 0xb6, 0xda, 0x21, 0x10, 0xff, 0xf3, 0xd2,
+    0xcd, 0x0c, 0x13, 0xec, 0x5f, 0x97, 0x44, 0x17, 0xc4, 0xa7, 0x7e, 0x3d, 0x64, 0x5d, 0x19, 0x73,
+    0x60, 0x81, 0x4f, 0xdc, 0x22, 0x2a, 0x90, 0x88, 0x46, 0xee, 0xb8, 0x14, 0xde, 0x5e, 0x0b, 0xdb,
+    0xe0, 0x32, 0x3a, 0x0a, 0x49, 0x06, 0x24, 0x5c, 0xc2, 0xd3, 0xac, 0x62, 0x91, 0x95, 0xe4, 0x79,
+    0xe7, 0xc8, 0x37, 0x6d, 0x8d, 0xd5, 0x4e, 0xa9, 0x6c, 0x56, 0xf4, 0xea, 0x65, 0x7a, 0xae, 0x08,
+    0xba, 0x78, 0x25, 0x2e, 0x1c, 0xa6, 0xb4, 0xc6, 0xe8, 0xdd, 0x74, 0x1f, 0x4b, 0xbd, 0x8b, 0x8a,
+    0x70, 0x3e, 0xb5, 0x66, 0x48, 0x03, 0xf6, 0x0e, 0x61, 0x35, 0x57, 0xb9, 0x86, 0xc1, 0x1d, 0x9e,
+    0xe1, 0xf8, 0x98, 0x11, 0x69, 0xd9, 0x8e, 0x94, 0x9b, 0x1e, 0x87, 0xe9, 0xce, 0x55, 0x28, 0xdf,
+    0x8c, 0xa1, 0x89, 0x0d, 0xbf, 0xe6, 0x42, 0x68, 0x41, 0x99, 0x2d, 0x0f, 0xb0, 0x54, 0xbb, 0x16,
+];
+
+const RCON: [u8; 10] = [0x01, 0x02, 0x04, 0x08, 0x10, 0x20, 0x40, 0x80, 0x1b, 0x36];
+
+/// Multiplication by x in GF(2^8).
+fn xtime(byte: u8) -> u8 {
+    (byte << 1) ^ if byte & 0x80 != 0 { 0x1b } else { 0x00 }
+}
+
+fn expand_key(key: &[u8; 16]) -> [[u8; 16]; 11] {
+    let mut round_keys = [[0u8; 16]; 11];
+    round_keys[0] = *key;
+
+    for round in 1..11 {
+        let previous = round_keys[round - 1];
+        let mut word = [previous[13], previous[14], previous[15], previous[12]];
+        for byte in word.iter_mut() {
+            *byte = SBOX[*byte as usize];
+        }
+        word[0] ^= RCON[round - 1];
+
+        for i in 0..16 {
+            let byte = previous[i] ^ word[i % 4];
+            round_keys[round][i] = byte;
+            word[i % 4] = byte;
+        }
+    }
+
+    round_keys
+}
+
+/// Encrypts a single block with AES-128.
+pub fn aes128_encrypt(key: &[u8; 16], block: &[u8; 16]) -> [u8; 16] {
+    let round_keys = expand_key(key);
+
+    let mut state = *block;
+    for (byte, key_byte) in state.iter_mut().zip(round_keys[0].iter()) {
+        *byte ^= key_byte;
+    }
+
+    for (round, round_key) in round_keys.iter().enumerate().skip(1) {
+        // SubBytes and ShiftRows, the state is stored column by column
+        let mut shifted = [0u8; 16];
+        for column in 0..4 {
+            for row in 0..4 {
+                shifted[column * 4 + row] = SBOX[state[((column + row) % 4) * 4 + row] as usize];
+            }
+        }
+        state = shifted;
+
+        // MixColumns, skipped in the last round
+        if round != 10 {
+            for column in state.chunks_mut(4) {
+                let all = column[0] ^ column[1] ^ column[2] ^ column[3];
+                let first = column[0];
+                column[0] ^= all ^ xtime(column[0] ^ column[1]);
+                column[1] ^= all ^ xtime(column[1] ^ column[2]);
+                column[2] ^= all ^ xtime(column[2] ^ column[3]);
+                column[3] ^= all ^ xtime(column[3] ^ first);
+            }
+        }
+
+        for (byte, key_byte) in state.iter_mut().zip(round_key.iter()) {
+            *byte ^= key_byte;
+        }
+    }
+
+    state
+}
+
+/// Doubling in GF(2^128) used to derive the CMAC subkeys.
+fn double(block: &[u8; 16]) -> [u8; 16] {
+    let mut doubled = [0u8; 16];
+    for i in 0..16 {
+        let carry = if i < 15 { block[i + 1] >> 7 } else { 0 };
+        doubled[i] = (block[i] << 1) | carry;
+    }
+    if block[0] & 0x80 != 0 {
+        doubled[15] ^= 0x87;
+    }
+    doubled
+}
+
+/// Calculates the AES-CMAC of the message.
+pub fn aes_cmac(key: &[u8; 16], message: &[u8]) -> [u8; 16] {
+    let mut cmac = AesCmac::new(key);
+    cmac.update(message);
+    cmac.finalize()
+}
+
+/// AES-CMAC over a message passed in parts, for messages which aren't available as one slice.
+pub struct AesCmac {
+    key: [u8; 16],
+    mac: [u8; 16],
+    /// Bytes of the current block. A complete block is only processed once more data follows,
+    /// the last block is padded and combined with a subkey.
+    block: [u8; 16],
+    block_len: usize,
+}
+
+impl AesCmac {
+    pub fn new(key: &[u8; 16]) -> AesCmac {
+        AesCmac {
+            key: *key,
+            mac: [0u8; 16],
+            block: [0u8; 16],
+            block_len: 0,
+        }
+    }
+
+    pub fn update(&mut self, mut data: &[u8]) {
+        while !data.is_empty() {
+            if self.block_len == 16 {
+                self.process_block();
+            }
+
+            let len = core::cmp::min(16 - self.block_len, data.len());
+            self.block[self.block_len..self.block_len + len].copy_from_slice(&data[..len]);
+            self.block_len += len;
+            data = &data[len..];
+        }
+    }
+
+    pub fn finalize(mut self) -> [u8; 16] {
+        let k1 = double(&aes128_encrypt(&self.key, &[0u8; 16]));
+        let subkey = if self.block_len == 16 {
+            k1
+        } else {
+            self.block[self.block_len] = 0x80;
+            for byte in self.block[self.block_len + 1..].iter_mut() {
+                *byte = 0;
+            }
+            double(&k1)
+        };
+        for (byte, key_byte) in self.block.iter_mut().zip(subkey.iter()) {
+            *byte ^= key_byte;
+        }
+
+        self.process_block();
+        self.mac
+    }
+
+    fn process_block(&mut self) {
+        for (byte, mac_byte) in self.block.iter_mut().zip(self.mac.iter()) {
+            *byte ^= mac_byte;
+        }
+        self.mac = aes128_encrypt(&self.key, &self.block);
+        self.block_len = 0;
+    }
+}
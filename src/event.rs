@@ -1,9 +1,47 @@
-use crate::{read_to_data, Data, HciConnector};
+use crate::HciConnector;
+
+/// Parameters of an HCI event. Unlike `Data` this holds the longest possible event.
+#[derive(Clone, Copy)]
+pub struct EventData {
+    pub data: [u8; 255],
+    pub len: usize,
+}
+
+impl EventData {
+    pub fn new(bytes: &[u8]) -> EventData {
+        let mut data = [0u8; 255];
+        data[..bytes.len()].copy_from_slice(bytes);
+        EventData {
+            data,
+            len: bytes.len(),
+        }
+    }
+
+    pub fn to_slice(&self) -> &[u8] {
+        &self.data[0..self.len]
+    }
+
+    pub fn subdata_from(&self, from: usize) -> EventData {
+        EventData::new(&self.data[from..self.len])
+    }
+}
+
+impl Default for EventData {
+    fn default() -> Self {
+        EventData::new(&[])
+    }
+}
+
+impl core::fmt::Debug for EventData {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        write!(f, "{:x?}", self.to_slice())
+    }
+}
 
 #[derive(Debug)]
 pub struct Event {
     code: u8,
-    data: Data,
+    data: EventData,
 }
 
 #[derive(Debug, Clone, Copy)]
@@ -11,37 +49,320 @@ pub enum EventType {
     CommandComplete {
         num_packets: u8,
         opcode: u16,
-        data: Data,
+        data: EventData,
     },
     DisconnectComplete {
         handle: u16,
         status: ErrorCode,
         reason: ErrorCode,
     },
+    /// Encryption of the connection was switched on or off.
+    EncryptionChange {
+        status: ErrorCode,
+        handle: u16,
+        enabled: bool,
+    },
     NumberOfCompletedPackets {
         number_of_connection_handles: u8,
         connection_handles: u16, // should be list
         completed_packets: u16,  // should be list
     },
-    Unknown,
+    LeMeta(LeMetaEvent),
+    Unknown {
+        code: u8,
+        data: EventData,
+    },
+}
+
+/// Role of the local device in a connection.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Role {
+    Central,
+    Peripheral,
+}
+
+/// Subevents of the LE Meta event.
+///
+/// Intervals and timeouts are given in the units defined by the spec, i.e. 1.25 ms for connection
+/// intervals and 10 ms for supervision timeouts.
+#[derive(Debug, Clone, Copy)]
+pub enum LeMetaEvent {
+    ConnectionComplete {
+        status: ErrorCode,
+        handle: u16,
+        role: Role,
+        peer_address_type: u8,
+        peer_address: [u8; 6],
+        connection_interval: u16,
+        peripheral_latency: u16,
+        supervision_timeout: u16,
+        central_clock_accuracy: u8,
+    },
+    /// Use `LeMetaEvent::advertising_reports` to iterate the reports.
+    AdvertisingReport {
+        num_reports: u8,
+        data: EventData,
+    },
+    ConnectionUpdateComplete {
+        status: ErrorCode,
+        handle: u16,
+        connection_interval: u16,
+        peripheral_latency: u16,
+        supervision_timeout: u16,
+    },
+    ReadRemoteFeaturesComplete {
+        status: ErrorCode,
+        handle: u16,
+        le_features: [u8; 8],
+    },
+    LongTermKeyRequest {
+        handle: u16,
+        random_number: [u8; 8],
+        encrypted_diversifier: u16,
+    },
+    DataLengthChange {
+        handle: u16,
+        max_tx_octets: u16,
+        max_tx_time: u16,
+        max_rx_octets: u16,
+        max_rx_time: u16,
+    },
+    EnhancedConnectionComplete {
+        status: ErrorCode,
+        handle: u16,
+        role: Role,
+        peer_address_type: u8,
+        peer_address: [u8; 6],
+        local_resolvable_private_address: [u8; 6],
+        peer_resolvable_private_address: [u8; 6],
+        connection_interval: u16,
+        peripheral_latency: u16,
+        supervision_timeout: u16,
+        central_clock_accuracy: u8,
+    },
+    PhyUpdateComplete {
+        status: ErrorCode,
+        handle: u16,
+        tx_phy: u8,
+        rx_phy: u8,
+    },
+    /// Use `LeMetaEvent::extended_advertising_reports` to iterate the reports.
+    ExtendedAdvertisingReport {
+        num_reports: u8,
+        data: EventData,
+    },
+    Unknown {
+        subevent_code: u8,
+        data: EventData,
+    },
+}
+
+impl LeMetaEvent {
+    /// Iterates the reports of an `AdvertisingReport`. Empty for any other subevent.
+    pub fn advertising_reports(&self) -> AdvertisingReports<'_> {
+        match self {
+            LeMetaEvent::AdvertisingReport { num_reports, data } => AdvertisingReports {
+                remaining: *num_reports,
+                data: data.to_slice(),
+            },
+            _ => AdvertisingReports {
+                remaining: 0,
+                data: &[],
+            },
+        }
+    }
+
+    /// Iterates the reports of an `ExtendedAdvertisingReport`. Empty for any other subevent.
+    pub fn extended_advertising_reports(&self) -> ExtendedAdvertisingReports<'_> {
+        match self {
+            LeMetaEvent::ExtendedAdvertisingReport { num_reports, data } => {
+                ExtendedAdvertisingReports {
+                    remaining: *num_reports,
+                    data: data.to_slice(),
+                }
+            }
+            _ => ExtendedAdvertisingReports {
+                remaining: 0,
+                data: &[],
+            },
+        }
+    }
 }
 
 #[derive(Debug, Clone, Copy)]
+pub struct AdvertisingReport<'a> {
+    pub event_type: u8,
+    pub address_type: u8,
+    pub address: [u8; 6],
+    pub data: &'a [u8],
+    pub rssi: i8,
+}
+
+pub struct AdvertisingReports<'a> {
+    remaining: u8,
+    data: &'a [u8],
+}
+
+impl<'a> Iterator for AdvertisingReports<'a> {
+    type Item = AdvertisingReport<'a>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.remaining == 0 || self.data.len() < 9 {
+            return None;
+        }
+
+        let data_len = self.data[8] as usize;
+        if self.data.len() < 10 + data_len {
+            return None;
+        }
+
+        let report = AdvertisingReport {
+            event_type: self.data[0],
+            address_type: self.data[1],
+            address: read_address(self.data, 2),
+            data: &self.data[9..9 + data_len],
+            rssi: self.data[9 + data_len] as i8,
+        };
+
+        self.remaining -= 1;
+        self.data = &self.data[10 + data_len..];
+        Some(report)
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct ExtendedAdvertisingReport<'a> {
+    pub event_type: u16,
+    pub address_type: u8,
+    pub address: [u8; 6],
+    pub primary_phy: u8,
+    pub secondary_phy: u8,
+    pub advertising_sid: u8,
+    pub tx_power: i8,
+    pub rssi: i8,
+    pub periodic_advertising_interval: u16,
+    pub direct_address_type: u8,
+    pub direct_address: [u8; 6],
+    pub data: &'a [u8],
+}
+
+pub struct ExtendedAdvertisingReports<'a> {
+    remaining: u8,
+    data: &'a [u8],
+}
+
+impl<'a> Iterator for ExtendedAdvertisingReports<'a> {
+    type Item = ExtendedAdvertisingReport<'a>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.remaining == 0 || self.data.len() < 24 {
+            return None;
+        }
+
+        let data_len = self.data[23] as usize;
+        if self.data.len() < 24 + data_len {
+            return None;
+        }
+
+        let report = ExtendedAdvertisingReport {
+            event_type: read_u16(self.data, 0),
+            address_type: self.data[2],
+            address: read_address(self.data, 3),
+            primary_phy: self.data[9],
+            secondary_phy: self.data[10],
+            advertising_sid: self.data[11],
+            tx_power: self.data[12] as i8,
+            rssi: self.data[13] as i8,
+            periodic_advertising_interval: read_u16(self.data, 14),
+            direct_address_type: self.data[16],
+            direct_address: read_address(self.data, 17),
+            data: &self.data[24..24 + data_len],
+        };
+
+        self.remaining -= 1;
+        self.data = &self.data[24 + data_len..];
+        Some(report)
+    }
+}
+
+#[derive(Debug)]
+pub enum EventParseError {
+    /// The event parameters are too short for the event code.
+    InvalidLength { code: u8, len: usize },
+    /// The connector had no more bytes before the end of the event.
+    Incomplete,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
 pub enum ErrorCode {
-    Okay = 0x00,
-    UnknownHciCommand = 0x01,
-    UnknownConnectionIdentifier = 0x02,
-    HardwareFailure = 0x03,
-    PageTimeout = 0x04,
-    AuthenticationFailure = 0x05,
-    PinOrKeyMissing = 0x06,
-    MemoryCapacityExceeded = 0x07,
-    ConnectionTimeout = 0x08,
-    ConnectionLimitExceeded = 0x09,
-    AclConnectionAlreadyExists = 0x0b,
-    CommandDisallowed = 0x0c,
-    RemoteUserTerminatedConnection = 0x13,
-    // see Error Codes Description in spec
+    Okay,
+    UnknownHciCommand,
+    UnknownConnectionIdentifier,
+    HardwareFailure,
+    PageTimeout,
+    AuthenticationFailure,
+    PinOrKeyMissing,
+    MemoryCapacityExceeded,
+    ConnectionTimeout,
+    ConnectionLimitExceeded,
+    SynchronousConnectionLimitExceeded,
+    AclConnectionAlreadyExists,
+    CommandDisallowed,
+    ConnectionRejectedLimitedResources,
+    ConnectionRejectedSecurityReasons,
+    ConnectionRejectedUnacceptableBdAddr,
+    ConnectionAcceptTimeoutExceeded,
+    UnsupportedFeatureOrParameterValue,
+    InvalidHciCommandParameters,
+    RemoteUserTerminatedConnection,
+    RemoteDeviceTerminatedConnectionLowResources,
+    RemoteDeviceTerminatedConnectionPowerOff,
+    ConnectionTerminatedByLocalHost,
+    RepeatedAttempts,
+    PairingNotAllowed,
+    UnknownLmpPdu,
+    UnsupportedRemoteFeature,
+    ScoOffsetRejected,
+    ScoIntervalRejected,
+    ScoAirModeRejected,
+    InvalidLlParameters,
+    UnspecifiedError,
+    UnsupportedLlParameterValue,
+    RoleChangeNotAllowed,
+    LlResponseTimeout,
+    LlProcedureCollision,
+    LmpPduNotAllowed,
+    EncryptionModeNotAcceptable,
+    LinkKeyCannotBeChanged,
+    RequestedQosNotSupported,
+    InstantPassed,
+    PairingWithUnitKeyNotSupported,
+    DifferentTransactionCollision,
+    QosUnacceptableParameter,
+    QosRejected,
+    ChannelClassificationNotSupported,
+    InsufficientSecurity,
+    ParameterOutOfMandatoryRange,
+    RoleSwitchPending,
+    ReservedSlotViolation,
+    RoleSwitchFailed,
+    ExtendedInquiryResponseTooLarge,
+    SecureSimplePairingNotSupportedByHost,
+    HostBusyPairing,
+    ConnectionRejectedNoSuitableChannelFound,
+    ControllerBusy,
+    UnacceptableConnectionParameters,
+    AdvertisingTimeout,
+    ConnectionTerminatedMicFailure,
+    ConnectionFailedToBeEstablished,
+    CoarseClockAdjustmentRejected,
+    Type0SubmapNotDefined,
+    UnknownAdvertisingIdentifier,
+    LimitReached,
+    OperationCancelledByHost,
+    PacketTooLong,
+    /// A code not defined in the Error Codes Description of the spec.
+    Other(u8),
 }
 
 impl ErrorCode {
@@ -57,24 +378,177 @@ impl ErrorCode {
             0x07 => ErrorCode::MemoryCapacityExceeded,
             0x08 => ErrorCode::ConnectionTimeout,
             0x09 => ErrorCode::ConnectionLimitExceeded,
+            0x0a => ErrorCode::SynchronousConnectionLimitExceeded,
             0x0b => ErrorCode::AclConnectionAlreadyExists,
             0x0c => ErrorCode::CommandDisallowed,
+            0x0d => ErrorCode::ConnectionRejectedLimitedResources,
+            0x0e => ErrorCode::ConnectionRejectedSecurityReasons,
+            0x0f => ErrorCode::ConnectionRejectedUnacceptableBdAddr,
+            0x10 => ErrorCode::ConnectionAcceptTimeoutExceeded,
+            0x11 => ErrorCode::UnsupportedFeatureOrParameterValue,
+            0x12 => ErrorCode::InvalidHciCommandParameters,
             0x13 => ErrorCode::RemoteUserTerminatedConnection,
+            0x14 => ErrorCode::RemoteDeviceTerminatedConnectionLowResources,
+            0x15 => ErrorCode::RemoteDeviceTerminatedConnectionPowerOff,
+            0x16 => ErrorCode::ConnectionTerminatedByLocalHost,
+            0x17 => ErrorCode::RepeatedAttempts,
+            0x18 => ErrorCode::PairingNotAllowed,
+            0x19 => ErrorCode::UnknownLmpPdu,
+            0x1a => ErrorCode::UnsupportedRemoteFeature,
+            0x1b => ErrorCode::ScoOffsetRejected,
+            0x1c => ErrorCode::ScoIntervalRejected,
+            0x1d => ErrorCode::ScoAirModeRejected,
+            0x1e => ErrorCode::InvalidLlParameters,
+            0x1f => ErrorCode::UnspecifiedError,
+            0x20 => ErrorCode::UnsupportedLlParameterValue,
+            0x21 => ErrorCode::RoleChangeNotAllowed,
+            0x22 => ErrorCode::LlResponseTimeout,
+            0x23 => ErrorCode::LlProcedureCollision,
+            0x24 => ErrorCode::LmpPduNotAllowed,
+            0x25 => ErrorCode::EncryptionModeNotAcceptable,
+            0x26 => ErrorCode::LinkKeyCannotBeChanged,
+            0x27 => ErrorCode::RequestedQosNotSupported,
+            0x28 => ErrorCode::InstantPassed,
+            0x29 => ErrorCode::PairingWithUnitKeyNotSupported,
+            0x2a => ErrorCode::DifferentTransactionCollision,
+            0x2c => ErrorCode::QosUnacceptableParameter,
+            0x2d => ErrorCode::QosRejected,
+            0x2e => ErrorCode::ChannelClassificationNotSupported,
+            0x2f => ErrorCode::InsufficientSecurity,
+            0x30 => ErrorCode::ParameterOutOfMandatoryRange,
+            0x32 => ErrorCode::RoleSwitchPending,
+            0x34 => ErrorCode::ReservedSlotViolation,
+            0x35 => ErrorCode::RoleSwitchFailed,
+            0x36 => ErrorCode::ExtendedInquiryResponseTooLarge,
+            0x37 => ErrorCode::SecureSimplePairingNotSupportedByHost,
+            0x38 => ErrorCode::HostBusyPairing,
+            0x39 => ErrorCode::ConnectionRejectedNoSuitableChannelFound,
+            0x3a => ErrorCode::ControllerBusy,
+            0x3b => ErrorCode::UnacceptableConnectionParameters,
+            0x3c => ErrorCode::AdvertisingTimeout,
+            0x3d => ErrorCode::ConnectionTerminatedMicFailure,
+            0x3e => ErrorCode::ConnectionFailedToBeEstablished,
+            0x40 => ErrorCode::CoarseClockAdjustmentRejected,
+            0x41 => ErrorCode::Type0SubmapNotDefined,
+            0x42 => ErrorCode::UnknownAdvertisingIdentifier,
+            0x43 => ErrorCode::LimitReached,
+            0x44 => ErrorCode::OperationCancelledByHost,
+            0x45 => ErrorCode::PacketTooLong,
+            _ => ErrorCode::Other(value),
+        }
+    }
 
-            _ => panic!("Unknown error code {}", value),
+    pub fn to_u8(self) -> u8 {
+        match self {
+            ErrorCode::Okay => 0x00,
+            ErrorCode::UnknownHciCommand => 0x01,
+            ErrorCode::UnknownConnectionIdentifier => 0x02,
+            ErrorCode::HardwareFailure => 0x03,
+            ErrorCode::PageTimeout => 0x04,
+            ErrorCode::AuthenticationFailure => 0x05,
+            ErrorCode::PinOrKeyMissing => 0x06,
+            ErrorCode::MemoryCapacityExceeded => 0x07,
+            ErrorCode::ConnectionTimeout => 0x08,
+            ErrorCode::ConnectionLimitExceeded => 0x09,
+            ErrorCode::SynchronousConnectionLimitExceeded => 0x0a,
+            ErrorCode::AclConnectionAlreadyExists => 0x0b,
+            ErrorCode::CommandDisallowed => 0x0c,
+            ErrorCode::ConnectionRejectedLimitedResources => 0x0d,
+            ErrorCode::ConnectionRejectedSecurityReasons => 0x0e,
+            ErrorCode::ConnectionRejectedUnacceptableBdAddr => 0x0f,
+            ErrorCode::ConnectionAcceptTimeoutExceeded => 0x10,
+            ErrorCode::UnsupportedFeatureOrParameterValue => 0x11,
+            ErrorCode::InvalidHciCommandParameters => 0x12,
+            ErrorCode::RemoteUserTerminatedConnection => 0x13,
+            ErrorCode::RemoteDeviceTerminatedConnectionLowResources => 0x14,
+            ErrorCode::RemoteDeviceTerminatedConnectionPowerOff => 0x15,
+            ErrorCode::ConnectionTerminatedByLocalHost => 0x16,
+            ErrorCode::RepeatedAttempts => 0x17,
+            ErrorCode::PairingNotAllowed => 0x18,
+            ErrorCode::UnknownLmpPdu => 0x19,
+            ErrorCode::UnsupportedRemoteFeature => 0x1a,
+            ErrorCode::ScoOffsetRejected => 0x1b,
+            ErrorCode::ScoIntervalRejected => 0x1c,
+            ErrorCode::ScoAirModeRejected => 0x1d,
+            ErrorCode::InvalidLlParameters => 0x1e,
+            ErrorCode::UnspecifiedError => 0x1f,
+            ErrorCode::UnsupportedLlParameterValue => 0x20,
+            ErrorCode::RoleChangeNotAllowed => 0x21,
+            ErrorCode::LlResponseTimeout => 0x22,
+            ErrorCode::LlProcedureCollision => 0x23,
+            ErrorCode::LmpPduNotAllowed => 0x24,
+            ErrorCode::EncryptionModeNotAcceptable => 0x25,
+            ErrorCode::LinkKeyCannotBeChanged => 0x26,
+            ErrorCode::RequestedQosNotSupported => 0x27,
+            ErrorCode::InstantPassed => 0x28,
+            ErrorCode::PairingWithUnitKeyNotSupported => 0x29,
+            ErrorCode::DifferentTransactionCollision => 0x2a,
+            ErrorCode::QosUnacceptableParameter => 0x2c,
+            ErrorCode::QosRejected => 0x2d,
+            ErrorCode::ChannelClassificationNotSupported => 0x2e,
+            ErrorCode::InsufficientSecurity => 0x2f,
+            ErrorCode::ParameterOutOfMandatoryRange => 0x30,
+            ErrorCode::RoleSwitchPending => 0x32,
+            ErrorCode::ReservedSlotViolation => 0x34,
+            ErrorCode::RoleSwitchFailed => 0x35,
+            ErrorCode::ExtendedInquiryResponseTooLarge => 0x36,
+            ErrorCode::SecureSimplePairingNotSupportedByHost => 0x37,
+            ErrorCode::HostBusyPairing => 0x38,
+            ErrorCode::ConnectionRejectedNoSuitableChannelFound => 0x39,
+            ErrorCode::ControllerBusy => 0x3a,
+            ErrorCode::UnacceptableConnectionParameters => 0x3b,
+            ErrorCode::AdvertisingTimeout => 0x3c,
+            ErrorCode::ConnectionTerminatedMicFailure => 0x3d,
+            ErrorCode::ConnectionFailedToBeEstablished => 0x3e,
+            ErrorCode::CoarseClockAdjustmentRejected => 0x40,
+            ErrorCode::Type0SubmapNotDefined => 0x41,
+            ErrorCode::UnknownAdvertisingIdentifier => 0x42,
+            ErrorCode::LimitReached => 0x43,
+            ErrorCode::OperationCancelledByHost => 0x44,
+            ErrorCode::PacketTooLong => 0x45,
+            ErrorCode::Other(value) => value,
         }
     }
 }
 
-const EVENT_COMMAND_COMPLETE: u8 = 0x0e;
+pub(crate) const EVENT_COMMAND_COMPLETE: u8 = 0x0e;
 const EVENT_DISCONNECTION_COMPLETE: u8 = 0x05;
+const EVENT_ENCRYPTION_CHANGE: u8 = 0x08;
 const EVENT_NUMBER_OF_COMPLETED_PACKETS: u8 = 0x13;
+const EVENT_LE_META: u8 = 0x3e;
+
+const LE_SUBEVENT_CONNECTION_COMPLETE: u8 = 0x01;
+const LE_SUBEVENT_ADVERTISING_REPORT: u8 = 0x02;
+const LE_SUBEVENT_CONNECTION_UPDATE_COMPLETE: u8 = 0x03;
+const LE_SUBEVENT_READ_REMOTE_FEATURES_COMPLETE: u8 = 0x04;
+const LE_SUBEVENT_LONG_TERM_KEY_REQUEST: u8 = 0x05;
+const LE_SUBEVENT_DATA_LENGTH_CHANGE: u8 = 0x07;
+const LE_SUBEVENT_ENHANCED_CONNECTION_COMPLETE: u8 = 0x0a;
+const LE_SUBEVENT_PHY_UPDATE_COMPLETE: u8 = 0x0c;
+const LE_SUBEVENT_EXTENDED_ADVERTISING_REPORT: u8 = 0x0d;
 
 /// Parses a command and assumes the packet type (0x04) is already read.
-pub fn parse_event(connector: &dyn HciConnector) -> EventType {
-    let event = read_to_event(connector);
+///
+/// Events not known to this crate are returned as `EventType::Unknown`.
+pub fn parse_event(connector: &dyn HciConnector) -> Result<EventType, EventParseError> {
+    let event = read_to_event(connector)?;
 
-    match event.code {
+    let min_len = match event.code {
+        EVENT_COMMAND_COMPLETE => 3,
+        EVENT_DISCONNECTION_COMPLETE => 4,
+        EVENT_ENCRYPTION_CHANGE => 4,
+        EVENT_NUMBER_OF_COMPLETED_PACKETS => 5,
+        EVENT_LE_META => 1,
+        _ => 0,
+    };
+    if event.data.len < min_len {
+        return Err(EventParseError::InvalidLength {
+            code: event.code,
+            len: event.data.len,
+        });
+    }
+
+    let event = match event.code {
         EVENT_COMMAND_COMPLETE => {
             let data = event.data.to_slice();
             let num_packets = data[0];
@@ -99,6 +573,14 @@ pub fn parse_event(connector: &dyn HciConnector) -> EventType {
                 reason,
             }
         }
+        EVENT_ENCRYPTION_CHANGE => {
+            let data = event.data.to_slice();
+            EventType::EncryptionChange {
+                status: ErrorCode::from_u8(data[0]),
+                handle: read_u16(data, 1),
+                enabled: data[3] != 0,
+            }
+        }
         EVENT_NUMBER_OF_COMPLETED_PACKETS => {
             let data = event.data.to_slice();
             let num_handles = data[0];
@@ -110,15 +592,145 @@ pub fn parse_event(connector: &dyn HciConnector) -> EventType {
                 completed_packets: completed_packet,
             }
         }
-        _ => {
-            panic!("Unknown event {:x}", event.code); // should be an error returned
+        EVENT_LE_META => EventType::LeMeta(parse_le_meta_event(&event.data)?),
+        _ => EventType::Unknown {
+            code: event.code,
+            data: event.data,
+        },
+    };
+
+    Ok(event)
+}
+
+fn parse_le_meta_event(event_data: &EventData) -> Result<LeMetaEvent, EventParseError> {
+    let subevent_code = event_data.to_slice()[0];
+    let data = &event_data.to_slice()[1..];
+
+    let min_len = match subevent_code {
+        LE_SUBEVENT_CONNECTION_COMPLETE => 18,
+        LE_SUBEVENT_ADVERTISING_REPORT => 1,
+        LE_SUBEVENT_CONNECTION_UPDATE_COMPLETE => 9,
+        LE_SUBEVENT_READ_REMOTE_FEATURES_COMPLETE => 11,
+        LE_SUBEVENT_LONG_TERM_KEY_REQUEST => 12,
+        LE_SUBEVENT_DATA_LENGTH_CHANGE => 10,
+        LE_SUBEVENT_ENHANCED_CONNECTION_COMPLETE => 30,
+        LE_SUBEVENT_PHY_UPDATE_COMPLETE => 5,
+        LE_SUBEVENT_EXTENDED_ADVERTISING_REPORT => 1,
+        _ => 0,
+    };
+    if data.len() < min_len {
+        return Err(EventParseError::InvalidLength {
+            code: EVENT_LE_META,
+            len: event_data.len,
+        });
+    }
+
+    let event = match subevent_code {
+        LE_SUBEVENT_CONNECTION_COMPLETE => LeMetaEvent::ConnectionComplete {
+            status: ErrorCode::from_u8(data[0]),
+            handle: read_u16(data, 1),
+            role: read_role(data[3]),
+            peer_address_type: data[4],
+            peer_address: read_address(data, 5),
+            connection_interval: read_u16(data, 11),
+            peripheral_latency: read_u16(data, 13),
+            supervision_timeout: read_u16(data, 15),
+            central_clock_accuracy: data[17],
+        },
+        LE_SUBEVENT_ADVERTISING_REPORT => LeMetaEvent::AdvertisingReport {
+            num_reports: data[0],
+            data: EventData::new(&data[1..]),
+        },
+        LE_SUBEVENT_CONNECTION_UPDATE_COMPLETE => LeMetaEvent::ConnectionUpdateComplete {
+            status: ErrorCode::from_u8(data[0]),
+            handle: read_u16(data, 1),
+            connection_interval: read_u16(data, 3),
+            peripheral_latency: read_u16(data, 5),
+            supervision_timeout: read_u16(data, 7),
+        },
+        LE_SUBEVENT_READ_REMOTE_FEATURES_COMPLETE => {
+            let mut le_features = [0u8; 8];
+            le_features.copy_from_slice(&data[3..11]);
+            LeMetaEvent::ReadRemoteFeaturesComplete {
+                status: ErrorCode::from_u8(data[0]),
+                handle: read_u16(data, 1),
+                le_features,
+            }
+        }
+        LE_SUBEVENT_LONG_TERM_KEY_REQUEST => {
+            let mut random_number = [0u8; 8];
+            random_number.copy_from_slice(&data[2..10]);
+            LeMetaEvent::LongTermKeyRequest {
+                handle: read_u16(data, 0),
+                random_number,
+                encrypted_diversifier: read_u16(data, 10),
+            }
         }
+        LE_SUBEVENT_DATA_LENGTH_CHANGE => LeMetaEvent::DataLengthChange {
+            handle: read_u16(data, 0),
+            max_tx_octets: read_u16(data, 2),
+            max_tx_time: read_u16(data, 4),
+            max_rx_octets: read_u16(data, 6),
+            max_rx_time: read_u16(data, 8),
+        },
+        LE_SUBEVENT_ENHANCED_CONNECTION_COMPLETE => LeMetaEvent::EnhancedConnectionComplete {
+            status: ErrorCode::from_u8(data[0]),
+            handle: read_u16(data, 1),
+            role: read_role(data[3]),
+            peer_address_type: data[4],
+            peer_address: read_address(data, 5),
+            local_resolvable_private_address: read_address(data, 11),
+            peer_resolvable_private_address: read_address(data, 17),
+            connection_interval: read_u16(data, 23),
+            peripheral_latency: read_u16(data, 25),
+            supervision_timeout: read_u16(data, 27),
+            central_clock_accuracy: data[29],
+        },
+        LE_SUBEVENT_PHY_UPDATE_COMPLETE => LeMetaEvent::PhyUpdateComplete {
+            status: ErrorCode::from_u8(data[0]),
+            handle: read_u16(data, 1),
+            tx_phy: data[3],
+            rx_phy: data[4],
+        },
+        LE_SUBEVENT_EXTENDED_ADVERTISING_REPORT => LeMetaEvent::ExtendedAdvertisingReport {
+            num_reports: data[0],
+            data: EventData::new(&data[1..]),
+        },
+        _ => LeMetaEvent::Unknown {
+            subevent_code,
+            data: EventData::new(data),
+        },
+    };
+
+    Ok(event)
+}
+
+fn read_u16(data: &[u8], at: usize) -> u16 {
+    (data[at] as u16) + ((data[at + 1] as u16) << 8)
+}
+
+fn read_address(data: &[u8], at: usize) -> [u8; 6] {
+    let mut address = [0u8; 6];
+    address.copy_from_slice(&data[at..at + 6]);
+    address
+}
+
+fn read_role(role: u8) -> Role {
+    if role == 0 {
+        Role::Central
+    } else {
+        Role::Peripheral
     }
 }
 
-fn read_to_event(connector: &dyn HciConnector) -> Event {
-    let code = connector.read().unwrap() as u8;
-    let len = connector.read().unwrap() as usize;
-    let data = read_to_data(connector, len);
-    Event { code, data }
+fn read_to_event(connector: &dyn HciConnector) -> Result<Event, EventParseError> {
+    let code = connector.read().ok_or(EventParseError::Incomplete)?;
+    let len = connector.read().ok_or(EventParseError::Incomplete)? as usize;
+
+    let mut data = EventData::default();
+    for i in 0..len {
+        data.data[i] = connector.read().ok_or(EventParseError::Incomplete)?;
+    }
+    data.len = len;
+    Ok(Event { code, data })
 }
@@ -0,0 +1,673 @@
+use crate::{
+    acl::{encode_acl_packet, BoundaryFlag, HostBroadcastFlag},
+    att::{
+        att_encode_exchange_mtu_request, att_encode_execute_write_request,
+        att_encode_find_by_type_value_request, att_encode_find_information_request,
+        att_encode_handle_value_confirmation, att_encode_prepare_write_request,
+        att_encode_read_blob_request, att_encode_read_by_group_type_request,
+        att_encode_read_by_type_request, att_encode_read_request, att_encode_write_command,
+        att_encode_write_request, parse_att, Att, AttErrorCode, AttParseError, Uuid,
+        ATT_DEFAULT_MTU, ATT_ERROR_RESPONSE_OPCODE, ATT_MAX_MTU,
+    },
+    event::{ErrorCode, EventType},
+    l2cap::{encode_l2cap, parse_l2cap, L2capParseError},
+    Ble, Data, Error, PollResult,
+};
+
+const PRIMARY_SERVICE_UUID16: u16 = 0x2800;
+const INCLUDE_UUID16: u16 = 0x2802;
+const CHARACTERISTIC_UUID16: u16 = 0x2803;
+
+const CCCD_NOTIFY: u16 = 0x0001;
+const CCCD_INDICATE: u16 = 0x0002;
+
+/// Flags of the Execute Write request.
+const EXECUTE_WRITE_CANCEL: u8 = 0x00;
+const EXECUTE_WRITE_WRITE: u8 = 0x01;
+
+/// L2CAP channel of the attribute protocol.
+const ATT_CHANNEL: u16 = 0x0004;
+
+/// Time the server has to answer a request, see Core Vol 3 Part F 3.3.3.
+const ATT_TIMEOUT_MILLIS: u64 = 30_000;
+
+#[derive(Debug)]
+pub enum GattClientError {
+    L2capError(L2capParseError),
+    AttError(AttParseError),
+    BleError(Error),
+    /// The server answered the request with an Error Response.
+    ErrorResponse {
+        handle: u16,
+        code: AttErrorCode,
+    },
+    /// The server answered with a response that doesn't fit the request.
+    UnexpectedResponse,
+    /// The server didn't answer within the ATT transaction timeout.
+    Timeout,
+    /// The connection was closed while waiting for the response.
+    Disconnected,
+}
+
+impl From<L2capParseError> for GattClientError {
+    fn from(err: L2capParseError) -> Self {
+        GattClientError::L2capError(err)
+    }
+}
+
+impl From<AttParseError> for GattClientError {
+    fn from(err: AttParseError) -> Self {
+        GattClientError::AttError(err)
+    }
+}
+
+impl From<Error> for GattClientError {
+    fn from(err: Error) -> Self {
+        GattClientError::BleError(err)
+    }
+}
+
+/// A service found by the service discovery, the attributes of the service are in
+/// `start_handle..=end_handle`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct DiscoveredService {
+    pub start_handle: u16,
+    pub end_handle: u16,
+    pub uuid: Uuid,
+}
+
+/// A characteristic found by the characteristic discovery.
+///
+/// Its descriptors follow the value handle up to the declaration of the next characteristic or
+/// the end of the service.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct DiscoveredCharacteristic {
+    pub declaration_handle: u16,
+    /// Properties like `ATT_READABLE` or `ATT_NOTIFY`.
+    pub properties: u8,
+    pub value_handle: u16,
+    pub uuid: Uuid,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct DiscoveredDescriptor {
+    pub handle: u16,
+    pub uuid: Uuid,
+}
+
+/// A service included by another one, found by the included service discovery.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct IncludedService {
+    /// Handle of the include declaration.
+    pub handle: u16,
+    pub start_handle: u16,
+    pub end_handle: u16,
+    pub uuid: Uuid,
+}
+
+/// A Handle Value Notification or Indication sent by the server.
+#[derive(Debug, Clone, Copy)]
+pub struct Notification {
+    pub handle: u16,
+    pub value: Data,
+    /// The server sent an indication, which was confirmed already.
+    pub indication: bool,
+}
+
+/// GATT client talking to the server on the other end of a connection.
+///
+/// Each procedure sends its requests and waits for the responses, polling `ble` until then.
+/// Notifications and indications received in the meantime are passed to the notification
+/// handler, anything else, e.g. data of other connections, is dropped.
+pub struct GattClient<'a> {
+    ble: &'a mut Ble<'a>,
+    connection_handle: u16,
+    mtu: u16,
+    notification_handler: Option<&'a mut dyn FnMut(Notification)>,
+}
+
+impl<'a> GattClient<'a> {
+    pub fn new(ble: &'a mut Ble<'a>, connection_handle: u16) -> GattClient<'a> {
+        GattClient {
+            ble,
+            connection_handle,
+            mtu: ATT_DEFAULT_MTU,
+            notification_handler: None,
+        }
+    }
+
+    /// Sets the handler called for each notification and indication of the server, both while
+    /// waiting for responses and in `do_work`. Indications are confirmed after it returned.
+    pub fn set_notification_handler(&mut self, handler: &'a mut dyn FnMut(Notification)) {
+        self.notification_handler = Some(handler);
+    }
+
+    /// MTU of the connection, `ATT_DEFAULT_MTU` until it was exchanged.
+    pub fn mtu(&self) -> u16 {
+        self.mtu
+    }
+
+    /// Offers the given receive MTU to the server and returns the MTU used afterwards.
+    ///
+    /// The value is clamped to `ATT_DEFAULT_MTU..=ATT_MAX_MTU`.
+    pub fn exchange_mtu(&mut self, mtu: u16) -> Result<u16, GattClientError> {
+        let client_mtu = mtu.clamp(ATT_DEFAULT_MTU, ATT_MAX_MTU);
+
+        match self.request(att_encode_exchange_mtu_request(client_mtu))? {
+            Att::ExchangeMtuRsp { mtu: server_mtu } => {
+                self.mtu = server_mtu.clamp(ATT_DEFAULT_MTU, client_mtu);
+                Ok(self.mtu)
+            }
+            _ => Err(GattClientError::UnexpectedResponse),
+        }
+    }
+
+    /// Discovers all primary services of the server.
+    pub fn discover_primary_services(
+        &mut self,
+        mut found: impl FnMut(DiscoveredService),
+    ) -> Result<(), GattClientError> {
+        let mut start = 0x0001;
+        loop {
+            let attribute_list =
+                match self.discovery_request(att_encode_read_by_group_type_request(
+                    start,
+                    0xffff,
+                    Uuid::Uuid16(PRIMARY_SERVICE_UUID16),
+                ))? {
+                    Some(Att::ReadByGroupTypeRsp { attribute_list }) => attribute_list,
+                    Some(_) => return Err(GattClientError::UnexpectedResponse),
+                    None => return Ok(()),
+                };
+
+            let mut last = 0;
+            for entry in attribute_list.entries() {
+                let service = DiscoveredService {
+                    start_handle: u16::from_le_bytes([entry[0], entry[1]]),
+                    end_handle: u16::from_le_bytes([entry[2], entry[3]]),
+                    uuid: Uuid::from_le_bytes(&entry[4..])
+                        .ok_or(GattClientError::UnexpectedResponse)?,
+                };
+                last = service.end_handle;
+                found(service);
+            }
+
+            start = match next_start(start, last, 0xffff)? {
+                Some(start) => start,
+                None => return Ok(()),
+            };
+        }
+    }
+
+    /// Discovers the primary services with the given UUID.
+    pub fn discover_primary_services_by_uuid(
+        &mut self,
+        uuid: Uuid,
+        mut found: impl FnMut(DiscoveredService),
+    ) -> Result<(), GattClientError> {
+        let mut start = 0x0001;
+        loop {
+            let handles = match self.discovery_request(att_encode_find_by_type_value_request(
+                start,
+                0xffff,
+                PRIMARY_SERVICE_UUID16,
+                &uuid.encode(),
+            ))? {
+                Some(Att::FindByTypeValueRsp { handles }) => handles,
+                Some(_) => return Err(GattClientError::UnexpectedResponse),
+                None => return Ok(()),
+            };
+
+            let mut last = 0;
+            for entry in handles.entries() {
+                let service = DiscoveredService {
+                    start_handle: u16::from_le_bytes([entry[0], entry[1]]),
+                    end_handle: u16::from_le_bytes([entry[2], entry[3]]),
+                    uuid,
+                };
+                last = service.end_handle;
+                found(service);
+            }
+
+            start = match next_start(start, last, 0xffff)? {
+                Some(start) => start,
+                None => return Ok(()),
+            };
+        }
+    }
+
+    /// Discovers the characteristics declared in the given handle range, usually the range of a
+    /// service.
+    pub fn discover_characteristics(
+        &mut self,
+        start: u16,
+        end: u16,
+        mut found: impl FnMut(DiscoveredCharacteristic),
+    ) -> Result<(), GattClientError> {
+        let mut start = start;
+        loop {
+            let attribute_list = match self.discovery_request(att_encode_read_by_type_request(
+                start,
+                end,
+                Uuid::Uuid16(CHARACTERISTIC_UUID16),
+            ))? {
+                Some(Att::ReadByTypeRsp { attribute_list }) => attribute_list,
+                Some(_) => return Err(GattClientError::UnexpectedResponse),
+                None => return Ok(()),
+            };
+
+            let mut last = 0;
+            for entry in attribute_list.entries() {
+                // declaration handle, properties, value handle and the UUID
+                if entry.len() < 7 {
+                    return Err(GattClientError::UnexpectedResponse);
+                }
+                let characteristic = DiscoveredCharacteristic {
+                    declaration_handle: u16::from_le_bytes([entry[0], entry[1]]),
+                    properties: entry[2],
+                    value_handle: u16::from_le_bytes([entry[3], entry[4]]),
+                    uuid: Uuid::from_le_bytes(&entry[5..])
+                        .ok_or(GattClientError::UnexpectedResponse)?,
+                };
+                last = characteristic.declaration_handle;
+                found(characteristic);
+            }
+
+            start = match next_start(start, last, end)? {
+                Some(start) => start,
+                None => return Ok(()),
+            };
+        }
+    }
+
+    /// Discovers the descriptors in the given handle range, usually from the handle after a
+    /// characteristic value to the handle before the next characteristic declaration.
+    pub fn discover_descriptors(
+        &mut self,
+        start: u16,
+        end: u16,
+        mut found: impl FnMut(DiscoveredDescriptor),
+    ) -> Result<(), GattClientError> {
+        let mut start = start;
+        loop {
+            let information =
+                match self.discovery_request(att_encode_find_information_request(start, end))? {
+                    Some(Att::FindInformationRsp { information }) => information,
+                    Some(_) => return Err(GattClientError::UnexpectedResponse),
+                    None => return Ok(()),
+                };
+
+            let mut last = 0;
+            for entry in information.entries() {
+                let descriptor = DiscoveredDescriptor {
+                    handle: u16::from_le_bytes([entry[0], entry[1]]),
+                    uuid: Uuid::from_le_bytes(&entry[2..])
+                        .ok_or(GattClientError::UnexpectedResponse)?,
+                };
+                last = descriptor.handle;
+                found(descriptor);
+            }
+
+            start = match next_start(start, last, end)? {
+                Some(start) => start,
+                None => return Ok(()),
+            };
+        }
+    }
+
+    /// Discovers the services included in the given handle range, usually the range of a
+    /// service.
+    ///
+    /// Include declarations only carry 16-bit UUIDs, 128-bit UUIDs are read from the declaration
+    /// of the included service.
+    pub fn discover_included_services(
+        &mut self,
+        start: u16,
+        end: u16,
+        mut found: impl FnMut(IncludedService),
+    ) -> Result<(), GattClientError> {
+        let mut start = start;
+        loop {
+            let attribute_list = match self.discovery_request(att_encode_read_by_type_request(
+                start,
+                end,
+                Uuid::Uuid16(INCLUDE_UUID16),
+            ))? {
+                Some(Att::ReadByTypeRsp { attribute_list }) => attribute_list,
+                Some(_) => return Err(GattClientError::UnexpectedResponse),
+                None => return Ok(()),
+            };
+
+            let mut last = 0;
+            for entry in attribute_list.entries() {
+                let start_handle = match entry.len() {
+                    6 | 8 => u16::from_le_bytes([entry[2], entry[3]]),
+                    _ => return Err(GattClientError::UnexpectedResponse),
+                };
+                let uuid = match Uuid::from_le_bytes(&entry[6..]) {
+                    Some(uuid) => uuid,
+                    None => self.read_service_uuid(start_handle)?,
+                };
+                let service = IncludedService {
+                    handle: u16::from_le_bytes([entry[0], entry[1]]),
+                    start_handle,
+                    end_handle: u16::from_le_bytes([entry[4], entry[5]]),
+                    uuid,
+                };
+                last = service.handle;
+                found(service);
+            }
+
+            start = match next_start(start, last, end)? {
+                Some(start) => start,
+                None => return Ok(()),
+            };
+        }
+    }
+
+    /// Reads the value of the attribute with the given handle.
+    ///
+    /// Values which don't fit into a Read Response are read in parts with Read Blob requests.
+    /// Values longer than `Data` can hold are truncated, `read_blob` reads the remaining parts.
+    pub fn read(&mut self, handle: u16) -> Result<Data, GattClientError> {
+        let mut value = match self.request(att_encode_read_request(handle))? {
+            Att::ReadRsp { value } => value,
+            _ => return Err(GattClientError::UnexpectedResponse),
+        };
+
+        // a part shorter than the maximum ends the value
+        let max_part_len = self.mtu as usize - 1;
+        let mut part_len = value.len;
+        while part_len == max_part_len && value.len < value.data.len() {
+            let part = match self.request(att_encode_read_blob_request(handle, value.len as u16)) {
+                Ok(Att::ReadBlobRsp { part }) => part,
+                Ok(_) => return Err(GattClientError::UnexpectedResponse),
+                // the value had exactly the length of the previous parts
+                Err(GattClientError::ErrorResponse {
+                    code: AttErrorCode::AttributeNotLong,
+                    ..
+                })
+                | Err(GattClientError::ErrorResponse {
+                    code: AttErrorCode::InvalidOffset,
+                    ..
+                }) => break,
+                Err(err) => return Err(err),
+            };
+
+            part_len = part.len;
+            let len = core::cmp::min(part.len, value.data.len() - value.len);
+            value.append(&part.to_slice()[..len]);
+        }
+
+        Ok(value)
+    }
+
+    /// Reads the part of the value starting at the given offset with a Read Blob request.
+    pub fn read_blob(&mut self, handle: u16, offset: u16) -> Result<Data, GattClientError> {
+        match self.request(att_encode_read_blob_request(handle, offset))? {
+            Att::ReadBlobRsp { part } => Ok(part),
+            _ => Err(GattClientError::UnexpectedResponse),
+        }
+    }
+
+    /// Writes the value with a Write Request. The value is truncated to MTU - 3 bytes, longer
+    /// values need `write_long`.
+    pub fn write(&mut self, handle: u16, value: &Data) -> Result<(), GattClientError> {
+        match self.request(att_encode_write_request(handle, value, self.mtu))? {
+            Att::WriteRsp => Ok(()),
+            _ => Err(GattClientError::UnexpectedResponse),
+        }
+    }
+
+    /// Writes the value with a Write Command, which the server doesn't answer. The value is
+    /// truncated to MTU - 3 bytes.
+    pub fn write_without_response(&mut self, handle: u16, value: &Data) {
+        self.write_att(att_encode_write_command(handle, value, self.mtu));
+    }
+
+    /// Writes the value in parts with Prepare Write requests, which the server applies when
+    /// they are executed.
+    ///
+    /// The server echoes each part. If an echo doesn't match or the server rejects a part, the
+    /// queued parts are cancelled.
+    pub fn write_long(&mut self, handle: u16, value: &Data) -> Result<(), GattClientError> {
+        let max_part_len = self.mtu as usize - 5;
+        let mut offset = 0;
+        while offset < value.len {
+            let len = core::cmp::min(value.len - offset, max_part_len);
+            let part = Data::new(&value.to_slice()[offset..offset + len]);
+
+            let response = self.request(att_encode_prepare_write_request(
+                handle,
+                offset as u16,
+                &part,
+                self.mtu,
+            ));
+            match response {
+                Ok(Att::PrepareWriteRsp {
+                    handle: echo_handle,
+                    offset: echo_offset,
+                    value: echo_value,
+                }) if echo_handle == handle
+                    && echo_offset as usize == offset
+                    && echo_value.to_slice() == part.to_slice() => {}
+                Ok(_) => {
+                    self.request(att_encode_execute_write_request(EXECUTE_WRITE_CANCEL))?;
+                    return Err(GattClientError::UnexpectedResponse);
+                }
+                Err(err @ GattClientError::ErrorResponse { .. }) => {
+                    self.request(att_encode_execute_write_request(EXECUTE_WRITE_CANCEL))?;
+                    return Err(err);
+                }
+                Err(err) => return Err(err),
+            }
+
+            offset += len;
+        }
+
+        match self.request(att_encode_execute_write_request(EXECUTE_WRITE_WRITE))? {
+            Att::ExecuteWriteRsp => Ok(()),
+            _ => Err(GattClientError::UnexpectedResponse),
+        }
+    }
+
+    /// Enables notifications, or indications if `indications` is set, by writing the given
+    /// Client Characteristic Configuration descriptor.
+    pub fn subscribe(
+        &mut self,
+        cccd_handle: u16,
+        indications: bool,
+    ) -> Result<(), GattClientError> {
+        let value = if indications {
+            CCCD_INDICATE
+        } else {
+            CCCD_NOTIFY
+        };
+        self.write(cccd_handle, &Data::new(&value.to_le_bytes()))
+    }
+
+    /// Disables notifications and indications by writing the given Client Characteristic
+    /// Configuration descriptor.
+    pub fn unsubscribe(&mut self, cccd_handle: u16) -> Result<(), GattClientError> {
+        self.write(cccd_handle, &Data::new(&[0x00, 0x00]))
+    }
+
+    /// Polls `ble` once and passes a received notification or indication to the handler.
+    ///
+    /// Returns `GattClientError::Disconnected` once the connection was closed.
+    pub fn do_work(&mut self) -> Result<(), GattClientError> {
+        if let Some(packet) = self.ble.poll()? {
+            // there is no request, any response is late or unsolicited
+            self.handle_packet(packet, None)?;
+        }
+
+        Ok(())
+    }
+
+    fn read_service_uuid(&mut self, service_handle: u16) -> Result<Uuid, GattClientError> {
+        match self.request(att_encode_read_request(service_handle))? {
+            Att::ReadRsp { value } => {
+                Uuid::from_le_bytes(value.to_slice()).ok_or(GattClientError::UnexpectedResponse)
+            }
+            _ => Err(GattClientError::UnexpectedResponse),
+        }
+    }
+
+    /// Sends a request of a discovery procedure, which ends when the server doesn't find any
+    /// further attributes. Returns `None` in that case.
+    fn discovery_request(&mut self, request: Data) -> Result<Option<Att>, GattClientError> {
+        match self.request(request) {
+            Ok(response) => Ok(Some(response)),
+            Err(GattClientError::ErrorResponse {
+                code: AttErrorCode::AttributeNotFound,
+                ..
+            }) => Ok(None),
+            Err(err) => Err(err),
+        }
+    }
+
+    /// Sends the request and waits for the response. Error Responses are returned as
+    /// `GattClientError::ErrorResponse`.
+    fn request(&mut self, request: Data) -> Result<Att, GattClientError> {
+        let opcode = request.to_slice()[0];
+        self.write_att(request);
+
+        match self.wait_for_response(opcode)? {
+            Att::ErrorRsp {
+                request_opcode,
+                handle,
+                code,
+            } if request_opcode == opcode => Err(GattClientError::ErrorResponse { handle, code }),
+            Att::ErrorRsp { .. } => Err(GattClientError::UnexpectedResponse),
+            response => Ok(response),
+        }
+    }
+
+    fn wait_for_response(&mut self, request_opcode: u8) -> Result<Att, GattClientError> {
+        let timeout_at = self.ble.connector.millis() + ATT_TIMEOUT_MILLIS;
+        loop {
+            match self.ble.poll()? {
+                Some(packet) => {
+                    if let Some(response) = self.handle_packet(packet, Some(request_opcode))? {
+                        return Ok(response);
+                    }
+                }
+                None => {
+                    if self.ble.connector.millis() > timeout_at {
+                        return Err(GattClientError::Timeout);
+                    }
+                }
+            }
+        }
+    }
+
+    /// Handles notifications and indications and returns responses received on the connection.
+    ///
+    /// PDUs which can't be parsed are ignored, unless they answer the outstanding request.
+    fn handle_packet(
+        &mut self,
+        packet: PollResult,
+        request_opcode: Option<u8>,
+    ) -> Result<Option<Att>, GattClientError> {
+        match packet {
+            PollResult::AsyncData(packet) if packet.handle == self.connection_handle => {
+                let l2cap_packet = parse_l2cap(packet)?;
+                if l2cap_packet.channel != ATT_CHANNEL {
+                    return Ok(None);
+                }
+
+                let opcode = l2cap_packet.payload.to_slice().first().copied();
+                let packet = match parse_att(l2cap_packet) {
+                    Ok(packet) => packet,
+                    Err(err) if is_response_to(opcode, request_opcode) => return Err(err.into()),
+                    Err(_) => return Ok(None),
+                };
+
+                match packet {
+                    Att::HandleValueNotification { handle, value } => {
+                        self.notify_handler(Notification {
+                            handle,
+                            value,
+                            indication: false,
+                        });
+                        Ok(None)
+                    }
+                    Att::HandleValueIndication { handle, value } => {
+                        self.notify_handler(Notification {
+                            handle,
+                            value,
+                            indication: true,
+                        });
+                        self.write_att(att_encode_handle_value_confirmation());
+                        Ok(None)
+                    }
+                    packet if is_response(&packet) => Ok(Some(packet)),
+                    _ => Ok(None),
+                }
+            }
+            PollResult::Event(EventType::DisconnectComplete {
+                handle,
+                status: ErrorCode::Okay,
+                ..
+            }) if handle == self.connection_handle => Err(GattClientError::Disconnected),
+            _ => Ok(None),
+        }
+    }
+
+    fn notify_handler(&mut self, notification: Notification) {
+        if let Some(handler) = self.notification_handler.as_mut() {
+            handler(notification);
+        }
+    }
+
+    fn write_att(&mut self, data: Data) {
+        let res = encode_l2cap(data);
+        let res = encode_acl_packet(
+            self.connection_handle,
+            BoundaryFlag::FirstAutoFlushable,
+            HostBroadcastFlag::NoBroadcast,
+            res,
+        );
+        self.ble.write_bytes(res.to_slice());
+    }
+}
+
+/// Returns true if the opcode is the one of the response or Error Response to the request.
+fn is_response_to(opcode: Option<u8>, request_opcode: Option<u8>) -> bool {
+    match (opcode, request_opcode) {
+        (Some(opcode), Some(request_opcode)) => {
+            opcode == request_opcode + 1 || opcode == ATT_ERROR_RESPONSE_OPCODE
+        }
+        _ => false,
+    }
+}
+
+fn is_response(packet: &Att) -> bool {
+    matches!(
+        packet,
+        Att::ErrorRsp { .. }
+            | Att::ExchangeMtuRsp { .. }
+            | Att::FindInformationRsp { .. }
+            | Att::FindByTypeValueRsp { .. }
+            | Att::ReadByGroupTypeRsp { .. }
+            | Att::ReadByTypeRsp { .. }
+            | Att::ReadRsp { .. }
+            | Att::ReadBlobRsp { .. }
+            | Att::WriteRsp
+            | Att::PrepareWriteRsp { .. }
+            | Att::ExecuteWriteRsp
+    )
+}
+
+/// Start handle of the next request of a discovery procedure, `None` once `end` was reached.
+///
+/// Servers have to answer with handles in ascending order, anything else would never end.
+fn next_start(start: u16, last: u16, end: u16) -> Result<Option<u16>, GattClientError> {
+    if last < start {
+        Err(GattClientError::UnexpectedResponse)
+    } else if last >= end {
+        Ok(None)
+    } else {
+        Ok(Some(last + 1))
+    }
+}
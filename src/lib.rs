@@ -4,10 +4,11 @@
 use acl::{parse_acl_packet, AclPacket};
 use att::Uuid;
 use command::{
-    create_command_data, opcode, Command, SET_ADVERTISE_ENABLE_OCF, SET_ADVERTISING_DATA_OCF,
+    create_command_data, opcode, AdvertisingParameters, AdvertisingParametersError, Command,
+    SET_ADVERTISE_ENABLE_OCF, SET_ADVERTISING_DATA_OCF,
 };
 use command::{LE_OGF, SET_ADVERTISING_PARAMETERS_OCF};
-use event::{parse_event, EventType};
+use event::{parse_event, EventParseError, EventType, EVENT_COMMAND_COMPLETE};
 
 pub mod acl;
 pub mod att;
@@ -20,6 +21,10 @@ pub mod ad_structure;
 
 pub mod attribute_server;
 
+pub mod gatt_client;
+
+pub mod crypto;
+
 use command::CONTROLLER_OGF;
 use command::RESET_OCF;
 
@@ -29,6 +34,14 @@ const TIMEOUT_MILLIS: u64 = 1000;
 pub enum Error {
     Timeout,
     Failed,
+    InvalidAdvertisingParameters(AdvertisingParametersError),
+    EventParseError(EventParseError),
+}
+
+impl From<EventParseError> for Error {
+    fn from(err: EventParseError) -> Self {
+        Error::EventParseError(err)
+    }
 }
 
 #[derive(Debug)]
@@ -98,8 +111,16 @@ fn check_command_completed(event: EventType) -> Result<EventType, Error> {
         data,
     } = event
     {
-        if data.to_slice()[0] != 0 {
-            return Err(Error::Failed);
+        // the return parameters start with the status
+        match data.to_slice().first() {
+            Some(0) => (),
+            Some(_) => return Err(Error::Failed),
+            None => {
+                return Err(Error::EventParseError(EventParseError::InvalidLength {
+                    code: EVENT_COMMAND_COMPLETE,
+                    len: 3,
+                }))
+            }
         }
     }
 
@@ -130,11 +151,20 @@ impl<'a> Ble<'a> {
         check_command_completed(self.wait_for_command_complete(CONTROLLER_OGF, RESET_OCF)?)
     }
 
-    pub fn cmd_set_le_advertising_parameters(&mut self) -> Result<EventType, Error>
+    pub fn cmd_set_le_advertising_parameters(
+        &mut self,
+        params: AdvertisingParameters,
+    ) -> Result<EventType, Error>
     where
         Self: Sized,
     {
-        self.write_bytes(create_command_data(Command::LeSetAdvertisingParameters).to_slice());
+        params
+            .validate()
+            .map_err(Error::InvalidAdvertisingParameters)?;
+
+        self.write_bytes(
+            create_command_data(Command::LeSetAdvertisingParameters(params)).to_slice(),
+        );
         check_command_completed(
             self.wait_for_command_complete(LE_OGF, SET_ADVERTISING_PARAMETERS_OCF)?,
         )
@@ -162,7 +192,7 @@ impl<'a> Ble<'a> {
     {
         let timeout_at = self.connector.millis() + TIMEOUT_MILLIS;
         loop {
-            let res = self.poll();
+            let res = self.poll()?;
 
             match res {
                 Some(PollResult::Event(event)) => match event {
@@ -180,7 +210,11 @@ impl<'a> Ble<'a> {
         }
     }
 
-    pub fn poll(&mut self) -> Option<PollResult>
+    /// Polls the controller for an event or ACL data.
+    ///
+    /// Returns `Ok(None)` if nothing was received. Events which can't be parsed are returned as
+    /// `Error::EventParseError` and are already consumed.
+    pub fn poll(&mut self) -> Result<Option<PollResult>, Error>
     where
         Self: Sized,
     {
@@ -192,11 +226,11 @@ impl<'a> Ble<'a> {
                 PACKET_TYPE_COMMAND => {}
                 PACKET_TYPE_ASYNC_DATA => {
                     let acl_packet = parse_acl_packet(self.connector);
-                    return Some(PollResult::AsyncData(acl_packet));
+                    return Ok(Some(PollResult::AsyncData(acl_packet)));
                 }
                 PACKET_TYPE_EVENT => {
-                    let event = parse_event(self.connector);
-                    return Some(PollResult::Event(event));
+                    let event = parse_event(self.connector)?;
+                    return Ok(Some(PollResult::Event(event)));
                 }
                 _ => {
                     // error
@@ -205,7 +239,7 @@ impl<'a> Ble<'a> {
             None => {}
         }
 
-        None
+        Ok(None)
     }
 
     fn write_bytes(&mut self, bytes: &[u8]) {
@@ -1,6 +1,7 @@
 #![feature(assert_matches)]
 
-use std::cell::RefCell;
+use std::assert_matches;
+use std::cell::{Cell, RefCell};
 
 use ble_hci::{
     acl::{encode_acl_packet, AclPacket, BoundaryFlag, ControllerBroadcastFlag, HostBroadcastFlag},
@@ -8,14 +9,42 @@ use ble_hci::{
         create_advertising_data, AdStructure, BR_EDR_NOT_SUPPORTED, LE_GENERAL_DISCOVERABLE,
     },
     att::{
-        att_encode_error_response, att_encode_read_by_group_type_response,
-        att_encode_read_by_type_response, att_encode_read_response, att_encode_write_response,
-        parse_att, Att, AttErrorCode, AttributeData, AttributePayloadData, Uuid,
-        ATT_READ_BY_GROUP_TYPE_REQUEST_OPCODE,
+        att_encode_error_response, att_encode_exchange_mtu_request,
+        att_encode_exchange_mtu_response, att_encode_execute_write_request,
+        att_encode_execute_write_response, att_encode_find_by_type_value_request,
+        att_encode_find_by_type_value_response, att_encode_find_information_request,
+        att_encode_find_information_response, att_encode_handle_value_confirmation,
+        att_encode_handle_value_indication, att_encode_handle_value_notification,
+        att_encode_prepare_write_request, att_encode_prepare_write_response,
+        att_encode_read_blob_request, att_encode_read_blob_response,
+        att_encode_read_by_group_type_request, att_encode_read_by_group_type_response,
+        att_encode_read_by_type_request, att_encode_read_by_type_response,
+        att_encode_read_multiple_response, att_encode_read_multiple_variable_response,
+        att_encode_read_request, att_encode_read_response, att_encode_write_command,
+        att_encode_write_request, att_encode_write_response, parse_att, Att, AttErrorCode,
+        AttParseError, AttributeData, AttributeInformation, AttributePayloadData,
+        HandlesInformation, Uuid, ATT_DEFAULT_MTU, ATT_READ_BY_GROUP_TYPE_REQUEST_OPCODE,
+    },
+    attribute_server::{
+        AccessContext, AttributeServer, AttributeServerError, Characteristic, ConnectionSecurity,
+        Descriptor, GenericAccess, Service, ATT_INDICATE, ATT_NOTIFY, ATT_READABLE,
+        ATT_SIGNED_WRITE, ATT_WRITEABLE, ATT_WRITE_WITHOUT_RESPONSE,
+        EXTENDED_PROPERTY_RELIABLE_WRITE, GENERIC_SERVICES_END_HANDLE, PERMISSION_READ,
+        PERMISSION_READ_AUTHORIZED, PERMISSION_READ_ENCRYPTED, PERMISSION_WRITE,
+        PERMISSION_WRITE_AUTHENTICATED, PERMISSION_WRITE_AUTHORIZED, PERMISSION_WRITE_ENCRYPTED,
+    },
+    command::{
+        create_command_data, AdvertisingFilterPolicy, AdvertisingParameters,
+        AdvertisingParametersError, AdvertisingType, Command, CommandHeader, OwnAddressType,
+        PeerAddressType, ADVERTISING_CHANNEL_ALL,
+    },
+    crypto::{aes128_encrypt, aes_cmac, AesCmac},
+    event::{ErrorCode, EventParseError, EventType, LeMetaEvent, Role},
+    gatt_client::{
+        DiscoveredCharacteristic, DiscoveredDescriptor, DiscoveredService, GattClient,
+        GattClientError, IncludedService, Notification,
     },
-    attribute_server::{AttributeServer, Service, ATT_READABLE, ATT_WRITEABLE},
-    command::{create_command_data, Command, CommandHeader},
-    event::{ErrorCode, EventType},
+    gatt_server,
     l2cap::{encode_l2cap, parse_l2cap},
     Ble, Data, HciConnector, PollResult,
 };
@@ -23,7 +52,7 @@ use ble_hci::{
 extern crate std;
 
 struct TestConnector {
-    to_read: RefCell<[u8; 128]>,
+    to_read: RefCell<[u8; 512]>,
     to_write: RefCell<[u8; 128]>,
     read_idx: RefCell<usize>,
     read_max: RefCell<usize>,
@@ -113,7 +142,7 @@ impl HciConnector for TestConnector {
 
 fn connector() -> TestConnector {
     TestConnector {
-        to_read: RefCell::new([0u8; 128]),
+        to_read: RefCell::new([0u8; 512]),
         to_write: RefCell::new([0u8; 128]),
         read_idx: RefCell::new(0),
         read_max: RefCell::new(0),
@@ -149,7 +178,7 @@ fn parse_event() {
 
     connector.provide_data_to_read(&[0x04, 0x0e, 0x04, 0x05, 0x03, 0x0c, 0x00]);
 
-    let res = ble.poll();
+    let res = ble.poll().unwrap();
 
     assert_matches!(res, Some(PollResult::Event(EventType::CommandComplete { num_packets: 5, opcode: 0x0c03, data})) if data.to_slice() == &[0] );
 
@@ -207,6 +236,23 @@ fn init_fails() {
     assert_eq!(connector.get_to_write_at(3), 0x00);
 }
 
+#[test]
+fn init_fails_on_truncated_command_complete() {
+    let connector = connector();
+    let mut ble = Ble::new(&connector);
+
+    connector.provide_data_to_read(&[0x04, 0x0e, 0x03, 0x05, 0x03, 0x0c]);
+
+    let res = ble.init();
+
+    assert_matches!(
+        res,
+        Err(ble_hci::Error::EventParseError(
+            EventParseError::InvalidLength { code: 0x0e, len: 3 }
+        ))
+    );
+}
+
 #[test]
 pub fn command_header_reset_parse_works() {
     let header = CommandHeader::from_bytes(&[0x03, 0x0c, 0x00]);
@@ -254,7 +300,9 @@ fn create_reset_command_works() {
 
 #[test]
 fn create_le_set_advertising_parameters_works() {
-    let data = create_command_data(Command::LeSetAdvertisingParameters);
+    let data = create_command_data(Command::LeSetAdvertisingParameters(
+        AdvertisingParameters::default(),
+    ));
     assert_eq!(data.len, 19);
     assert_eq!(
         data.data[..19],
@@ -262,6 +310,68 @@ fn create_le_set_advertising_parameters_works() {
     );
 }
 
+#[test]
+fn create_le_set_advertising_parameters_nonconnectable_works() {
+    let params = AdvertisingParameters {
+        interval_min: 0x0800,
+        interval_max: 0x1000,
+        advertising_type: AdvertisingType::AdvNonconnInd,
+        own_address_type: OwnAddressType::Random,
+        peer_address_type: PeerAddressType::Public,
+        peer_address: [1, 2, 3, 4, 5, 6],
+        channel_map: ADVERTISING_CHANNEL_ALL,
+        filter_policy: AdvertisingFilterPolicy::FilterScanRequests,
+    };
+    assert_matches!(params.validate(), Ok(()));
+
+    let data = create_command_data(Command::LeSetAdvertisingParameters(params));
+    assert_eq!(data.len, 19);
+    assert_eq!(
+        data.data[..19],
+        [0x01, 0x06, 0x20, 0x0f, 0x00, 0x08, 0x00, 0x10, 3, 1, 0, 1, 2, 3, 4, 5, 6, 7, 1]
+    );
+}
+
+#[test]
+fn advertising_parameters_validation_works() {
+    let params = AdvertisingParameters {
+        interval_min: 0x001f,
+        ..Default::default()
+    };
+    assert_matches!(
+        params.validate(),
+        Err(AdvertisingParametersError::IntervalOutOfRange)
+    );
+
+    let params = AdvertisingParameters {
+        interval_min: 0x0400,
+        interval_max: 0x0200,
+        ..Default::default()
+    };
+    assert_matches!(
+        params.validate(),
+        Err(AdvertisingParametersError::IntervalMinGreaterThanMax)
+    );
+
+    let params = AdvertisingParameters {
+        channel_map: 0b1000,
+        ..Default::default()
+    };
+    assert_matches!(
+        params.validate(),
+        Err(AdvertisingParametersError::InvalidChannelMap)
+    );
+
+    // intervals are ignored for high duty cycle directed advertising
+    let params = AdvertisingParameters {
+        interval_min: 0,
+        interval_max: 0,
+        advertising_type: AdvertisingType::AdvDirectIndHighDuty,
+        ..Default::default()
+    };
+    assert_matches!(params.validate(), Ok(()));
+}
+
 #[test]
 fn set_advertising_parameters_works() {
     let connector = connector();
@@ -269,11 +379,30 @@ fn set_advertising_parameters_works() {
 
     connector.provide_data_to_read(&[0x04, 0x0e, 0x04, 0x05, 0x06, 0x20, 0x00]);
 
-    let res = ble.cmd_set_le_advertising_parameters();
+    let res = ble.cmd_set_le_advertising_parameters(AdvertisingParameters::default());
 
     assert_matches!(res, Ok(EventType::CommandComplete{ num_packets: 5, opcode: 0x2006, data}) if data.to_slice() == &[0]);
 }
 
+#[test]
+fn set_advertising_parameters_rejects_invalid_parameters() {
+    let connector = connector();
+    let mut ble = Ble::new(&connector);
+
+    let res = ble.cmd_set_le_advertising_parameters(AdvertisingParameters {
+        channel_map: 0,
+        ..Default::default()
+    });
+
+    assert_matches!(
+        res,
+        Err(ble_hci::Error::InvalidAdvertisingParameters(
+            AdvertisingParametersError::InvalidChannelMap
+        ))
+    );
+    assert_eq!(connector.get_write_idx(), 0);
+}
+
 #[test]
 fn create_le_set_advertising_data_works() {
     let data = create_command_data(Command::LeSetAdvertisingData {
@@ -324,7 +453,7 @@ fn receiving_async_data_works() {
         0x28,
     ]);
 
-    let res = ble.poll();
+    let res = ble.poll().unwrap();
 
     assert_matches!(res,
         Some(PollResult::AsyncData(AclPacket {
@@ -343,7 +472,7 @@ fn receiving_disconnection_complete_works() {
 
     connector.provide_data_to_read(&[0x04, 0x05, 0x04, 0x00, 0x00, 0x00, 0x13]);
 
-    let res = ble.poll();
+    let res = ble.poll().unwrap();
 
     assert_matches!(
         res,
@@ -355,6 +484,317 @@ fn receiving_disconnection_complete_works() {
     );
 }
 
+#[test]
+fn receiving_disconnection_complete_with_unlisted_reason_works() {
+    let connector = connector();
+    let mut ble = Ble::new(&connector);
+
+    connector.provide_data_to_read(&[0x04, 0x05, 0x04, 0x00, 0x40, 0x00, 0xf0]);
+
+    let res = ble.poll().unwrap();
+
+    assert_matches!(
+        res,
+        Some(PollResult::Event(EventType::DisconnectComplete {
+            handle: 0x40,
+            status: ErrorCode::Okay,
+            reason: ErrorCode::Other(0xf0)
+        }))
+    );
+    assert_eq!(
+        ErrorCode::from_u8(0x3e),
+        ErrorCode::ConnectionFailedToBeEstablished
+    );
+    assert_eq!(ErrorCode::ConnectionFailedToBeEstablished.to_u8(), 0x3e);
+}
+
+#[test]
+fn receiving_encryption_change_works() {
+    let connector = connector();
+    let mut ble = Ble::new(&connector);
+
+    connector.provide_data_to_read(&[0x04, 0x08, 0x04, 0x00, 0x40, 0x00, 0x01]);
+
+    let res = ble.poll().unwrap();
+
+    assert_matches!(
+        res,
+        Some(PollResult::Event(EventType::EncryptionChange {
+            status: ErrorCode::Okay,
+            handle: 0x40,
+            enabled: true,
+        }))
+    );
+}
+
+#[test]
+fn receiving_unknown_event_works() {
+    let connector = connector();
+    let mut ble = Ble::new(&connector);
+
+    connector.provide_data_to_read(&[0x04, 0xfe, 0x02, 0xaa, 0xbb]);
+
+    let res = ble.poll().unwrap();
+
+    assert_matches!(
+        res,
+        Some(PollResult::Event(EventType::Unknown { code: 0xfe, data })) if data.to_slice() == [0xaa, 0xbb]
+    );
+}
+
+#[test]
+fn receiving_truncated_event_fails() {
+    let connector = connector();
+    let mut ble = Ble::new(&connector);
+
+    connector.provide_data_to_read(&[0x04, 0x05, 0x02, 0x00, 0x00]);
+
+    let res = ble.poll();
+
+    assert_matches!(
+        res,
+        Err(ble_hci::Error::EventParseError(
+            EventParseError::InvalidLength { code: 0x05, len: 2 }
+        ))
+    );
+}
+
+#[test]
+fn receiving_incomplete_event_fails() {
+    let connector = connector();
+    let mut ble = Ble::new(&connector);
+
+    connector.provide_data_to_read(&[0x04, 0x05, 0x04, 0x00]);
+
+    let res = ble.poll();
+
+    assert_matches!(
+        res,
+        Err(ble_hci::Error::EventParseError(EventParseError::Incomplete))
+    );
+}
+
+#[test]
+fn receiving_le_connection_complete_works() {
+    let connector = connector();
+    let mut ble = Ble::new(&connector);
+
+    connector.provide_data_to_read(&[
+        0x04, 0x3e, 0x13, 0x01, 0x00, 0x40, 0x00, 0x01, 0x01, 0x11, 0x22, 0x33, 0x44, 0x55, 0x66,
+        0x18, 0x00, 0x00, 0x00, 0x48, 0x00, 0x05,
+    ]);
+
+    let res = ble.poll().unwrap();
+
+    assert_matches!(
+        res,
+        Some(PollResult::Event(EventType::LeMeta(
+            LeMetaEvent::ConnectionComplete {
+                status: ErrorCode::Okay,
+                handle: 0x0040,
+                role: Role::Peripheral,
+                peer_address_type: 0x01,
+                peer_address: [0x11, 0x22, 0x33, 0x44, 0x55, 0x66],
+                connection_interval: 0x0018,
+                peripheral_latency: 0x0000,
+                supervision_timeout: 0x0048,
+                central_clock_accuracy: 0x05,
+            }
+        )))
+    );
+}
+
+#[test]
+fn receiving_le_connection_update_complete_works() {
+    let connector = connector();
+    let mut ble = Ble::new(&connector);
+
+    connector.provide_data_to_read(&[
+        0x04, 0x3e, 0x0a, 0x03, 0x00, 0x40, 0x00, 0x28, 0x00, 0x02, 0x00, 0xc8, 0x00,
+    ]);
+
+    let res = ble.poll().unwrap();
+
+    assert_matches!(
+        res,
+        Some(PollResult::Event(EventType::LeMeta(
+            LeMetaEvent::ConnectionUpdateComplete {
+                status: ErrorCode::Okay,
+                handle: 0x0040,
+                connection_interval: 0x0028,
+                peripheral_latency: 0x0002,
+                supervision_timeout: 0x00c8,
+            }
+        )))
+    );
+}
+
+#[test]
+fn receiving_le_advertising_report_works() {
+    let connector = connector();
+    let mut ble = Ble::new(&connector);
+
+    connector.provide_data_to_read(&[
+        0x04, 0x3e, 0x19, 0x02, 0x02, // two reports
+        0x00, 0x00, 0x11, 0x22, 0x33, 0x44, 0x55, 0x66, 0x03, 0x02, 0x01, 0x06, 0xc4, //
+        0x03, 0x01, 0x01, 0x02, 0x03, 0x04, 0x05, 0x06, 0x00, 0xb0,
+    ]);
+
+    let res = ble.poll().unwrap();
+
+    match res {
+        Some(PollResult::Event(EventType::LeMeta(event))) => {
+            let mut reports = event.advertising_reports();
+
+            let report = reports.next().unwrap();
+            assert_eq!(report.event_type, 0x00);
+            assert_eq!(report.address_type, 0x00);
+            assert_eq!(report.address, [0x11, 0x22, 0x33, 0x44, 0x55, 0x66]);
+            assert_eq!(report.data, [0x02, 0x01, 0x06]);
+            assert_eq!(report.rssi, -60);
+
+            let report = reports.next().unwrap();
+            assert_eq!(report.event_type, 0x03);
+            assert_eq!(report.address_type, 0x01);
+            assert_eq!(report.address, [0x01, 0x02, 0x03, 0x04, 0x05, 0x06]);
+            assert!(report.data.is_empty());
+            assert_eq!(report.rssi, -80);
+
+            assert!(reports.next().is_none());
+        }
+        _ => panic!("Expected LE meta event"),
+    }
+}
+
+#[test]
+fn receiving_le_extended_advertising_report_works() {
+    let connector = connector();
+    let mut ble = Ble::new(&connector);
+
+    connector.provide_data_to_read(&[
+        0x04, 0x3e, 0x1c, 0x0d, 0x01, 0x13, 0x00, 0x01, 0x11, 0x22, 0x33, 0x44, 0x55, 0x66, 0x01,
+        0x00, 0xff, 0x7f, 0xd8, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x02, 0xaa,
+        0xbb,
+    ]);
+
+    let res = ble.poll().unwrap();
+
+    match res {
+        Some(PollResult::Event(EventType::LeMeta(event))) => {
+            let mut reports = event.extended_advertising_reports();
+
+            let report = reports.next().unwrap();
+            assert_eq!(report.event_type, 0x0013);
+            assert_eq!(report.address_type, 0x01);
+            assert_eq!(report.address, [0x11, 0x22, 0x33, 0x44, 0x55, 0x66]);
+            assert_eq!(report.primary_phy, 0x01);
+            assert_eq!(report.secondary_phy, 0x00);
+            assert_eq!(report.advertising_sid, 0xff);
+            assert_eq!(report.tx_power, 0x7f);
+            assert_eq!(report.rssi, -40);
+            assert_eq!(report.data, [0xaa, 0xbb]);
+
+            assert!(reports.next().is_none());
+            assert!(event.advertising_reports().next().is_none());
+        }
+        _ => panic!("Expected LE meta event"),
+    }
+}
+
+#[test]
+fn receiving_longest_le_extended_advertising_report_works() {
+    let connector = connector();
+    let mut ble = Ble::new(&connector);
+
+    let advertising_data: Vec<u8> = (0..229).map(|i| i as u8).collect();
+    connector.provide_data_to_read(&[
+        0x04, 0x3e, 0xff, 0x0d, 0x01, 0x13, 0x00, 0x01, 0x11, 0x22, 0x33, 0x44, 0x55, 0x66, 0x01,
+        0x00, 0xff, 0x7f, 0xd8, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0xe5,
+    ]);
+    connector.provide_data_to_read(&advertising_data);
+    // the next event is still parsed
+    connector.provide_data_to_read(&[0x04, 0x05, 0x04, 0x00, 0x00, 0x00, 0x13]);
+
+    let res = ble.poll().unwrap();
+
+    match res {
+        Some(PollResult::Event(EventType::LeMeta(event))) => {
+            let mut reports = event.extended_advertising_reports();
+
+            let report = reports.next().unwrap();
+            assert_eq!(report.address, [0x11, 0x22, 0x33, 0x44, 0x55, 0x66]);
+            assert_eq!(report.data, &advertising_data[..]);
+
+            assert!(reports.next().is_none());
+        }
+        _ => panic!("Expected LE meta event"),
+    }
+
+    let res = ble.poll().unwrap();
+
+    assert_matches!(
+        res,
+        Some(PollResult::Event(EventType::DisconnectComplete {
+            handle: 0,
+            ..
+        }))
+    );
+}
+
+#[test]
+fn receiving_le_long_term_key_request_works() {
+    let connector = connector();
+    let mut ble = Ble::new(&connector);
+
+    connector.provide_data_to_read(&[
+        0x04, 0x3e, 0x0d, 0x05, 0x40, 0x00, 1, 2, 3, 4, 5, 6, 7, 8, 0x34, 0x12,
+    ]);
+
+    let res = ble.poll().unwrap();
+
+    assert_matches!(
+        res,
+        Some(PollResult::Event(EventType::LeMeta(
+            LeMetaEvent::LongTermKeyRequest {
+                handle: 0x0040,
+                random_number: [1, 2, 3, 4, 5, 6, 7, 8],
+                encrypted_diversifier: 0x1234,
+            }
+        )))
+    );
+}
+
+#[test]
+fn receiving_unknown_le_meta_event_works() {
+    let connector = connector();
+    let mut ble = Ble::new(&connector);
+
+    connector.provide_data_to_read(&[0x04, 0x3e, 0x03, 0x7f, 0x01, 0x02]);
+
+    let res = ble.poll().unwrap();
+
+    assert_matches!(
+        res,
+        Some(PollResult::Event(EventType::LeMeta(LeMetaEvent::Unknown {
+            subevent_code: 0x7f,
+            data
+        }))) if data.to_slice() == [0x01, 0x02]
+    );
+
+    // truncated connection complete
+    connector.reset();
+    connector.provide_data_to_read(&[0x04, 0x3e, 0x03, 0x01, 0x00, 0x40]);
+
+    let res = ble.poll();
+
+    assert_matches!(
+        res,
+        Err(ble_hci::Error::EventParseError(
+            EventParseError::InvalidLength { code: 0x3e, len: 3 }
+        ))
+    );
+}
+
 #[test]
 fn receiving_number_of_completed_packets_works() {
     let connector = connector();
@@ -362,7 +802,7 @@ fn receiving_number_of_completed_packets_works() {
 
     connector.provide_data_to_read(&[0x04, 0x13, 0x05, 0x01, 0x00, 0x00, 0x01, 0x00]);
 
-    let res = ble.poll();
+    let res = ble.poll().unwrap();
 
     assert_matches!(
         res,
@@ -384,7 +824,7 @@ fn receiving_read_by_group_type_works() {
         0x28,
     ]);
 
-    let res = ble.poll();
+    let res = ble.poll().unwrap();
     match res {
         Some(res) => match res {
             PollResult::Event(_) => assert!(true, "Expected async data"),
@@ -410,7 +850,7 @@ fn create_ready_by_group_type_resp_works() {
         AttributeData::new(0x0001, 0x0010, Uuid::Uuid16(0x1801)),
         AttributeData::new(0x0020, 0x0030, Uuid::Uuid16(0x1802)),
     ];
-    let res = att_encode_read_by_group_type_response(&attribute_list);
+    let res = att_encode_read_by_group_type_response(&attribute_list, ATT_DEFAULT_MTU);
 
     assert_matches!(
         res.to_slice(),
@@ -424,7 +864,7 @@ fn create_ready_by_group_type_resp_acl_works() {
         AttributeData::new(0x0001, 0x0010, Uuid::Uuid16(0x1801)),
         AttributeData::new(0x0020, 0x0030, Uuid::Uuid16(0x1802)),
     ];
-    let res = att_encode_read_by_group_type_response(&attribute_list);
+    let res = att_encode_read_by_group_type_response(&attribute_list, ATT_DEFAULT_MTU);
     let res = encode_l2cap(res);
     let res = encode_acl_packet(
         0x0000,
@@ -442,6 +882,32 @@ fn create_ready_by_group_type_resp_acl_works() {
     );
 }
 
+#[test]
+fn create_ready_by_group_type_resp_packs_to_mtu() {
+    let attribute_list = [
+        AttributeData::new(0x0001, 0x0010, Uuid::Uuid16(0x1801)),
+        AttributeData::new(0x0011, 0x0020, Uuid::Uuid16(0x1802)),
+        AttributeData::new(0x0021, 0x0030, Uuid::Uuid16(0x1803)),
+        AttributeData::new(0x0031, 0x0040, Uuid::Uuid16(0x1804)),
+    ];
+    let res = att_encode_read_by_group_type_response(&attribute_list, ATT_DEFAULT_MTU);
+
+    // only three entries fit into 23 bytes
+    assert_eq!(res.len, 20);
+
+    let attribute_list = [
+        AttributeData::new(0x0001, 0x0010, Uuid::Uuid16(0x1801)),
+        AttributeData::new(0x0011, 0x0020, Uuid::Uuid128([0u8; 16])),
+    ];
+    let res = att_encode_read_by_group_type_response(&attribute_list, ATT_DEFAULT_MTU);
+
+    // entries with a different UUID size are left out
+    assert_matches!(
+        res.to_slice(),
+        &[0x11, 0x06, 0x01, 0x00, 0x10, 0x00, 0x01, 0x18]
+    );
+}
+
 #[test]
 fn create_error_resp_works() {
     let res = att_encode_error_response(
@@ -463,7 +929,7 @@ fn receiving_read_by_type_works() {
         0x28,
     ]);
 
-    let res = ble.poll();
+    let res = ble.poll().unwrap();
     match res {
         Some(res) => match res {
             PollResult::Event(_) => assert!(true, "Expected async data"),
@@ -489,7 +955,7 @@ fn create_read_by_type_resp_works() {
         0x0002,
         Data::new(&[1u8, 2u8, 3u8, 4u8]),
     )];
-    let res = att_encode_read_by_type_response(&attribute_list);
+    let res = att_encode_read_by_type_response(&attribute_list, ATT_DEFAULT_MTU);
 
     assert_matches!(
         res.to_slice(),
@@ -498,15 +964,68 @@ fn create_read_by_type_resp_works() {
 }
 
 #[test]
-fn receiving_read_works() {
-    let connector = connector();
-    let mut ble = Ble::new(&connector);
+fn create_read_by_type_resp_packs_to_mtu() {
+    let attribute_list = [
+        AttributePayloadData::new(0x0002, Data::new(&[1u8, 2u8, 3u8, 4u8])),
+        AttributePayloadData::new(0x0004, Data::new(&[5u8, 6u8, 7u8, 8u8])),
+        AttributePayloadData::new(0x0006, Data::new(&[9u8])),
+    ];
+    let res = att_encode_read_by_type_response(&attribute_list, ATT_DEFAULT_MTU);
 
-    connector.provide_data_to_read(&[
-        0x02, 0x00, 0x20, 0x07, 0x00, 0x03, 0x00, 0x04, 0x00, 0x0a, 0x03, 0x00,
-    ]);
+    // the third entry has a different length
+    assert_matches!(
+        res.to_slice(),
+        &[0x09, 0x06, 0x02, 0x00, 0x01, 0x02, 0x03, 0x04, 0x04, 0x00, 0x05, 0x06, 0x07, 0x08]
+    );
 
-    let res = ble.poll();
+    let attribute_list = [AttributePayloadData::new(0x0002, Data::new(&[0xaa; 30]))];
+    let res = att_encode_read_by_type_response(&attribute_list, ATT_DEFAULT_MTU);
+
+    // a single long entry is truncated
+    assert_eq!(res.len, 23);
+    assert_eq!(res.to_slice()[1], 21);
+}
+
+#[test]
+fn receiving_read_by_type_with_128_bit_uuid_works() {
+    let connector = connector();
+    let mut ble = Ble::new(&connector);
+
+    connector.provide_data_to_read(&[
+        0x02, 0x00, 0x20, 0x19, 0x00, 0x15, 0x00, 0x04, 0x00, 0x08, 0x01, 0x00, 0xff, 0xff, 0xa8,
+        0x6a, 0x62, 0xf1, 0x5d, 0x26, 0x45, 0x38, 0xb3, 0x64, 0x56, 0x54, 0x96, 0x15, 0x15, 0xc9,
+    ]);
+
+    let res = ble.poll().unwrap();
+    match res {
+        Some(PollResult::AsyncData(res)) => {
+            let res = parse_att(parse_l2cap(res).unwrap());
+            assert_matches!(
+                res,
+                Ok(Att::ReadByTypeReq {
+                    start: 0x0001,
+                    end: 0xffff,
+                    attribute_type: Uuid::Uuid128([
+                        0xc9, 0x15, 0x15, 0x96, 0x54, 0x56, 0x64, 0xb3, 0x38, 0x45, 0x26, 0x5d,
+                        0xf1, 0x62, 0x6a, 0xa8,
+                    ]),
+                })
+            )
+        }
+        _ => panic!("Expected async data"),
+    }
+}
+
+#[test]
+fn receiving_read_works() {
+    let connector = connector();
+    let mut ble = Ble::new(&connector);
+
+    connector.provide_data_to_read(&[
+        0x02, 0x00, 0x20, 0x07, 0x00, 0x03, 0x00, 0x04, 0x00, 0x0a, 0x03, 0x00,
+    ]);
+
+    let res = ble.poll().unwrap();
     match res {
         Some(res) => match res {
             PollResult::Event(_) => assert!(true, "Expected async data"),
@@ -521,263 +1040,3922 @@ fn receiving_read_works() {
 
 #[test]
 fn create_read_resp_works() {
-    let res = att_encode_read_response(&Data::new(&[0x01, 0x02, 0x03, 0x04]));
+    let res = att_encode_read_response(&Data::new(&[0x01, 0x02, 0x03, 0x04]), ATT_DEFAULT_MTU);
 
     assert_matches!(res.to_slice(), &[0x0b, 0x01, 0x02, 0x03, 0x04,]);
 }
 
 #[test]
-fn receiving_write_works() {
+fn create_read_resp_truncates_to_mtu() {
+    let res = att_encode_read_response(&Data::new(&[0xaa; 30]), ATT_DEFAULT_MTU);
+
+    assert_eq!(res.len, 23);
+    assert_eq!(res.to_slice()[0], 0x0b);
+}
+
+#[test]
+fn receiving_read_blob_works() {
     let connector = connector();
     let mut ble = Ble::new(&connector);
 
     connector.provide_data_to_read(&[
-        0x02, 0x00, 0x20, 0x08, 0x00, 0x04, 0x00, 0x04, 0x00, 0x12, 0x03, 0x00, 0x0ff,
+        0x02, 0x00, 0x20, 0x09, 0x00, 0x05, 0x00, 0x04, 0x00, 0x0c, 0x03, 0x00, 0x16, 0x00,
     ]);
 
-    let res = ble.poll();
+    let res = ble.poll().unwrap();
     match res {
-        Some(res) => match res {
-            PollResult::Event(_) => assert!(true, "Expected async data"),
-            PollResult::AsyncData(res) => {
-                let res = parse_att(parse_l2cap(res).unwrap());
-                assert_matches!(
-                    res,
-                    Ok(Att::WriteReq {
-                        handle: 0x03,
-                        data
-                    }) if data.to_slice() == &[0xff]
-                )
-            }
-        },
-        None => assert!(true, "Expected result"),
+        Some(PollResult::AsyncData(res)) => {
+            let res = parse_att(parse_l2cap(res).unwrap());
+            assert_matches!(
+                res,
+                Ok(Att::ReadBlobReq {
+                    handle: 0x0003,
+                    offset: 0x0016
+                })
+            )
+        }
+        _ => panic!("Expected async data"),
     }
 }
 
 #[test]
-fn create_write_resp_works() {
-    let res = att_encode_write_response();
+fn create_read_blob_resp_works() {
+    let res = att_encode_read_blob_response(&Data::new(&[0x01, 0x02, 0x03]), ATT_DEFAULT_MTU);
 
-    assert_matches!(res.to_slice(), &[0x13]);
+    assert_matches!(res.to_slice(), &[0x0d, 0x01, 0x02, 0x03]);
+
+    let res = att_encode_read_blob_response(&Data::new(&[0xaa; 30]), ATT_DEFAULT_MTU);
+
+    assert_eq!(res.len, 23);
+    assert_eq!(res.to_slice()[0], 0x0d);
 }
 
 #[test]
-fn create_advertising_data_works() {
-    let res = create_advertising_data(&[
-        AdStructure::Flags(LE_GENERAL_DISCOVERABLE | BR_EDR_NOT_SUPPORTED),
-        AdStructure::ServiceUuids16(&[Uuid::Uuid16(0x1809)]),
-        AdStructure::CompleteLocalName("BL-602 Ble-Example!"),
+fn receiving_read_multiple_works() {
+    let connector = connector();
+    let mut ble = Ble::new(&connector);
+
+    connector.provide_data_to_read(&[
+        0x02, 0x00, 0x20, 0x09, 0x00, 0x05, 0x00, 0x04, 0x00, 0x0e, 0x03, 0x00, 0x05, 0x00,
     ]);
 
-    println!("{:x?}", res);
+    let res = ble.poll().unwrap();
+    match res {
+        Some(PollResult::AsyncData(res)) => {
+            let res = parse_att(parse_l2cap(res).unwrap());
+            assert_matches!(
+                res,
+                Ok(Att::ReadMultipleReq { handles }) if handles.to_slice() == [0x0003, 0x0005]
+            )
+        }
+        _ => panic!("Expected async data"),
+    }
+}
+
+#[test]
+fn receiving_read_multiple_variable_works() {
+    let connector = connector();
+    let mut ble = Ble::new(&connector);
+
+    connector.provide_data_to_read(&[
+        0x02, 0x00, 0x20, 0x0b, 0x00, 0x07, 0x00, 0x04, 0x00, 0x20, 0x03, 0x00, 0x05, 0x00, 0x07,
+        0x00,
+    ]);
+
+    let res = ble.poll().unwrap();
+    match res {
+        Some(PollResult::AsyncData(res)) => {
+            let res = parse_att(parse_l2cap(res).unwrap());
+            assert_matches!(
+                res,
+                Ok(Att::ReadMultipleVariableReq { handles })
+                    if handles.to_slice() == [0x0003, 0x0005, 0x0007]
+            )
+        }
+        _ => panic!("Expected async data"),
+    }
+}
+
+#[test]
+fn create_read_multiple_resp_works() {
+    let values = [Data::new(&[0x01, 0x02]), Data::new(&[0x03])];
+    let res = att_encode_read_multiple_response(values.iter().copied(), ATT_DEFAULT_MTU);
+
+    assert_matches!(res.to_slice(), &[0x0f, 0x01, 0x02, 0x03]);
+
+    let values = [Data::new(&[0xaa; 20]), Data::new(&[0xbb; 20])];
+    let res = att_encode_read_multiple_response(values.iter().copied(), ATT_DEFAULT_MTU);
+
+    assert_eq!(res.len, 23);
+    assert_eq!(&res.to_slice()[21..], &[0xbb, 0xbb]);
+}
+
+#[test]
+fn create_read_multiple_variable_resp_works() {
+    let values = [Data::new(&[0x01, 0x02]), Data::new(&[0x03])];
+    let res = att_encode_read_multiple_variable_response(values.iter().copied(), ATT_DEFAULT_MTU);
 
     assert_matches!(
         res.to_slice(),
-        &[
-            0x1c, 0x02, 0x01, 0x06, 0x03, 0x02, 0x09, 0x18, 0x14, 0x09, 0x42, 0x4C, 0x2D, 0x36,
-            0x30, 0x32, 0x20, 0x42, 0x6C, 0x65, 0x2D, 0x45, 0x78, 0x61, 0x6D, 0x70, 0x6C, 0x65,
-            0x21, 0x00, 0x00, 0x00
-        ]
+        &[0x21, 0x02, 0x00, 0x01, 0x02, 0x01, 0x00, 0x03]
     );
+
+    let values = [Data::new(&[0xaa; 10]), Data::new(&[0xbb; 10])];
+    let res = att_encode_read_multiple_variable_response(values.iter().copied(), ATT_DEFAULT_MTU);
+
+    assert_eq!(res.len, 23);
+    assert_eq!(&res.to_slice()[11..16], &[0xaa, 0xaa, 0x0a, 0x00, 0xbb]);
 }
 
 #[test]
-fn attribute_server_replies_to_group_type_requests() {
-    let mut written = Vec::<u8>::new();
+fn receiving_exchange_mtu_works() {
+    let connector = connector();
+    let mut ble = Ble::new(&connector);
+
+    connector.provide_data_to_read(&[
+        0x02, 0x00, 0x20, 0x07, 0x00, 0x03, 0x00, 0x04, 0x00, 0x02, 0xf7, 0x00,
+    ]);
+
+    let res = ble.poll().unwrap();
+    match res {
+        Some(PollResult::AsyncData(res)) => {
+            let res = parse_att(parse_l2cap(res).unwrap());
+            assert_matches!(res, Ok(Att::ExchangeMtuReq { mtu: 0x00f7 }))
+        }
+        _ => panic!("Expected async data"),
+    }
+}
+
+#[test]
+fn create_exchange_mtu_resp_works() {
+    let res = att_encode_exchange_mtu_response(0x0040);
+
+    assert_matches!(res.to_slice(), &[0x03, 0x40, 0x00]);
+}
 
+#[test]
+fn receiving_find_information_works() {
     let connector = connector();
     let mut ble = Ble::new(&connector);
 
-    let mut rf = || Data::new(&[b'H', b'e', b'l', b'l', b'o']);
-    let mut wf = |data: Data| {
-        written.extend_from_slice(data.to_slice());
+    connector.provide_data_to_read(&[
+        0x02, 0x00, 0x20, 0x09, 0x00, 0x05, 0x00, 0x04, 0x00, 0x04, 0x04, 0x00, 0xff, 0xff,
+    ]);
+
+    let res = ble.poll().unwrap();
+    match res {
+        Some(PollResult::AsyncData(res)) => {
+            let res = parse_att(parse_l2cap(res).unwrap());
+            assert_matches!(
+                res,
+                Ok(Att::FindInformationReq {
+                    start: 0x0004,
+                    end: 0xffff
+                })
+            )
+        }
+        _ => panic!("Expected async data"),
+    }
+}
+
+#[test]
+fn create_find_information_resp_works() {
+    let information_list = [
+        AttributeInformation::new(0x0003, Uuid::Uuid16(0x2a37)),
+        AttributeInformation::new(0x0004, Uuid::Uuid16(0x2902)),
+        AttributeInformation::new(0x0005, Uuid::Uuid128([0u8; 16])),
+    ];
+    let res = att_encode_find_information_response(&information_list, ATT_DEFAULT_MTU);
+
+    assert_matches!(
+        res.to_slice(),
+        &[0x05, 0x01, 0x03, 0x00, 0x37, 0x2a, 0x04, 0x00, 0x02, 0x29]
+    );
+
+    let res = att_encode_find_information_response(&information_list[2..], ATT_DEFAULT_MTU);
+
+    assert_matches!(
+        res.to_slice(),
+        &[0x05, 0x02, 0x05, 0x00, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0]
+    );
+}
+
+#[test]
+fn receiving_find_by_type_value_works() {
+    let connector = connector();
+    let mut ble = Ble::new(&connector);
+
+    connector.provide_data_to_read(&[
+        0x02, 0x00, 0x20, 0x0d, 0x00, 0x09, 0x00, 0x04, 0x00, 0x06, 0x01, 0x00, 0xff, 0xff, 0x00,
+        0x28, 0x0f, 0x18,
+    ]);
+
+    let res = ble.poll().unwrap();
+    match res {
+        Some(PollResult::AsyncData(res)) => {
+            let res = parse_att(parse_l2cap(res).unwrap());
+            assert_matches!(
+                res,
+                Ok(Att::FindByTypeValueReq {
+                    start: 0x0001,
+                    end: 0xffff,
+                    att_type: Uuid::Uuid16(0x2800),
+                    value,
+                }) if value.to_slice() == [0x0f, 0x18]
+            )
+        }
+        _ => panic!("Expected async data"),
+    }
+}
+
+#[test]
+fn create_find_by_type_value_resp_works() {
+    let handles_list = [
+        HandlesInformation::new(0x0001, 0x0005),
+        HandlesInformation::new(0x0010, 0x0012),
+    ];
+    let res = att_encode_find_by_type_value_response(&handles_list, ATT_DEFAULT_MTU);
+
+    assert_matches!(
+        res.to_slice(),
+        &[0x07, 0x01, 0x00, 0x05, 0x00, 0x10, 0x00, 0x12, 0x00]
+    );
+}
+
+#[test]
+fn create_discovery_requests_works() {
+    assert_eq!(
+        att_encode_exchange_mtu_request(0x0040).to_slice(),
+        &[0x02, 0x40, 0x00]
+    );
+    assert_eq!(
+        att_encode_find_information_request(0x0004, 0xffff).to_slice(),
+        &[0x04, 0x04, 0x00, 0xff, 0xff]
+    );
+    assert_eq!(
+        att_encode_find_by_type_value_request(0x0001, 0xffff, 0x2800, &Data::new(&[0x0f, 0x18]))
+            .to_slice(),
+        &[0x06, 0x01, 0x00, 0xff, 0xff, 0x00, 0x28, 0x0f, 0x18]
+    );
+    assert_eq!(
+        att_encode_read_by_group_type_request(0x0001, 0xffff, Uuid::Uuid16(0x2800)).to_slice(),
+        &[0x10, 0x01, 0x00, 0xff, 0xff, 0x00, 0x28]
+    );
+    assert_eq!(
+        att_encode_read_by_type_request(0x0001, 0x0005, Uuid::Uuid16(0x2803)).to_slice(),
+        &[0x08, 0x01, 0x00, 0x05, 0x00, 0x03, 0x28]
+    );
+    assert_eq!(
+        att_encode_read_request(0x0003).to_slice(),
+        &[0x0a, 0x03, 0x00]
+    );
+}
+
+#[test]
+fn receiving_discovery_responses_works() {
+    let connector = connector();
+    let mut ble = Ble::new(&connector);
+
+    // ErrorRsp { request_opcode: 0x10, handle: 0x000a, code: AttributeNotFound }
+    connector.provide_data_to_read(&[
+        0x02, 0x00, 0x20, 0x09, 0x00, 0x05, 0x00, 0x04, 0x00, 0x01, 0x10, 0x0a, 0x00, 0x0a,
+    ]);
+    // ReadByGroupTypeRsp { 1-5 0x180f, 6-9 0x180d }
+    connector.provide_data_to_read(&[
+        0x02, 0x00, 0x20, 0x12, 0x00, 0x0e, 0x00, 0x04, 0x00, 0x11, 0x06, 0x01, 0x00, 0x05, 0x00,
+        0x0f, 0x18, 0x06, 0x00, 0x09, 0x00, 0x0d, 0x18,
+    ]);
+    // FindInformationRsp with a 128-bit UUID
+    connector.provide_data_to_read(&[
+        0x02, 0x00, 0x20, 0x18, 0x00, 0x14, 0x00, 0x04, 0x00, 0x05, 0x02, 0x05, 0x00, 0x00, 0x01,
+        0x02, 0x03, 0x04, 0x05, 0x06, 0x07, 0x08, 0x09, 0x0a, 0x0b, 0x0c, 0x0d, 0x0e, 0x0f,
+    ]);
+    // ReadByTypeRsp with an entry length not matching the payload
+    connector.provide_data_to_read(&[
+        0x02, 0x00, 0x20, 0x0a, 0x00, 0x06, 0x00, 0x04, 0x00, 0x09, 0x07, 0x02, 0x00, 0x02, 0x03,
+    ]);
+
+    let mut next = || match ble.poll().unwrap() {
+        Some(PollResult::AsyncData(res)) => parse_att(parse_l2cap(res).unwrap()),
+        _ => panic!("Expected async data"),
     };
 
-    let srv = Service::new(
-        Uuid::Uuid128([
-            0xC9, 0x15, 0x15, 0x96, 0x54, 0x56, 0x64, 0xB3, 0x38, 0x45, 0x26, 0x5D, 0xF1, 0x62,
-            0x6A, 0xA8,
-        ]),
-        ATT_READABLE | ATT_WRITEABLE,
-        &mut rf,
-        &mut wf,
+    assert_matches!(
+        next(),
+        Ok(Att::ErrorRsp {
+            request_opcode: 0x10,
+            handle: 0x000a,
+            code: AttErrorCode::AttributeNotFound,
+        })
+    );
+
+    let res = next();
+    assert_matches!(res, Ok(Att::ReadByGroupTypeRsp { .. }));
+    if let Ok(Att::ReadByGroupTypeRsp { attribute_list }) = res {
+        assert_eq!(attribute_list.entry_len(), 6);
+        assert_eq!(
+            attribute_list.entries().collect::<Vec<&[u8]>>(),
+            [
+                &[0x01, 0x00, 0x05, 0x00, 0x0f, 0x18],
+                &[0x06, 0x00, 0x09, 0x00, 0x0d, 0x18]
+            ]
+        );
+    }
+
+    let res = next();
+    assert_matches!(res, Ok(Att::FindInformationRsp { .. }));
+    if let Ok(Att::FindInformationRsp { information }) = res {
+        assert_eq!(information.entry_len(), 18);
+        assert_eq!(information.entries().count(), 1);
+    }
+
+    assert_matches!(next(), Err(AttParseError::UnexpectedPayload));
+}
+
+#[test]
+fn create_client_requests_works() {
+    assert_eq!(
+        att_encode_read_blob_request(0x0003, 0x0016).to_slice(),
+        &[0x0c, 0x03, 0x00, 0x16, 0x00]
+    );
+    assert_eq!(
+        att_encode_write_request(0x0003, &Data::new(&[0x01, 0x02]), ATT_DEFAULT_MTU).to_slice(),
+        &[0x12, 0x03, 0x00, 0x01, 0x02]
+    );
+    assert_eq!(
+        att_encode_write_command(0x0003, &Data::new(&[0x01, 0x02]), ATT_DEFAULT_MTU).to_slice(),
+        &[0x52, 0x03, 0x00, 0x01, 0x02]
+    );
+    assert_eq!(
+        att_encode_prepare_write_request(0x0003, 0x0012, &Data::new(&[0x01]), ATT_DEFAULT_MTU)
+            .to_slice(),
+        &[0x16, 0x03, 0x00, 0x12, 0x00, 0x01]
+    );
+    assert_eq!(
+        att_encode_execute_write_request(0x01).to_slice(),
+        &[0x18, 0x01]
+    );
+    assert_eq!(att_encode_handle_value_confirmation().to_slice(), &[0x1e]);
+
+    // values are truncated to the MTU
+    let res = att_encode_write_request(0x0003, &Data::new(&[0xaa; 30]), ATT_DEFAULT_MTU);
+    assert_eq!(res.len, ATT_DEFAULT_MTU as usize);
+    let res = att_encode_prepare_write_request(0x0003, 0, &Data::new(&[0xaa; 30]), ATT_DEFAULT_MTU);
+    assert_eq!(res.len, ATT_DEFAULT_MTU as usize);
+}
+
+#[test]
+fn receiving_client_responses_works() {
+    let connector = connector();
+    let mut ble = Ble::new(&connector);
+
+    // PrepareWriteRsp { handle: 3, offset: 0x12, value: [0x01] }
+    connector.provide_data_to_read(&[
+        0x02, 0x00, 0x20, 0x0a, 0x00, 0x06, 0x00, 0x04, 0x00, 0x17, 0x03, 0x00, 0x12, 0x00, 0x01,
+    ]);
+    // HandleValueNotification { handle: 3, value: [0x61] }
+    connector.provide_data_to_read(&[
+        0x02, 0x00, 0x20, 0x08, 0x00, 0x04, 0x00, 0x04, 0x00, 0x1b, 0x03, 0x00, 0x61,
+    ]);
+    // HandleValueIndication { handle: 3, value: [0x62] }
+    connector.provide_data_to_read(&[
+        0x02, 0x00, 0x20, 0x08, 0x00, 0x04, 0x00, 0x04, 0x00, 0x1d, 0x03, 0x00, 0x62,
+    ]);
+    // WriteRsp
+    connector.provide_data_to_read(&[0x02, 0x00, 0x20, 0x05, 0x00, 0x01, 0x00, 0x04, 0x00, 0x13]);
+
+    let mut next = || match ble.poll().unwrap() {
+        Some(PollResult::AsyncData(res)) => parse_att(parse_l2cap(res).unwrap()),
+        _ => panic!("Expected async data"),
+    };
+
+    assert_matches!(
+        next(),
+        Ok(Att::PrepareWriteRsp {
+            handle: 0x0003,
+            offset: 0x0012,
+            value,
+        }) if value.to_slice() == [0x01]
+    );
+    assert_matches!(
+        next(),
+        Ok(Att::HandleValueNotification {
+            handle: 0x0003,
+            value,
+        }) if value.to_slice() == [0x61]
+    );
+    assert_matches!(
+        next(),
+        Ok(Att::HandleValueIndication {
+            handle: 0x0003,
+            value,
+        }) if value.to_slice() == [0x62]
+    );
+    assert_matches!(next(), Ok(Att::WriteRsp));
+}
+
+#[test]
+fn uuid_128_bit_form_works() {
+    assert_eq!(
+        Uuid::Uuid16(0x180f).to_uuid128(),
+        [
+            0x00, 0x00, 0x18, 0x0f, 0x00, 0x00, 0x10, 0x00, 0x80, 0x00, 0x00, 0x80, 0x5f, 0x9b,
+            0x34, 0xfb
+        ]
+    );
+    assert_eq!(
+        Uuid::from_le_bytes(&[0x0f, 0x18]),
+        Some(Uuid::Uuid16(0x180f))
+    );
+    assert_eq!(Uuid::from_le_bytes(&[0x0f]), None);
+}
+
+#[test]
+fn uuid_16_bit_form_works() {
+    assert_eq!(Uuid::Uuid16(0x180f).to_uuid16(), Some(0x180f));
+    assert_eq!(
+        Uuid::Uuid128(Uuid::Uuid16(0x2901).to_uuid128()).to_uuid16(),
+        Some(0x2901)
     );
+    assert_eq!(Uuid::Uuid128([0u8; 16]).to_uuid16(), None);
+}
+
+#[test]
+fn receiving_write_works() {
+    let connector = connector();
+    let mut ble = Ble::new(&connector);
+
+    connector.provide_data_to_read(&[
+        0x02, 0x00, 0x20, 0x08, 0x00, 0x04, 0x00, 0x04, 0x00, 0x12, 0x03, 0x00, 0x0ff,
+    ]);
+
+    let res = ble.poll().unwrap();
+    match res {
+        Some(res) => match res {
+            PollResult::Event(_) => assert!(true, "Expected async data"),
+            PollResult::AsyncData(res) => {
+                let res = parse_att(parse_l2cap(res).unwrap());
+                assert_matches!(
+                    res,
+                    Ok(Att::WriteReq {
+                        handle: 0x03,
+                        data
+                    }) if data.to_slice() == &[0xff]
+                )
+            }
+        },
+        None => assert!(true, "Expected result"),
+    }
+}
+
+#[test]
+fn receiving_write_cmd_works() {
+    let connector = connector();
+    let mut ble = Ble::new(&connector);
+
+    connector.provide_data_to_read(&[
+        0x02, 0x00, 0x20, 0x09, 0x00, 0x05, 0x00, 0x04, 0x00, 0x52, 0x03, 0x00, 0xff, 0xee,
+    ]);
+
+    let res = ble.poll().unwrap();
+    match res {
+        Some(PollResult::AsyncData(res)) => {
+            let res = parse_att(parse_l2cap(res).unwrap());
+            assert_matches!(
+                res,
+                Ok(Att::WriteCmd {
+                    handle: 0x0003,
+                    data,
+                }) if data.to_slice() == [0xff, 0xee]
+            )
+        }
+        _ => panic!("Expected async data"),
+    }
+}
+
+#[test]
+fn receiving_signed_write_cmd_works() {
+    let connector = connector();
+    let mut ble = Ble::new(&connector);
+
+    connector.provide_data_to_read(&[
+        0x02, 0x00, 0x20, 0x15, 0x00, 0x11, 0x00, 0x04, 0x00, 0xd2, 0x03, 0x00, 0xff, 0xee, 0x01,
+        0x02, 0x03, 0x04, 0x11, 0x12, 0x13, 0x14, 0x15, 0x16, 0x17, 0x18,
+    ]);
+
+    let res = ble.poll().unwrap();
+    match res {
+        Some(PollResult::AsyncData(res)) => {
+            let res = parse_att(parse_l2cap(res).unwrap());
+            assert_matches!(
+                res,
+                Ok(Att::SignedWriteCmd {
+                    handle: 0x0003,
+                    data,
+                    sign_counter: 0x04030201,
+                    signature: [0x11, 0x12, 0x13, 0x14, 0x15, 0x16, 0x17, 0x18],
+                }) if data.to_slice() == [0xff, 0xee]
+            )
+        }
+        _ => panic!("Expected async data"),
+    }
+}
+
+#[test]
+fn receiving_prepare_write_works() {
+    let connector = connector();
+    let mut ble = Ble::new(&connector);
+
+    connector.provide_data_to_read(&[
+        0x02, 0x00, 0x20, 0x0c, 0x00, 0x08, 0x00, 0x04, 0x00, 0x16, 0x03, 0x00, 0x16, 0x00, 0xff,
+        0x00, 0xee,
+    ]);
+
+    let res = ble.poll().unwrap();
+    match res {
+        Some(PollResult::AsyncData(res)) => {
+            let res = parse_att(parse_l2cap(res).unwrap());
+            assert_matches!(
+                res,
+                Ok(Att::PrepareWriteReq {
+                    handle: 0x0003,
+                    offset: 0x0016,
+                    value,
+                }) if value.to_slice() == [0xff, 0x00, 0xee]
+            )
+        }
+        _ => panic!("Expected async data"),
+    }
+}
+
+#[test]
+fn receiving_execute_write_works() {
+    let connector = connector();
+    let mut ble = Ble::new(&connector);
+
+    connector.provide_data_to_read(&[
+        0x02, 0x00, 0x20, 0x06, 0x00, 0x02, 0x00, 0x04, 0x00, 0x18, 0x01,
+    ]);
+
+    let res = ble.poll().unwrap();
+    match res {
+        Some(PollResult::AsyncData(res)) => {
+            let res = parse_att(parse_l2cap(res).unwrap());
+            assert_matches!(res, Ok(Att::ExecuteWriteReq { flags: 0x01 }))
+        }
+        _ => panic!("Expected async data"),
+    }
+}
+
+#[test]
+fn create_prepare_write_resp_works() {
+    let res = att_encode_prepare_write_response(0x0003, 0x0016, &Data::new(&[0xff, 0x00, 0xee]));
+
+    assert_matches!(
+        res.to_slice(),
+        &[0x17, 0x03, 0x00, 0x16, 0x00, 0xff, 0x00, 0xee]
+    );
+}
+
+#[test]
+fn create_execute_write_resp_works() {
+    let res = att_encode_execute_write_response();
+
+    assert_matches!(res.to_slice(), &[0x19]);
+}
+
+#[test]
+fn create_write_resp_works() {
+    let res = att_encode_write_response();
+
+    assert_matches!(res.to_slice(), &[0x13]);
+}
+
+#[test]
+fn create_handle_value_notification_works() {
+    let res =
+        att_encode_handle_value_notification(0x0003, &Data::new(&[0x01, 0x02]), ATT_DEFAULT_MTU);
+
+    assert_matches!(res.to_slice(), &[0x1b, 0x03, 0x00, 0x01, 0x02]);
+}
+
+#[test]
+fn create_handle_value_indication_works() {
+    let res =
+        att_encode_handle_value_indication(0x0003, &Data::new(&[0x01, 0x02]), ATT_DEFAULT_MTU);
+
+    assert_matches!(res.to_slice(), &[0x1d, 0x03, 0x00, 0x01, 0x02]);
+}
+
+#[test]
+fn create_advertising_data_works() {
+    let res = create_advertising_data(&[
+        AdStructure::Flags(LE_GENERAL_DISCOVERABLE | BR_EDR_NOT_SUPPORTED),
+        AdStructure::ServiceUuids16(&[Uuid::Uuid16(0x1809)]),
+        AdStructure::CompleteLocalName("BL-602 Ble-Example!"),
+    ]);
+
+    println!("{:x?}", res);
+
+    assert_matches!(
+        res.to_slice(),
+        &[
+            0x1c, 0x02, 0x01, 0x06, 0x03, 0x02, 0x09, 0x18, 0x14, 0x09, 0x42, 0x4C, 0x2D, 0x36,
+            0x30, 0x32, 0x20, 0x42, 0x6C, 0x65, 0x2D, 0x45, 0x78, 0x61, 0x6D, 0x70, 0x6C, 0x65,
+            0x21, 0x00, 0x00, 0x00
+        ]
+    );
+}
+
+#[test]
+fn attribute_server_replies_to_group_type_requests() {
+    let mut written = Vec::<u8>::new();
+
+    let connector = connector();
+    let mut ble = Ble::new(&connector);
+
+    let mut rf = |_| Ok(Data::new(b"Hello"));
+    let mut wf = |_, data: Data| {
+        written.extend_from_slice(data.to_slice());
+        Ok(())
+    };
+
+    let characteristics = &mut [Characteristic::new(
+        Uuid::Uuid128([
+            0xC9, 0x15, 0x15, 0x96, 0x54, 0x56, 0x64, 0xB3, 0x38, 0x45, 0x26, 0x5D, 0xF1, 0x62,
+            0x6A, 0xA8,
+        ]),
+        ATT_READABLE | ATT_WRITEABLE,
+        &mut rf,
+        &mut wf,
+    )];
+    let srv = Service::new(Uuid::Uuid16(0x1809), characteristics);
+
+    let services = &mut [srv];
+    let mut srv = AttributeServer::new(&mut ble, services);
+
+    // ReadByGroupTypeReq { start: 1, end: ffff, group_type: Uuid16(2800) }
+    connector.provide_data_to_read(&[
+        0x02, 0x00, 0x20, 0x0b, 0x00, 0x07, 0x00, 0x04, 0x00, 0x10, 0x01, 0x00, 0xff, 0xff, 0x00,
+        0x28,
+    ]);
+    assert_matches!(srv.do_work(), Ok(()));
+    // check response (1-3, 0x1809)
+    let response_data = connector.get_written_data();
+    assert_eq!(
+        response_data.to_slice(),
+        &[
+            0x02, 0x00, 0x20, 0x0c, 0x00, 0x08, 0x00, 0x04, 0x00, 0x11, 0x06, 0x01, 0x00, 0x03,
+            0x00, 0x09, 0x18
+        ]
+    );
+
+    // ReadByGroupTypeReq { start: 3, end: ffff, group_type: Uuid16(2800) }
+    connector.reset();
+    connector.provide_data_to_read(&[
+        0x02, 0x00, 0x20, 0x0b, 0x00, 0x07, 0x00, 0x04, 0x00, 0x10, 0x03, 0x00, 0xff, 0xff, 0x00,
+        0x28,
+    ]);
+    assert_matches!(srv.do_work(), Ok(()));
+    // check response (not found)
+    let response_data = connector.get_written_data();
+    assert_eq!(
+        response_data.to_slice(),
+        &[0x02, 0x00, 0x20, 0x09, 0x00, 0x05, 0x00, 0x04, 0x00, 0x01, 0x10, 0x03, 0x00, 0x0a]
+    );
+
+    // ReadByTypeReq { start: 1, end: 3, attribute_type: Uuid16(2802) }
+    connector.reset();
+    connector.provide_data_to_read(&[
+        0x02, 0x00, 0x20, 0x0b, 0x00, 0x07, 0x00, 0x04, 0x00, 0x08, 0x01, 0x00, 0x02, 0x00, 0x02,
+        0x28,
+    ]);
+    assert_matches!(srv.do_work(), Ok(()));
+    // check response (not found)
+    let response_data = connector.get_written_data();
+    assert_eq!(
+        response_data.to_slice(),
+        &[0x02, 0x00, 0x20, 0x09, 0x00, 0x05, 0x00, 0x04, 0x00, 0x01, 0x08, 0x01, 0x00, 0x0a]
+    );
+
+    // ReadByTypeReq { start: 1, end: 3, attribute_type: Uuid16(2803) }
+    connector.reset();
+    connector.provide_data_to_read(&[
+        0x02, 0x00, 0x20, 0x0b, 0x00, 0x07, 0x00, 0x04, 0x00, 0x08, 0x01, 0x00, 0x03, 0x00, 0x03,
+        0x28,
+    ]);
+    assert_matches!(srv.do_work(), Ok(()));
+    // check response (not found)
+    let response_data = connector.get_written_data();
+    assert_eq!(
+        response_data.to_slice(),
+        &[
+            0x02, 0x00, 0x20, 0x1b, 0x00, 0x17, 0x0, 0x4, 0x0, 0x9, 0x15, 0x2, 0x0, 0xa, 0x3, 0x0,
+            0xa8, 0x6a, 0x62, 0xf1, 0x5d, 0x26, 0x45, 0x38, 0xb3, 0x64, 0x56, 0x54, 0x96, 0x15,
+            0x15, 0xc9,
+        ]
+    );
+
+    // ReadReq { handle: 3 }
+    connector.reset();
+    connector.provide_data_to_read(&[
+        0x02, 0x00, 0x20, 0x07, 0x00, 0x03, 0x00, 0x04, 0x00, 0x0a, 0x03, 0x00,
+    ]);
+    assert_matches!(srv.do_work(), Ok(()));
+    // check response (read resp 'Hello')
+    let response_data = connector.get_written_data();
+    assert_eq!(
+        response_data.to_slice(),
+        &[
+            0x02, 0x00, 0x20, 0x0a, 0x00, 0x06, 0x00, 0x04, 0x00, 0x0b, 0x48, 0x65, 0x6c, 0x6c,
+            0x6f,
+        ]
+    );
+
+    // WriteReq { handle: 3, data: [0xab] }
+    connector.reset();
+    connector.provide_data_to_read(&[
+        0x02, 0x00, 0x20, 0x08, 0x00, 0x04, 0x00, 0x04, 0x00, 0x12, 0x03, 0x00, 0xab,
+    ]);
+    assert_matches!(srv.do_work(), Ok(()));
+    // check response (write resp)
+    let response_data = connector.get_written_data();
+    assert_eq!(
+        response_data.to_slice(),
+        &[0x02, 0x00, 0x20, 0x05, 0x00, 0x01, 0x00, 0x04, 0x00, 0x13]
+    );
+
+    assert_eq!(&written[..], &[0xab_u8]);
+}
+
+#[test]
+fn attribute_server_discover_two_services() {
+    let connector = connector();
+    let mut ble = Ble::new(&connector);
+
+    let mut rf1 = |_| Ok(Data::default());
+    let mut wf1 = |_, _data: Data| Ok(());
+
+    let characteristics1 = &mut [Characteristic::new(
+        Uuid::Uuid128([
+            0xC9, 0x15, 0x15, 0x96, 0x54, 0x56, 0x64, 0xB3, 0x38, 0x45, 0x26, 0x5D, 0xF1, 0x62,
+            0x6A, 0xA8,
+        ]),
+        ATT_READABLE | ATT_WRITEABLE,
+        &mut rf1,
+        &mut wf1,
+    )];
+    let srv1 = Service::new(Uuid::Uuid16(0x1809), characteristics1);
+
+    let mut rf2 = |_| Ok(Data::default());
+    let mut wf2 = |_, _data: Data| Ok(());
+
+    let characteristics2 = &mut [Characteristic::new(
+        Uuid::Uuid128([
+            0xC8, 0x15, 0x15, 0x96, 0x54, 0x56, 0x64, 0xB3, 0x38, 0x45, 0x26, 0x5D, 0xF1, 0x62,
+            0x6A, 0xA8,
+        ]),
+        ATT_READABLE | ATT_WRITEABLE,
+        &mut rf2,
+        &mut wf2,
+    )];
+    let srv2 = Service::new(Uuid::Uuid16(0x180f), characteristics2);
+
+    let services = &mut [srv1, srv2];
+    let mut srv = AttributeServer::new(&mut ble, services);
+
+    // ReadByGroupTypeReq { start: 1, end: ffff, group_type: Uuid16(2800) }
+    connector.provide_data_to_read(&[
+        0x02, 0x00, 0x20, 0x0b, 0x00, 0x07, 0x00, 0x04, 0x00, 0x10, 0x01, 0x00, 0xff, 0xff, 0x00,
+        0x28,
+    ]);
+    assert_matches!(srv.do_work(), Ok(()));
+    // check response (1-3, 0x1809 and 4-6, 0x180f)
+    let response_data = connector.get_written_data();
+    assert_eq!(
+        response_data.to_slice(),
+        &[
+            0x02, 0x00, 0x20, 0x12, 0x00, 0x0e, 0x00, 0x04, 0x00, 0x11, 0x06, 0x01, 0x00, 0x03,
+            0x00, 0x09, 0x18, 0x04, 0x00, 0x06, 0x00, 0x0f, 0x18
+        ]
+    );
+
+    // ReadByGroupTypeReq { start: 4, end: ffff, group_type: Uuid16(2800) }
+    connector.reset();
+    connector.provide_data_to_read(&[
+        0x02, 0x00, 0x20, 0x0b, 0x00, 0x07, 0x00, 0x04, 0x00, 0x10, 0x04, 0x00, 0xff, 0xff, 0x00,
+        0x28,
+    ]);
+    assert_matches!(srv.do_work(), Ok(()));
+    // check response (4-6, 0x180f)
+    let response_data = connector.get_written_data();
+    assert_eq!(
+        response_data.to_slice(),
+        &[
+            0x02, 0x00, 0x20, 0x0c, 0x00, 0x08, 0x00, 0x04, 0x00, 0x11, 0x06, 0x04, 0x00, 0x06,
+            0x00, 0x0f, 0x18
+        ]
+    );
+
+    // ReadByGroupTypeReq { start: 7, end: ffff, group_type: Uuid16(2800) }
+    connector.reset();
+    connector.provide_data_to_read(&[
+        0x02, 0x00, 0x20, 0x0b, 0x00, 0x07, 0x00, 0x04, 0x00, 0x10, 0x07, 0x00, 0xff, 0xff, 0x00,
+        0x28,
+    ]);
+    assert_matches!(srv.do_work(), Ok(()));
+    // check response (not found)
+    let response_data = connector.get_written_data();
+    assert_eq!(
+        response_data.to_slice(),
+        &[0x02, 0x00, 0x20, 0x09, 0x00, 0x05, 0x00, 0x04, 0x00, 0x01, 0x10, 0x07, 0x00, 0x0a]
+    );
+}
+
+#[test]
+fn attribute_server_replies_on_connection_handle() {
+    let connector = connector();
+    let mut ble = Ble::new(&connector);
+
+    let mut rf = |_| Ok(Data::new(b"Hi"));
+    let mut wf = |_, _data: Data| Ok(());
+
+    let characteristics = &mut [Characteristic::new(
+        Uuid::Uuid16(0x2a00),
+        ATT_READABLE | ATT_WRITEABLE,
+        &mut rf,
+        &mut wf,
+    )];
+    let srv = Service::new(Uuid::Uuid16(0x1800), characteristics);
+
+    let services = &mut [srv];
+    let mut srv = AttributeServer::new(&mut ble, services);
+
+    // LE Connection Complete { handle: 0x0040 }
+    connector.provide_data_to_read(&[
+        0x04, 0x3e, 0x13, 0x01, 0x00, 0x40, 0x00, 0x01, 0x01, 0x11, 0x22, 0x33, 0x44, 0x55, 0x66,
+        0x18, 0x00, 0x00, 0x00, 0x48, 0x00, 0x05,
+    ]);
+    assert_matches!(srv.do_work(), Ok(()));
+    assert_eq!(srv.connection_handles().collect::<Vec<u16>>(), [0x0040]);
+
+    // ReadReq { handle: 3 } on connection 0x0040
+    connector.reset();
+    connector.provide_data_to_read(&[
+        0x02, 0x40, 0x20, 0x07, 0x00, 0x03, 0x00, 0x04, 0x00, 0x0a, 0x03, 0x00,
+    ]);
+    assert_matches!(srv.do_work(), Ok(()));
+    // check response (read resp 'Hi' on connection 0x0040)
+    let response_data = connector.get_written_data();
+    assert_eq!(
+        response_data.to_slice(),
+        &[0x02, 0x40, 0x20, 0x07, 0x00, 0x03, 0x00, 0x04, 0x00, 0x0b, 0x48, 0x69]
+    );
+
+    // Disconnection Complete { handle: 0x0040 }
+    connector.reset();
+    connector.provide_data_to_read(&[0x04, 0x05, 0x04, 0x00, 0x40, 0x00, 0x13]);
+    assert_matches!(srv.do_work(), Ok(()));
+    assert_eq!(srv.connection_handles().count(), 0);
+}
+
+#[test]
+fn attribute_server_discover_multiple_characteristics() {
+    let mut written = Vec::<u8>::new();
+
+    let connector = connector();
+    let mut ble = Ble::new(&connector);
+
+    let mut rf1 = |_| Ok(Data::new(b"one"));
+    let mut wf1 = |_, _data: Data| Ok(());
+    let mut rf2 = |_| Ok(Data::new(b"two"));
+    let mut wf2 = |_, data: Data| {
+        written.extend_from_slice(data.to_slice());
+        Ok(())
+    };
+
+    let characteristics = &mut [
+        Characteristic::new(Uuid::Uuid16(0x2a19), ATT_READABLE, &mut rf1, &mut wf1),
+        Characteristic::new(
+            Uuid::Uuid16(0x2a1a),
+            ATT_READABLE | ATT_WRITEABLE,
+            &mut rf2,
+            &mut wf2,
+        ),
+    ];
+    let services = &mut [Service::new(Uuid::Uuid16(0x180f), characteristics)];
+    assert_eq!(services[0].characteristics[0].value_handle(), 0);
+
+    let mut srv = AttributeServer::new(&mut ble, services);
+
+    // ReadByGroupTypeReq { start: 1, end: ffff, group_type: Uuid16(2800) }
+    connector.provide_data_to_read(&[
+        0x02, 0x00, 0x20, 0x0b, 0x00, 0x07, 0x00, 0x04, 0x00, 0x10, 0x01, 0x00, 0xff, 0xff, 0x00,
+        0x28,
+    ]);
+    assert_matches!(srv.do_work(), Ok(()));
+    // check response (1-5, 0x180f)
+    let response_data = connector.get_written_data();
+    assert_eq!(
+        response_data.to_slice(),
+        &[
+            0x02, 0x00, 0x20, 0x0c, 0x00, 0x08, 0x00, 0x04, 0x00, 0x11, 0x06, 0x01, 0x00, 0x05,
+            0x00, 0x0f, 0x18
+        ]
+    );
+
+    // ReadByTypeReq { start: 1, end: 5, attribute_type: Uuid16(2803) }
+    connector.reset();
+    connector.provide_data_to_read(&[
+        0x02, 0x00, 0x20, 0x0b, 0x00, 0x07, 0x00, 0x04, 0x00, 0x08, 0x01, 0x00, 0x05, 0x00, 0x03,
+        0x28,
+    ]);
+    assert_matches!(srv.do_work(), Ok(()));
+    // check response (declaration 2 -> value 3, 0x2a19 and declaration 4 -> value 5, 0x2a1a)
+    let response_data = connector.get_written_data();
+    assert_eq!(
+        response_data.to_slice(),
+        &[
+            0x02, 0x00, 0x20, 0x14, 0x00, 0x10, 0x00, 0x04, 0x00, 0x09, 0x07, 0x02, 0x00, 0x02,
+            0x03, 0x00, 0x19, 0x2a, 0x04, 0x00, 0x0a, 0x05, 0x00, 0x1a, 0x2a
+        ]
+    );
+
+    // ReadByTypeReq { start: 3, end: 5, attribute_type: Uuid16(2803) }
+    connector.reset();
+    connector.provide_data_to_read(&[
+        0x02, 0x00, 0x20, 0x0b, 0x00, 0x07, 0x00, 0x04, 0x00, 0x08, 0x03, 0x00, 0x05, 0x00, 0x03,
+        0x28,
+    ]);
+    assert_matches!(srv.do_work(), Ok(()));
+    // check response (declaration 4 -> value 5, 0x2a1a)
+    let response_data = connector.get_written_data();
+    assert_eq!(
+        response_data.to_slice(),
+        &[
+            0x02, 0x00, 0x20, 0x0d, 0x00, 0x09, 0x00, 0x04, 0x00, 0x09, 0x07, 0x04, 0x00, 0x0a,
+            0x05, 0x00, 0x1a, 0x2a
+        ]
+    );
+
+    // ReadReq { handle: 5 }
+    connector.reset();
+    connector.provide_data_to_read(&[
+        0x02, 0x00, 0x20, 0x07, 0x00, 0x03, 0x00, 0x04, 0x00, 0x0a, 0x05, 0x00,
+    ]);
+    assert_matches!(srv.do_work(), Ok(()));
+    // check response (read resp 'two')
+    let response_data = connector.get_written_data();
+    assert_eq!(
+        response_data.to_slice(),
+        &[0x02, 0x00, 0x20, 0x08, 0x00, 0x04, 0x00, 0x04, 0x00, 0x0b, b't', b'w', b'o']
+    );
+
+    // WriteReq { handle: 5, data: [0xab] }
+    connector.reset();
+    connector.provide_data_to_read(&[
+        0x02, 0x00, 0x20, 0x08, 0x00, 0x04, 0x00, 0x04, 0x00, 0x12, 0x05, 0x00, 0xab,
+    ]);
+    assert_matches!(srv.do_work(), Ok(()));
+
+    assert_eq!(&written[..], &[0xab_u8]);
+}
+
+#[test]
+fn attribute_server_notifies_subscribed_clients() {
+    let connector = connector();
+    let mut ble = Ble::new(&connector);
+
+    let mut rf = |_| Ok(Data::default());
+    let mut wf = |_, _data: Data| Ok(());
+
+    let characteristics = &mut [Characteristic::new(
+        Uuid::Uuid16(0x2a37),
+        ATT_READABLE | ATT_NOTIFY,
+        &mut rf,
+        &mut wf,
+    )];
+    let services = &mut [Service::new(Uuid::Uuid16(0x180d), characteristics)];
+    let mut srv = AttributeServer::new(&mut ble, services);
+
+    // ReadByGroupTypeReq { start: 1, end: ffff, group_type: Uuid16(2800) }
+    connector.provide_data_to_read(&[
+        0x02, 0x00, 0x20, 0x0b, 0x00, 0x07, 0x00, 0x04, 0x00, 0x10, 0x01, 0x00, 0xff, 0xff, 0x00,
+        0x28,
+    ]);
+    assert_matches!(srv.do_work(), Ok(()));
+    // check response (1-4 including the CCCD, 0x180d)
+    let response_data = connector.get_written_data();
+    assert_eq!(
+        response_data.to_slice(),
+        &[
+            0x02, 0x00, 0x20, 0x0c, 0x00, 0x08, 0x00, 0x04, 0x00, 0x11, 0x06, 0x01, 0x00, 0x04,
+            0x00, 0x0d, 0x18
+        ]
+    );
+
+    // not subscribed yet
+    connector.reset();
+    assert_matches!(srv.notify(0x0003, &Data::new(&[0x61, 0x62])), Ok(()));
+    assert_eq!(connector.get_write_idx(), 0);
+
+    // WriteReq { handle: 4, data: [0x01, 0x00] }
+    connector.reset();
+    connector.provide_data_to_read(&[
+        0x02, 0x00, 0x20, 0x09, 0x00, 0x05, 0x00, 0x04, 0x00, 0x12, 0x04, 0x00, 0x01, 0x00,
+    ]);
+    assert_matches!(srv.do_work(), Ok(()));
+    // check response (write resp)
+    let response_data = connector.get_written_data();
+    assert_eq!(
+        response_data.to_slice(),
+        &[0x02, 0x00, 0x20, 0x05, 0x00, 0x01, 0x00, 0x04, 0x00, 0x13]
+    );
+
+    // ReadReq { handle: 4 }
+    connector.reset();
+    connector.provide_data_to_read(&[
+        0x02, 0x00, 0x20, 0x07, 0x00, 0x03, 0x00, 0x04, 0x00, 0x0a, 0x04, 0x00,
+    ]);
+    assert_matches!(srv.do_work(), Ok(()));
+    // check response (read resp [0x01, 0x00])
+    let response_data = connector.get_written_data();
+    assert_eq!(
+        response_data.to_slice(),
+        &[0x02, 0x00, 0x20, 0x07, 0x00, 0x03, 0x00, 0x04, 0x00, 0x0b, 0x01, 0x00]
+    );
+
+    connector.reset();
+    assert_matches!(srv.notify(0x0003, &Data::new(&[0x61, 0x62])), Ok(()));
+    let response_data = connector.get_written_data();
+    assert_eq!(
+        response_data.to_slice(),
+        &[0x02, 0x00, 0x20, 0x09, 0x00, 0x05, 0x00, 0x04, 0x00, 0x1b, 0x03, 0x00, 0x61, 0x62]
+    );
+
+    assert_matches!(
+        srv.indicate(0x0003, &Data::new(&[0x61, 0x62])),
+        Err(AttributeServerError::InvalidHandle(0x0003))
+    );
+}
+
+#[test]
+fn attribute_server_waits_for_indication_confirmation() {
+    let connector = connector();
+    let mut ble = Ble::new(&connector);
+
+    let mut rf = |_| Ok(Data::default());
+    let mut wf = |_, _data: Data| Ok(());
+
+    let characteristics = &mut [Characteristic::new(
+        Uuid::Uuid16(0x2a05),
+        ATT_INDICATE,
+        &mut rf,
+        &mut wf,
+    )];
+    let services = &mut [Service::new(Uuid::Uuid16(0x1801), characteristics)];
+    let mut srv = AttributeServer::new(&mut ble, services);
+
+    // WriteReq { handle: 4, data: [0x02, 0x00] }
+    connector.provide_data_to_read(&[
+        0x02, 0x00, 0x20, 0x09, 0x00, 0x05, 0x00, 0x04, 0x00, 0x12, 0x04, 0x00, 0x02, 0x00,
+    ]);
+    assert_matches!(srv.do_work(), Ok(()));
+
+    connector.reset();
+    assert_matches!(srv.indicate(0x0003, &Data::new(&[0x01])), Ok(()));
+    let response_data = connector.get_written_data();
+    assert_eq!(
+        response_data.to_slice(),
+        &[0x02, 0x00, 0x20, 0x08, 0x00, 0x04, 0x00, 0x04, 0x00, 0x1d, 0x03, 0x00, 0x01]
+    );
+    assert!(srv.is_indication_pending());
+
+    connector.reset();
+    assert_matches!(
+        srv.indicate(0x0003, &Data::new(&[0x02])),
+        Err(AttributeServerError::IndicationPending)
+    );
+    assert_eq!(connector.get_write_idx(), 0);
+
+    // HandleValueConfirmation
+    connector.reset();
+    connector.provide_data_to_read(&[0x02, 0x00, 0x20, 0x05, 0x00, 0x01, 0x00, 0x04, 0x00, 0x1e]);
+    assert_matches!(srv.do_work(), Ok(()));
+    assert!(!srv.is_indication_pending());
+
+    connector.reset();
+    assert_matches!(srv.indicate(0x0003, &Data::new(&[0x02])), Ok(()));
+    assert_eq!(connector.get_write_idx(), 13);
+}
+
+#[test]
+fn attribute_server_keeps_state_per_connection() {
+    let connector = connector();
+    let mut ble = Ble::new(&connector);
+
+    let read_on = Cell::new(0xffff_u16);
+    let mut rf = |context: AccessContext| {
+        read_on.set(context.connection_handle);
+        Ok(Data::new(b"Hi"))
+    };
+    let mut wf = |_, _data: Data| Ok(());
+
+    let characteristics = &mut [Characteristic::new(
+        Uuid::Uuid16(0x2a37),
+        ATT_READABLE | ATT_NOTIFY | ATT_INDICATE,
+        &mut rf,
+        &mut wf,
+    )];
+    let services = &mut [Service::new(Uuid::Uuid16(0x180d), characteristics)];
+    let mut srv = AttributeServer::<2>::new_with_max_connections(&mut ble, services, None);
+
+    // WriteReq { handle: 4, data: [0x03, 0x00] } on connection 0
+    connector.provide_data_to_read(&[
+        0x02, 0x00, 0x20, 0x09, 0x00, 0x05, 0x00, 0x04, 0x00, 0x12, 0x04, 0x00, 0x03, 0x00,
+    ]);
+    assert_matches!(srv.do_work(), Ok(()));
+    let response_data = connector.get_written_data();
+    assert_eq!(
+        response_data.to_slice(),
+        &[0x02, 0x00, 0x20, 0x05, 0x00, 0x01, 0x00, 0x04, 0x00, 0x13]
+    );
+
+    // ReadReq { handle: 4 } on connection 1 - not subscribed there
+    connector.reset();
+    connector.provide_data_to_read(&[
+        0x02, 0x01, 0x20, 0x07, 0x00, 0x03, 0x00, 0x04, 0x00, 0x0a, 0x04, 0x00,
+    ]);
+    assert_matches!(srv.do_work(), Ok(()));
+    let response_data = connector.get_written_data();
+    assert_eq!(
+        response_data.to_slice(),
+        &[0x02, 0x01, 0x20, 0x07, 0x00, 0x03, 0x00, 0x04, 0x00, 0x0b, 0x00, 0x00]
+    );
+
+    // ReadReq { handle: 3 } on connection 1, the callback is told the connection
+    connector.reset();
+    connector.provide_data_to_read(&[
+        0x02, 0x01, 0x20, 0x07, 0x00, 0x03, 0x00, 0x04, 0x00, 0x0a, 0x03, 0x00,
+    ]);
+    assert_matches!(srv.do_work(), Ok(()));
+    assert_eq!(read_on.get(), 0x0001);
+    assert_eq!(
+        srv.connection_handles().collect::<Vec<u16>>(),
+        [0x0000, 0x0001]
+    );
+
+    // only connection 0 is notified
+    connector.reset();
+    assert_matches!(srv.notify(0x0003, &Data::new(&[0x61])), Ok(()));
+    let response_data = connector.get_written_data();
+    assert_eq!(
+        response_data.to_slice(),
+        &[0x02, 0x00, 0x20, 0x08, 0x00, 0x04, 0x00, 0x04, 0x00, 0x1b, 0x03, 0x00, 0x61]
+    );
+
+    connector.reset();
+    assert_matches!(
+        srv.notify_connection(0x0001, 0x0003, &Data::new(&[0x61])),
+        Ok(())
+    );
+    assert_eq!(connector.get_write_idx(), 0);
+
+    connector.reset();
+    assert_matches!(
+        srv.indicate_connection(0x0000, 0x0003, &Data::new(&[0x62])),
+        Ok(())
+    );
+    let response_data = connector.get_written_data();
+    assert_eq!(
+        response_data.to_slice(),
+        &[0x02, 0x00, 0x20, 0x08, 0x00, 0x04, 0x00, 0x04, 0x00, 0x1d, 0x03, 0x00, 0x62]
+    );
+    assert_matches!(
+        srv.indicate_connection(0x0000, 0x0003, &Data::new(&[0x62])),
+        Err(AttributeServerError::IndicationPending)
+    );
+    assert_matches!(
+        srv.indicate_connection(0x0001, 0x0003, &Data::new(&[0x62])),
+        Ok(())
+    );
+
+    // LE Connection Complete { handle: 0x0002 } - no slot left
+    connector.reset();
+    connector.provide_data_to_read(&[
+        0x04, 0x3e, 0x13, 0x01, 0x00, 0x02, 0x00, 0x01, 0x01, 0x11, 0x22, 0x33, 0x44, 0x55, 0x66,
+        0x18, 0x00, 0x00, 0x00, 0x48, 0x00, 0x05,
+    ]);
+    assert_matches!(srv.do_work(), Ok(()));
+    assert_eq!(
+        srv.connection_handles().collect::<Vec<u16>>(),
+        [0x0000, 0x0001]
+    );
+
+    // ReadReq { handle: 3 } on connection 2
+    connector.reset();
+    connector.provide_data_to_read(&[
+        0x02, 0x02, 0x20, 0x07, 0x00, 0x03, 0x00, 0x04, 0x00, 0x0a, 0x03, 0x00,
+    ]);
+    assert_matches!(srv.do_work(), Ok(()));
+    // check response (error insufficient resources)
+    let response_data = connector.get_written_data();
+    assert_eq!(
+        response_data.to_slice(),
+        &[0x02, 0x02, 0x20, 0x09, 0x00, 0x05, 0x00, 0x04, 0x00, 0x01, 0x0a, 0x00, 0x00, 0x11]
+    );
+
+    // Disconnection Complete { handle: 0x0000 }
+    connector.reset();
+    connector.provide_data_to_read(&[0x04, 0x05, 0x04, 0x00, 0x00, 0x00, 0x13]);
+    assert_matches!(srv.do_work(), Ok(()));
+    assert_eq!(srv.connection_handles().collect::<Vec<u16>>(), [0x0001]);
+}
+
+#[test]
+fn attribute_server_exchanges_mtu() {
+    let connector = connector();
+    let mut ble = Ble::new(&connector);
+
+    let mut rf = |_| Ok(Data::new(&[0xaa; 40]));
+    let mut wf = |_, _data: Data| Ok(());
+
+    let characteristics = &mut [Characteristic::new(
+        Uuid::Uuid16(0x2a26),
+        ATT_READABLE,
+        &mut rf,
+        &mut wf,
+    )];
+    let services = &mut [Service::new(Uuid::Uuid16(0x180a), characteristics)];
+    let mut srv = AttributeServer::new(&mut ble, services);
+    srv.set_mtu(64);
+
+    // ReadReq { handle: 3 }
+    connector.provide_data_to_read(&[
+        0x02, 0x00, 0x20, 0x07, 0x00, 0x03, 0x00, 0x04, 0x00, 0x0a, 0x03, 0x00,
+    ]);
+    assert_matches!(srv.do_work(), Ok(()));
+    // check response (truncated to the default MTU)
+    let response_data = connector.get_written_data();
+    assert_eq!(response_data.len, 5 + 4 + 23);
+
+    // ExchangeMtuReq { mtu: 0x00f7 }
+    connector.reset();
+    connector.provide_data_to_read(&[
+        0x02, 0x00, 0x20, 0x07, 0x00, 0x03, 0x00, 0x04, 0x00, 0x02, 0xf7, 0x00,
+    ]);
+    assert_matches!(srv.do_work(), Ok(()));
+    // check response (server MTU 64)
+    let response_data = connector.get_written_data();
+    assert_eq!(
+        response_data.to_slice(),
+        &[0x02, 0x00, 0x20, 0x07, 0x00, 0x03, 0x00, 0x04, 0x00, 0x03, 0x40, 0x00]
+    );
+
+    // ReadReq { handle: 3 }
+    connector.reset();
+    connector.provide_data_to_read(&[
+        0x02, 0x00, 0x20, 0x07, 0x00, 0x03, 0x00, 0x04, 0x00, 0x0a, 0x03, 0x00,
+    ]);
+    assert_matches!(srv.do_work(), Ok(()));
+    // check response (complete value)
+    let response_data = connector.get_written_data();
+    assert_eq!(response_data.len, 5 + 4 + 41);
+}
+
+#[test]
+fn attribute_server_discovers_mixed_uuid_sizes() {
+    let connector = connector();
+    let mut ble = Ble::new(&connector);
+
+    let mut rf1 = |_| Ok(Data::default());
+    let mut wf1 = |_, _data: Data| Ok(());
+    let mut rf2 = |_| Ok(Data::default());
+    let mut wf2 = |_, _data: Data| Ok(());
+    let mut rf3 = |_| Ok(Data::default());
+    let mut wf3 = |_, _data: Data| Ok(());
+
+    let characteristics1 = &mut [Characteristic::new(
+        Uuid::Uuid16(0x2a19),
+        ATT_READABLE,
+        &mut rf1,
+        &mut wf1,
+    )];
+    let characteristics2 = &mut [Characteristic::new(
+        Uuid::Uuid16(0x2a1a),
+        ATT_READABLE,
+        &mut rf2,
+        &mut wf2,
+    )];
+    let characteristics3 = &mut [Characteristic::new(
+        Uuid::Uuid16(0x2a1b),
+        ATT_READABLE,
+        &mut rf3,
+        &mut wf3,
+    )];
+    let services = &mut [
+        Service::new(Uuid::Uuid16(0x180f), characteristics1),
+        Service::new(
+            Uuid::Uuid128([
+                0xC9, 0x15, 0x15, 0x96, 0x54, 0x56, 0x64, 0xB3, 0x38, 0x45, 0x26, 0x5D, 0xF1, 0x62,
+                0x6A, 0xA8,
+            ]),
+            characteristics2,
+        ),
+        Service::new(Uuid::Uuid16(0x180a), characteristics3),
+    ];
+    let mut srv = AttributeServer::new(&mut ble, services);
+
+    // ReadByGroupTypeReq { start: 1, end: ffff, group_type: Uuid16(2800) }
+    connector.provide_data_to_read(&[
+        0x02, 0x00, 0x20, 0x0b, 0x00, 0x07, 0x00, 0x04, 0x00, 0x10, 0x01, 0x00, 0xff, 0xff, 0x00,
+        0x28,
+    ]);
+    assert_matches!(srv.do_work(), Ok(()));
+    // check response (1-3, 0x180f)
+    let response_data = connector.get_written_data();
+    assert_eq!(
+        response_data.to_slice(),
+        &[
+            0x02, 0x00, 0x20, 0x0c, 0x00, 0x08, 0x00, 0x04, 0x00, 0x11, 0x06, 0x01, 0x00, 0x03,
+            0x00, 0x0f, 0x18
+        ]
+    );
+
+    // ReadByGroupTypeReq { start: 4, end: ffff, group_type: Uuid16(2800) }
+    connector.reset();
+    connector.provide_data_to_read(&[
+        0x02, 0x00, 0x20, 0x0b, 0x00, 0x07, 0x00, 0x04, 0x00, 0x10, 0x04, 0x00, 0xff, 0xff, 0x00,
+        0x28,
+    ]);
+    assert_matches!(srv.do_work(), Ok(()));
+    // check response (4-6, 128 bit UUID)
+    let response_data = connector.get_written_data();
+    assert_eq!(
+        response_data.to_slice(),
+        &[
+            0x02, 0x00, 0x20, 0x1a, 0x00, 0x16, 0x00, 0x04, 0x00, 0x11, 0x14, 0x04, 0x00, 0x06,
+            0x00, 0xa8, 0x6a, 0x62, 0xf1, 0x5d, 0x26, 0x45, 0x38, 0xb3, 0x64, 0x56, 0x54, 0x96,
+            0x15, 0x15, 0xc9
+        ]
+    );
+
+    // ReadByGroupTypeReq { start: 7, end: ffff, group_type: Uuid16(2800) }
+    connector.reset();
+    connector.provide_data_to_read(&[
+        0x02, 0x00, 0x20, 0x0b, 0x00, 0x07, 0x00, 0x04, 0x00, 0x10, 0x07, 0x00, 0xff, 0xff, 0x00,
+        0x28,
+    ]);
+    assert_matches!(srv.do_work(), Ok(()));
+    // check response (7-9, 0x180a)
+    let response_data = connector.get_written_data();
+    assert_eq!(
+        response_data.to_slice(),
+        &[
+            0x02, 0x00, 0x20, 0x0c, 0x00, 0x08, 0x00, 0x04, 0x00, 0x11, 0x06, 0x07, 0x00, 0x09,
+            0x00, 0x0a, 0x18
+        ]
+    );
+}
+
+#[test]
+fn attribute_server_reads_only_values_sent_by_type() {
+    let connector = connector();
+    let mut ble = Ble::new(&connector);
+
+    let reads = [Cell::new(0), Cell::new(0), Cell::new(0), Cell::new(0)];
+    let mut rf1 = |_| {
+        reads[0].set(reads[0].get() + 1);
+        Ok(Data::new(&[0x01]))
+    };
+    let mut rf2 = |_| {
+        reads[1].set(reads[1].get() + 1);
+        Ok(Data::new(&[0x02]))
+    };
+    let mut rf3 = |_| {
+        reads[2].set(reads[2].get() + 1);
+        Ok(Data::new(&[0x03, 0x03]))
+    };
+    let mut rf4 = |_| {
+        reads[3].set(reads[3].get() + 1);
+        Ok(Data::new(&[0x04]))
+    };
+    let long_reads = [Cell::new(0), Cell::new(0)];
+    let mut rf5 = |_| {
+        long_reads[0].set(long_reads[0].get() + 1);
+        Ok(Data::new(&[0x05; 10]))
+    };
+    let mut rf6 = |_| {
+        long_reads[1].set(long_reads[1].get() + 1);
+        Ok(Data::new(&[0x06; 10]))
+    };
+    let mut wf1 = |_, _data: Data| Ok(());
+    let mut wf2 = |_, _data: Data| Ok(());
+    let mut wf3 = |_, _data: Data| Ok(());
+    let mut wf4 = |_, _data: Data| Ok(());
+    let mut wf5 = |_, _data: Data| Ok(());
+    let mut wf6 = |_, _data: Data| Ok(());
+
+    let characteristics = &mut [
+        Characteristic::new(Uuid::Uuid16(0x2a19), ATT_READABLE, &mut rf1, &mut wf1),
+        Characteristic::new(Uuid::Uuid16(0x2a19), ATT_READABLE, &mut rf2, &mut wf2),
+        Characteristic::new(Uuid::Uuid16(0x2a19), ATT_READABLE, &mut rf3, &mut wf3),
+        Characteristic::new(Uuid::Uuid16(0x2a19), ATT_READABLE, &mut rf4, &mut wf4),
+        Characteristic::new(Uuid::Uuid16(0x2a1a), ATT_READABLE, &mut rf5, &mut wf5),
+        Characteristic::new(Uuid::Uuid16(0x2a1a), ATT_READABLE, &mut rf6, &mut wf6),
+    ];
+    let services = &mut [Service::new(Uuid::Uuid16(0x180f), characteristics)];
+    let mut srv = AttributeServer::new(&mut ble, services);
+
+    // ReadByTypeReq { start: 1, end: ffff, attribute_type: Uuid16(2a19) }
+    connector.provide_data_to_read(&[
+        0x02, 0x00, 0x20, 0x0b, 0x00, 0x07, 0x00, 0x04, 0x00, 0x08, 0x01, 0x00, 0xff, 0xff, 0x19,
+        0x2a,
+    ]);
+    assert_matches!(srv.do_work(), Ok(()));
+    // check response (value 3 -> [0x01] and value 5 -> [0x02])
+    let response_data = connector.get_written_data();
+    assert_eq!(
+        response_data.to_slice(),
+        &[
+            0x02, 0x00, 0x20, 0x0c, 0x00, 0x08, 0x00, 0x04, 0x00, 0x09, 0x03, 0x03, 0x00, 0x01,
+            0x05, 0x00, 0x02
+        ]
+    );
+    // the value of a different length ends the response, nothing after it is read
+    assert_eq!(
+        reads.iter().map(Cell::get).collect::<Vec<_>>(),
+        [1, 1, 1, 0]
+    );
+
+    // ReadByTypeReq { start: 1, end: ffff, attribute_type: Uuid16(2a1a) }
+    connector.reset();
+    connector.provide_data_to_read(&[
+        0x02, 0x00, 0x20, 0x0b, 0x00, 0x07, 0x00, 0x04, 0x00, 0x08, 0x01, 0x00, 0xff, 0xff, 0x1a,
+        0x2a,
+    ]);
+    assert_matches!(srv.do_work(), Ok(()));
+    // check response (value 11 -> [0x05; 10])
+    let response_data = connector.get_written_data();
+    assert_eq!(
+        response_data.to_slice(),
+        &[
+            0x02, 0x00, 0x20, 0x12, 0x00, 0x0e, 0x00, 0x04, 0x00, 0x09, 0x0c, 0x0b, 0x00, 0x05,
+            0x05, 0x05, 0x05, 0x05, 0x05, 0x05, 0x05, 0x05, 0x05
+        ]
+    );
+    // a second entry wouldn't fit into the default MTU, so it isn't read
+    assert_eq!(long_reads.iter().map(Cell::get).collect::<Vec<_>>(), [1, 0]);
+}
+
+#[test]
+fn attribute_server_replies_to_find_information() {
+    let connector = connector();
+    let mut ble = Ble::new(&connector);
+
+    let mut rf1 = |_| Ok(Data::default());
+    let mut wf1 = |_, _data: Data| Ok(());
+    let mut rf2 = |_| Ok(Data::default());
+    let mut wf2 = |_, _data: Data| Ok(());
+
+    let characteristics = &mut [
+        Characteristic::new(
+            Uuid::Uuid16(0x2a37),
+            ATT_READABLE | ATT_NOTIFY,
+            &mut rf1,
+            &mut wf1,
+        ),
+        Characteristic::new(
+            Uuid::Uuid128([
+                0xC9, 0x15, 0x15, 0x96, 0x54, 0x56, 0x64, 0xB3, 0x38, 0x45, 0x26, 0x5D, 0xF1, 0x62,
+                0x6A, 0xA8,
+            ]),
+            ATT_WRITEABLE,
+            &mut rf2,
+            &mut wf2,
+        ),
+    ];
+    let services = &mut [Service::new(Uuid::Uuid16(0x180d), characteristics)];
+    let mut srv = AttributeServer::new(&mut ble, services);
+
+    // FindInformationReq { start: 1, end: ffff }
+    connector.provide_data_to_read(&[
+        0x02, 0x00, 0x20, 0x09, 0x00, 0x05, 0x00, 0x04, 0x00, 0x04, 0x01, 0x00, 0xff, 0xff,
+    ]);
+    assert_matches!(srv.do_work(), Ok(()));
+    // check response (service, declaration, value, CCCD, declaration)
+    let response_data = connector.get_written_data();
+    assert_eq!(
+        response_data.to_slice(),
+        &[
+            0x02, 0x00, 0x20, 0x1a, 0x00, 0x16, 0x00, 0x04, 0x00, 0x05, 0x01, 0x01, 0x00, 0x00,
+            0x28, 0x02, 0x00, 0x03, 0x28, 0x03, 0x00, 0x37, 0x2a, 0x04, 0x00, 0x02, 0x29, 0x05,
+            0x00, 0x03, 0x28
+        ]
+    );
+
+    // FindInformationReq { start: 6, end: ffff }
+    connector.reset();
+    connector.provide_data_to_read(&[
+        0x02, 0x00, 0x20, 0x09, 0x00, 0x05, 0x00, 0x04, 0x00, 0x04, 0x06, 0x00, 0xff, 0xff,
+    ]);
+    assert_matches!(srv.do_work(), Ok(()));
+    // check response (128 bit value)
+    let response_data = connector.get_written_data();
+    assert_eq!(
+        response_data.to_slice(),
+        &[
+            0x02, 0x00, 0x20, 0x18, 0x00, 0x14, 0x00, 0x04, 0x00, 0x05, 0x02, 0x06, 0x00, 0xa8,
+            0x6a, 0x62, 0xf1, 0x5d, 0x26, 0x45, 0x38, 0xb3, 0x64, 0x56, 0x54, 0x96, 0x15, 0x15,
+            0xc9
+        ]
+    );
+
+    // FindInformationReq { start: 7, end: ffff }
+    connector.reset();
+    connector.provide_data_to_read(&[
+        0x02, 0x00, 0x20, 0x09, 0x00, 0x05, 0x00, 0x04, 0x00, 0x04, 0x07, 0x00, 0xff, 0xff,
+    ]);
+    assert_matches!(srv.do_work(), Ok(()));
+    // check response (not found)
+    let response_data = connector.get_written_data();
+    assert_eq!(
+        response_data.to_slice(),
+        &[0x02, 0x00, 0x20, 0x09, 0x00, 0x05, 0x00, 0x04, 0x00, 0x01, 0x04, 0x07, 0x00, 0x0a]
+    );
+
+    // FindInformationReq { start: 0, end: ffff }
+    connector.reset();
+    connector.provide_data_to_read(&[
+        0x02, 0x00, 0x20, 0x09, 0x00, 0x05, 0x00, 0x04, 0x00, 0x04, 0x00, 0x00, 0xff, 0xff,
+    ]);
+    assert_matches!(srv.do_work(), Ok(()));
+    // check response (invalid handle)
+    let response_data = connector.get_written_data();
+    assert_eq!(
+        response_data.to_slice(),
+        &[0x02, 0x00, 0x20, 0x09, 0x00, 0x05, 0x00, 0x04, 0x00, 0x01, 0x04, 0x00, 0x00, 0x01]
+    );
+}
+
+#[test]
+fn attribute_server_replies_to_find_by_type_value() {
+    let connector = connector();
+    let mut ble = Ble::new(&connector);
+
+    let mut rf1 = |_| Ok(Data::default());
+    let mut wf1 = |_, _data: Data| Ok(());
+    let mut rf2 = |_| Ok(Data::default());
+    let mut wf2 = |_, _data: Data| Ok(());
+
+    let characteristics1 = &mut [Characteristic::new(
+        Uuid::Uuid16(0x2a19),
+        ATT_READABLE,
+        &mut rf1,
+        &mut wf1,
+    )];
+    let characteristics2 = &mut [Characteristic::new(
+        Uuid::Uuid16(0x2a1a),
+        ATT_READABLE,
+        &mut rf2,
+        &mut wf2,
+    )];
+    let services = &mut [
+        Service::new(
+            Uuid::Uuid128([
+                0xC9, 0x15, 0x15, 0x96, 0x54, 0x56, 0x64, 0xB3, 0x38, 0x45, 0x26, 0x5D, 0xF1, 0x62,
+                0x6A, 0xA8,
+            ]),
+            characteristics1,
+        ),
+        Service::new(Uuid::Uuid16(0x180f), characteristics2),
+    ];
+    let mut srv = AttributeServer::new(&mut ble, services);
+
+    // FindByTypeValueReq { start: 1, end: ffff, att_type: Uuid16(2800), value: [0x0f, 0x18] }
+    connector.provide_data_to_read(&[
+        0x02, 0x00, 0x20, 0x0d, 0x00, 0x09, 0x00, 0x04, 0x00, 0x06, 0x01, 0x00, 0xff, 0xff, 0x00,
+        0x28, 0x0f, 0x18,
+    ]);
+    assert_matches!(srv.do_work(), Ok(()));
+    // check response (4-6)
+    let response_data = connector.get_written_data();
+    assert_eq!(
+        response_data.to_slice(),
+        &[0x02, 0x00, 0x20, 0x09, 0x00, 0x05, 0x00, 0x04, 0x00, 0x07, 0x04, 0x00, 0x06, 0x00]
+    );
+
+    // FindByTypeValueReq { start: 1, end: ffff, att_type: Uuid16(2800), value: 128 bit form of 0x180f }
+    connector.reset();
+    connector.provide_data_to_read(&[
+        0x02, 0x00, 0x20, 0x1b, 0x00, 0x17, 0x00, 0x04, 0x00, 0x06, 0x01, 0x00, 0xff, 0xff, 0x00,
+        0x28, 0xfb, 0x34, 0x9b, 0x5f, 0x80, 0x00, 0x00, 0x80, 0x00, 0x10, 0x00, 0x00, 0x0f, 0x18,
+        0x00, 0x00,
+    ]);
+    assert_matches!(srv.do_work(), Ok(()));
+    // check response (4-6)
+    let response_data = connector.get_written_data();
+    assert_eq!(
+        response_data.to_slice(),
+        &[0x02, 0x00, 0x20, 0x09, 0x00, 0x05, 0x00, 0x04, 0x00, 0x07, 0x04, 0x00, 0x06, 0x00]
+    );
+
+    // FindByTypeValueReq { start: 1, end: ffff, att_type: Uuid16(2800), value: 128 bit UUID }
+    connector.reset();
+    connector.provide_data_to_read(&[
+        0x02, 0x00, 0x20, 0x1b, 0x00, 0x17, 0x00, 0x04, 0x00, 0x06, 0x01, 0x00, 0xff, 0xff, 0x00,
+        0x28, 0xa8, 0x6a, 0x62, 0xf1, 0x5d, 0x26, 0x45, 0x38, 0xb3, 0x64, 0x56, 0x54, 0x96, 0x15,
+        0x15, 0xc9,
+    ]);
+    assert_matches!(srv.do_work(), Ok(()));
+    // check response (1-3)
+    let response_data = connector.get_written_data();
+    assert_eq!(
+        response_data.to_slice(),
+        &[0x02, 0x00, 0x20, 0x09, 0x00, 0x05, 0x00, 0x04, 0x00, 0x07, 0x01, 0x00, 0x03, 0x00]
+    );
+
+    // FindByTypeValueReq { start: 4, end: ffff, att_type: Uuid16(2800), value: [0x0d, 0x18] }
+    connector.reset();
+    connector.provide_data_to_read(&[
+        0x02, 0x00, 0x20, 0x0d, 0x00, 0x09, 0x00, 0x04, 0x00, 0x06, 0x04, 0x00, 0xff, 0xff, 0x00,
+        0x28, 0x0d, 0x18,
+    ]);
+    assert_matches!(srv.do_work(), Ok(()));
+    // check response (not found)
+    let response_data = connector.get_written_data();
+    assert_eq!(
+        response_data.to_slice(),
+        &[0x02, 0x00, 0x20, 0x09, 0x00, 0x05, 0x00, 0x04, 0x00, 0x01, 0x06, 0x04, 0x00, 0x0a]
+    );
+}
+
+#[test]
+fn attribute_server_reads_long_values() {
+    let connector = connector();
+    let mut ble = Ble::new(&connector);
+
+    let mut long_value = [0u8; 30];
+    for (i, byte) in long_value.iter_mut().enumerate() {
+        *byte = i as u8;
+    }
+    let mut rf1 = |context: AccessContext| match long_value.get(context.offset as usize..) {
+        Some(part) => Ok(Data::new(part)),
+        None => Err(AttErrorCode::InvalidOffset),
+    };
+    let mut wf1 = |_, _data: Data| Ok(());
+    let mut rf2 = |context: AccessContext| match context.offset {
+        0 => Ok(Data::new(&[0x01, 0x02])),
+        _ => Err(AttErrorCode::AttributeNotLong),
+    };
+    let mut wf2 = |_, _data: Data| Ok(());
+
+    let characteristics = &mut [
+        Characteristic::new(Uuid::Uuid16(0x2a26), ATT_READABLE, &mut rf1, &mut wf1),
+        Characteristic::new(Uuid::Uuid16(0x2a19), ATT_READABLE, &mut rf2, &mut wf2),
+    ];
+    let services = &mut [Service::new(Uuid::Uuid16(0x180a), characteristics)];
+    let mut srv = AttributeServer::new(&mut ble, services);
+
+    // ReadReq { handle: 3 }
+    connector.provide_data_to_read(&[
+        0x02, 0x00, 0x20, 0x07, 0x00, 0x03, 0x00, 0x04, 0x00, 0x0a, 0x03, 0x00,
+    ]);
+    assert_matches!(srv.do_work(), Ok(()));
+    // check response (first 22 bytes)
+    let response_data = connector.get_written_data();
+    assert_eq!(
+        &response_data.to_slice()[..10],
+        &[0x02, 0x00, 0x20, 0x1b, 0x00, 0x17, 0x00, 0x04, 0x00, 0x0b]
+    );
+    assert_eq!(&response_data.to_slice()[10..], &long_value[..22]);
+
+    // ReadBlobReq { handle: 3, offset: 22 }
+    connector.reset();
+    connector.provide_data_to_read(&[
+        0x02, 0x00, 0x20, 0x09, 0x00, 0x05, 0x00, 0x04, 0x00, 0x0c, 0x03, 0x00, 0x16, 0x00,
+    ]);
+    assert_matches!(srv.do_work(), Ok(()));
+    // check response (remaining 8 bytes)
+    let response_data = connector.get_written_data();
+    assert_eq!(
+        &response_data.to_slice()[..10],
+        &[0x02, 0x00, 0x20, 0x0d, 0x00, 0x09, 0x00, 0x04, 0x00, 0x0d]
+    );
+    assert_eq!(&response_data.to_slice()[10..], &long_value[22..]);
+
+    // ReadBlobReq { handle: 3, offset: 31 }
+    connector.reset();
+    connector.provide_data_to_read(&[
+        0x02, 0x00, 0x20, 0x09, 0x00, 0x05, 0x00, 0x04, 0x00, 0x0c, 0x03, 0x00, 0x1f, 0x00,
+    ]);
+    assert_matches!(srv.do_work(), Ok(()));
+    // check response (invalid offset)
+    let response_data = connector.get_written_data();
+    assert_eq!(
+        response_data.to_slice(),
+        &[0x02, 0x00, 0x20, 0x09, 0x00, 0x05, 0x00, 0x04, 0x00, 0x01, 0x0c, 0x03, 0x00, 0x07]
+    );
+
+    // ReadBlobReq { handle: 5, offset: 1 }
+    connector.reset();
+    connector.provide_data_to_read(&[
+        0x02, 0x00, 0x20, 0x09, 0x00, 0x05, 0x00, 0x04, 0x00, 0x0c, 0x05, 0x00, 0x01, 0x00,
+    ]);
+    assert_matches!(srv.do_work(), Ok(()));
+    // check response (attribute not long)
+    let response_data = connector.get_written_data();
+    assert_eq!(
+        response_data.to_slice(),
+        &[0x02, 0x00, 0x20, 0x09, 0x00, 0x05, 0x00, 0x04, 0x00, 0x01, 0x0c, 0x05, 0x00, 0x0b]
+    );
+}
+
+#[test]
+fn attribute_server_reads_values_longer_than_data() {
+    let connector = connector();
+    let mut ble = Ble::new(&connector);
+
+    let long_value: Vec<u8> = (0..300).map(|i| i as u8).collect();
+    let mut offsets = Vec::new();
+    let mut rf = |context: AccessContext| {
+        offsets.push(context.offset);
+        match long_value.get(context.offset as usize..) {
+            Some(part) => Ok(Data::new(&part[..core::cmp::min(part.len(), 128)])),
+            None => Err(AttErrorCode::InvalidOffset),
+        }
+    };
+    let mut wf = |_, _data: Data| Ok(());
+
+    let characteristics = &mut [Characteristic::new(
+        Uuid::Uuid16(0x2a26),
+        ATT_READABLE,
+        &mut rf,
+        &mut wf,
+    )];
+    let services = &mut [Service::new(Uuid::Uuid16(0x180a), characteristics)];
+    let mut srv = AttributeServer::new(&mut ble, services);
+
+    // ReadBlobReq { handle: 3, offset: 286 }
+    connector.provide_data_to_read(&[
+        0x02, 0x00, 0x20, 0x09, 0x00, 0x05, 0x00, 0x04, 0x00, 0x0c, 0x03, 0x00, 0x1e, 0x01,
+    ]);
+    assert_matches!(srv.do_work(), Ok(()));
+    // check response (remaining 14 bytes)
+    let response_data = connector.get_written_data();
+    assert_eq!(
+        &response_data.to_slice()[..10],
+        &[0x02, 0x00, 0x20, 0x13, 0x00, 0x0f, 0x00, 0x04, 0x00, 0x0d]
+    );
+    assert_eq!(&response_data.to_slice()[10..], &long_value[286..]);
+
+    // ReadBlobReq { handle: 3, offset: 301 }
+    connector.reset();
+    connector.provide_data_to_read(&[
+        0x02, 0x00, 0x20, 0x09, 0x00, 0x05, 0x00, 0x04, 0x00, 0x0c, 0x03, 0x00, 0x2d, 0x01,
+    ]);
+    assert_matches!(srv.do_work(), Ok(()));
+    // check response (invalid offset)
+    let response_data = connector.get_written_data();
+    assert_eq!(
+        response_data.to_slice(),
+        &[0x02, 0x00, 0x20, 0x09, 0x00, 0x05, 0x00, 0x04, 0x00, 0x01, 0x0c, 0x03, 0x00, 0x07]
+    );
+
+    assert_eq!(offsets, [286, 301]);
+}
+
+#[test]
+fn attribute_server_executes_prepared_writes() {
+    let mut written = Vec::<u8>::new();
+
+    let connector = connector();
+    let mut ble = Ble::new(&connector);
+
+    let mut rf = |_| Ok(Data::default());
+    let mut wf = |_, data: Data| {
+        written.extend_from_slice(data.to_slice());
+        Ok(())
+    };
+
+    let characteristics = &mut [Characteristic::new(
+        Uuid::Uuid16(0x2a00),
+        ATT_WRITEABLE,
+        &mut rf,
+        &mut wf,
+    )];
+    let services = &mut [Service::new(Uuid::Uuid16(0x1800), characteristics)];
+    let mut srv = AttributeServer::new(&mut ble, services);
+
+    // PrepareWriteReq { handle: 3, offset: 0, value: "Hello " }
+    connector.provide_data_to_read(&[
+        0x02, 0x00, 0x20, 0x0f, 0x00, 0x0b, 0x00, 0x04, 0x00, 0x16, 0x03, 0x00, 0x00, 0x00, b'H',
+        b'e', b'l', b'l', b'o', b' ',
+    ]);
+    assert_matches!(srv.do_work(), Ok(()));
+    // check response (echo)
+    let response_data = connector.get_written_data();
+    assert_eq!(
+        response_data.to_slice(),
+        &[
+            0x02, 0x00, 0x20, 0x0f, 0x00, 0x0b, 0x00, 0x04, 0x00, 0x17, 0x03, 0x00, 0x00, 0x00,
+            b'H', b'e', b'l', b'l', b'o', b' '
+        ]
+    );
+
+    // PrepareWriteReq { handle: 3, offset: 6, value: "World" }
+    connector.reset();
+    connector.provide_data_to_read(&[
+        0x02, 0x00, 0x20, 0x0e, 0x00, 0x0a, 0x00, 0x04, 0x00, 0x16, 0x03, 0x00, 0x06, 0x00, b'W',
+        b'o', b'r', b'l', b'd',
+    ]);
+    assert_matches!(srv.do_work(), Ok(()));
+    // check response (echo)
+    let response_data = connector.get_written_data();
+    assert_eq!(
+        response_data.to_slice(),
+        &[
+            0x02, 0x00, 0x20, 0x0e, 0x00, 0x0a, 0x00, 0x04, 0x00, 0x17, 0x03, 0x00, 0x06, 0x00,
+            b'W', b'o', b'r', b'l', b'd'
+        ]
+    );
+
+    // ExecuteWriteReq { flags: 1 }
+    connector.reset();
+    connector.provide_data_to_read(&[
+        0x02, 0x00, 0x20, 0x06, 0x00, 0x02, 0x00, 0x04, 0x00, 0x18, 0x01,
+    ]);
+    assert_matches!(srv.do_work(), Ok(()));
+    // check response
+    let response_data = connector.get_written_data();
+    assert_eq!(
+        response_data.to_slice(),
+        &[0x02, 0x00, 0x20, 0x05, 0x00, 0x01, 0x00, 0x04, 0x00, 0x19]
+    );
+
+    // PrepareWriteReq { handle: 3, offset: 0, value: "X" }
+    connector.reset();
+    connector.provide_data_to_read(&[
+        0x02, 0x00, 0x20, 0x0a, 0x00, 0x06, 0x00, 0x04, 0x00, 0x16, 0x03, 0x00, 0x00, 0x00, b'X',
+    ]);
+    assert_matches!(srv.do_work(), Ok(()));
+
+    // ExecuteWriteReq { flags: 0 }
+    connector.reset();
+    connector.provide_data_to_read(&[
+        0x02, 0x00, 0x20, 0x06, 0x00, 0x02, 0x00, 0x04, 0x00, 0x18, 0x00,
+    ]);
+    assert_matches!(srv.do_work(), Ok(()));
+    // check response (cancelled)
+    let response_data = connector.get_written_data();
+    assert_eq!(
+        response_data.to_slice(),
+        &[0x02, 0x00, 0x20, 0x05, 0x00, 0x01, 0x00, 0x04, 0x00, 0x19]
+    );
+
+    // PrepareWriteReq { handle: 3, offset: 2, value: "X" }
+    connector.reset();
+    connector.provide_data_to_read(&[
+        0x02, 0x00, 0x20, 0x0a, 0x00, 0x06, 0x00, 0x04, 0x00, 0x16, 0x03, 0x00, 0x02, 0x00, b'X',
+    ]);
+    assert_matches!(srv.do_work(), Ok(()));
+
+    // ExecuteWriteReq { flags: 1 }
+    connector.reset();
+    connector.provide_data_to_read(&[
+        0x02, 0x00, 0x20, 0x06, 0x00, 0x02, 0x00, 0x04, 0x00, 0x18, 0x01,
+    ]);
+    assert_matches!(srv.do_work(), Ok(()));
+    // check response (invalid offset)
+    let response_data = connector.get_written_data();
+    assert_eq!(
+        response_data.to_slice(),
+        &[0x02, 0x00, 0x20, 0x09, 0x00, 0x05, 0x00, 0x04, 0x00, 0x01, 0x18, 0x03, 0x00, 0x07]
+    );
+
+    // fill the prepare queue
+    for _ in 0..8 {
+        connector.reset();
+        connector.provide_data_to_read(&[
+            0x02, 0x00, 0x20, 0x0a, 0x00, 0x06, 0x00, 0x04, 0x00, 0x16, 0x03, 0x00, 0x00, 0x00,
+            b'X',
+        ]);
+        assert_matches!(srv.do_work(), Ok(()));
+    }
+
+    // PrepareWriteReq { handle: 3, offset: 0, value: "X" }
+    connector.reset();
+    connector.provide_data_to_read(&[
+        0x02, 0x00, 0x20, 0x0a, 0x00, 0x06, 0x00, 0x04, 0x00, 0x16, 0x03, 0x00, 0x00, 0x00, b'X',
+    ]);
+    assert_matches!(srv.do_work(), Ok(()));
+    // check response (queue full)
+    let response_data = connector.get_written_data();
+    assert_eq!(
+        response_data.to_slice(),
+        &[0x02, 0x00, 0x20, 0x09, 0x00, 0x05, 0x00, 0x04, 0x00, 0x01, 0x16, 0x03, 0x00, 0x09]
+    );
+
+    assert_eq!(written, b"Hello World");
+}
+
+#[test]
+fn attribute_server_checks_prepared_writes_before_executing() {
+    let mut written = Vec::<u8>::new();
+
+    let connector = connector();
+    let mut ble = Ble::new(&connector);
+
+    let mut rf1 = |_| Ok(Data::default());
+    let mut wf1 = |_, data: Data| {
+        written.extend_from_slice(data.to_slice());
+        Ok(())
+    };
+    let mut rf2 = |_| Ok(Data::default());
+    let mut wf2 = |_, _data: Data| Err(AttErrorCode::Application(0x80));
+    let mut rf3 = |_| Ok(Data::default());
+    let mut wf3 = |_, _data: Data| Ok(());
+
+    let mut characteristic3 =
+        Characteristic::new(Uuid::Uuid16(0x2a02), ATT_WRITEABLE, &mut rf3, &mut wf3);
+    characteristic3.permissions = PERMISSION_WRITE | PERMISSION_WRITE_AUTHENTICATED;
+    let characteristics = &mut [
+        Characteristic::new(Uuid::Uuid16(0x2a00), ATT_WRITEABLE, &mut rf1, &mut wf1),
+        Characteristic::new(Uuid::Uuid16(0x2a01), ATT_WRITEABLE, &mut rf2, &mut wf2),
+        characteristic3,
+    ];
+    let services = &mut [Service::new(Uuid::Uuid16(0x1800), characteristics)];
+    let mut srv = AttributeServer::new(&mut ble, services);
+
+    // PrepareWriteReq { handle: 3, offset: 0, value: "A" }
+    connector.provide_data_to_read(&[
+        0x02, 0x00, 0x20, 0x0a, 0x00, 0x06, 0x00, 0x04, 0x00, 0x16, 0x03, 0x00, 0x00, 0x00, b'A',
+    ]);
+    assert_matches!(srv.do_work(), Ok(()));
+    // PrepareWriteReq { handle: 5, offset: 2, value: "B" }
+    connector.reset();
+    connector.provide_data_to_read(&[
+        0x02, 0x00, 0x20, 0x0a, 0x00, 0x06, 0x00, 0x04, 0x00, 0x16, 0x05, 0x00, 0x02, 0x00, b'B',
+    ]);
+    assert_matches!(srv.do_work(), Ok(()));
+
+    // ExecuteWriteReq { flags: 1 }
+    connector.reset();
+    connector.provide_data_to_read(&[
+        0x02, 0x00, 0x20, 0x06, 0x00, 0x02, 0x00, 0x04, 0x00, 0x18, 0x01,
+    ]);
+    assert_matches!(srv.do_work(), Ok(()));
+    // check response (invalid offset, nothing written)
+    let response_data = connector.get_written_data();
+    assert_eq!(
+        response_data.to_slice(),
+        &[0x02, 0x00, 0x20, 0x09, 0x00, 0x05, 0x00, 0x04, 0x00, 0x01, 0x18, 0x05, 0x00, 0x07]
+    );
+
+    srv.set_security(
+        0x0000,
+        ConnectionSecurity {
+            encrypted: true,
+            authenticated: true,
+            authorized: false,
+            key_size: 16,
+        },
+    );
+
+    // PrepareWriteReq { handle: 3, offset: 0, value: "A" }
+    connector.reset();
+    connector.provide_data_to_read(&[
+        0x02, 0x00, 0x20, 0x0a, 0x00, 0x06, 0x00, 0x04, 0x00, 0x16, 0x03, 0x00, 0x00, 0x00, b'A',
+    ]);
+    assert_matches!(srv.do_work(), Ok(()));
+    // PrepareWriteReq { handle: 7, offset: 0, value: "C" }
+    connector.reset();
+    connector.provide_data_to_read(&[
+        0x02, 0x00, 0x20, 0x0a, 0x00, 0x06, 0x00, 0x04, 0x00, 0x16, 0x07, 0x00, 0x00, 0x00, b'C',
+    ]);
+    assert_matches!(srv.do_work(), Ok(()));
+
+    srv.set_security(
+        0x0000,
+        ConnectionSecurity {
+            encrypted: true,
+            authenticated: false,
+            authorized: false,
+            key_size: 16,
+        },
+    );
+
+    // ExecuteWriteReq { flags: 1 }
+    connector.reset();
+    connector.provide_data_to_read(&[
+        0x02, 0x00, 0x20, 0x06, 0x00, 0x02, 0x00, 0x04, 0x00, 0x18, 0x01,
+    ]);
+    assert_matches!(srv.do_work(), Ok(()));
+    // check response (insufficient authentication, nothing written)
+    let response_data = connector.get_written_data();
+    assert_eq!(
+        response_data.to_slice(),
+        &[0x02, 0x00, 0x20, 0x09, 0x00, 0x05, 0x00, 0x04, 0x00, 0x01, 0x18, 0x07, 0x00, 0x05]
+    );
+
+    // PrepareWriteReq { handle: 3, offset: 0, value: "A" }
+    connector.reset();
+    connector.provide_data_to_read(&[
+        0x02, 0x00, 0x20, 0x0a, 0x00, 0x06, 0x00, 0x04, 0x00, 0x16, 0x03, 0x00, 0x00, 0x00, b'A',
+    ]);
+    assert_matches!(srv.do_work(), Ok(()));
+    // PrepareWriteReq { handle: 5, offset: 0, value: "B" }
+    connector.reset();
+    connector.provide_data_to_read(&[
+        0x02, 0x00, 0x20, 0x0a, 0x00, 0x06, 0x00, 0x04, 0x00, 0x16, 0x05, 0x00, 0x00, 0x00, b'B',
+    ]);
+    assert_matches!(srv.do_work(), Ok(()));
+
+    // ExecuteWriteReq { flags: 1 }
+    connector.reset();
+    connector.provide_data_to_read(&[
+        0x02, 0x00, 0x20, 0x06, 0x00, 0x02, 0x00, 0x04, 0x00, 0x18, 0x01,
+    ]);
+    assert_matches!(srv.do_work(), Ok(()));
+    // check response (error of the second callback, the first value stays written)
+    let response_data = connector.get_written_data();
+    assert_eq!(
+        response_data.to_slice(),
+        &[0x02, 0x00, 0x20, 0x09, 0x00, 0x05, 0x00, 0x04, 0x00, 0x01, 0x18, 0x05, 0x00, 0x80]
+    );
+
+    assert_eq!(written, b"A");
+}
+
+#[test]
+fn aes128_encrypt_works() {
+    // FIPS-197 appendix C.1
+    let key = [
+        0x00, 0x01, 0x02, 0x03, 0x04, 0x05, 0x06, 0x07, 0x08, 0x09, 0x0a, 0x0b, 0x0c, 0x0d, 0x0e,
+        0x0f,
+    ];
+    let plaintext = [
+        0x00, 0x11, 0x22, 0x33, 0x44, 0x55, 0x66, 0x77, 0x88, 0x99, 0xaa, 0xbb, 0xcc, 0xdd, 0xee,
+        0xff,
+    ];
+
+    assert_eq!(
+        aes128_encrypt(&key, &plaintext),
+        [
+            0x69, 0xc4, 0xe0, 0xd8, 0x6a, 0x7b, 0x04, 0x30, 0xd8, 0xcd, 0xb7, 0x80, 0x70, 0xb4,
+            0xc5, 0x5a
+        ]
+    );
+}
+
+#[test]
+fn aes_cmac_works() {
+    // RFC 4493 section 4
+    let key = [
+        0x2b, 0x7e, 0x15, 0x16, 0x28, 0xae, 0xd2, 0xa6, 0xab, 0xf7, 0x15, 0x88, 0x09, 0xcf, 0x4f,
+        0x3c,
+    ];
+    let message = [
+        0x6b, 0xc1, 0xbe, 0xe2, 0x2e, 0x40, 0x9f, 0x96, 0xe9, 0x3d, 0x7e, 0x11, 0x73, 0x93, 0x17,
+        0x2a, 0xae, 0x2d, 0x8a, 0x57, 0x1e, 0x03, 0xac, 0x9c, 0x9e, 0xb7, 0x6f, 0xac, 0x45, 0xaf,
+        0x8e, 0x51, 0x30, 0xc8, 0x1c, 0x46, 0xa3, 0x5c, 0xe4, 0x11, 0xe5, 0xfb, 0xc1, 0x19, 0x1a,
+        0x0a, 0x52, 0xef, 0xf6, 0x9f, 0x24, 0x45, 0xdf, 0x4f, 0x9b, 0x17, 0xad, 0x2b, 0x41, 0x7b,
+        0xe6, 0x6c, 0x37, 0x10,
+    ];
+
+    assert_eq!(
+        aes_cmac(&key, &[]),
+        [
+            0xbb, 0x1d, 0x69, 0x29, 0xe9, 0x59, 0x37, 0x28, 0x7f, 0xa3, 0x7d, 0x12, 0x9b, 0x75,
+            0x67, 0x46
+        ]
+    );
+    assert_eq!(
+        aes_cmac(&key, &message[..16]),
+        [
+            0x07, 0x0a, 0x16, 0xb4, 0x6b, 0x4d, 0x41, 0x44, 0xf7, 0x9b, 0xdd, 0x9d, 0xd0, 0x4a,
+            0x28, 0x7c
+        ]
+    );
+    assert_eq!(
+        aes_cmac(&key, &message[..40]),
+        [
+            0xdf, 0xa6, 0x67, 0x47, 0xde, 0x9a, 0xe6, 0x30, 0x30, 0xca, 0x32, 0x61, 0x14, 0x97,
+            0xc8, 0x27
+        ]
+    );
+    assert_eq!(
+        aes_cmac(&key, &message),
+        [
+            0x51, 0xf0, 0xbe, 0xbf, 0x7e, 0x3b, 0x9d, 0x92, 0xfc, 0x49, 0x74, 0x17, 0x79, 0x36,
+            0x3c, 0xfe
+        ]
+    );
+
+    // the same message passed in parts
+    let mut cmac = AesCmac::new(&key);
+    for part in message[..40].chunks(7) {
+        cmac.update(part);
+    }
+    assert_eq!(cmac.finalize(), aes_cmac(&key, &message[..40]));
+}
+
+#[test]
+fn attribute_server_handles_write_commands() {
+    let mut written = Vec::<u8>::new();
+
+    let connector = connector();
+    let mut ble = Ble::new(&connector);
+
+    let mut rf1 = |_| Ok(Data::default());
+    let mut wf1 = |_, data: Data| {
+        written.extend_from_slice(data.to_slice());
+        Ok(())
+    };
+    let mut rf2 = |_| Ok(Data::default());
+    let mut wf2 = |_, _data: Data| panic!("not writable without response");
+
+    let characteristics = &mut [
+        Characteristic::new(
+            Uuid::Uuid16(0x2a00),
+            ATT_WRITE_WITHOUT_RESPONSE,
+            &mut rf1,
+            &mut wf1,
+        ),
+        Characteristic::new(Uuid::Uuid16(0x2a01), ATT_WRITEABLE, &mut rf2, &mut wf2),
+    ];
+    let services = &mut [Service::new(Uuid::Uuid16(0x1800), characteristics)];
+    let mut srv = AttributeServer::new(&mut ble, services);
+
+    // WriteCmd { handle: 3, data: [0xff, 0xee] }
+    connector.provide_data_to_read(&[
+        0x02, 0x00, 0x20, 0x09, 0x00, 0x05, 0x00, 0x04, 0x00, 0x52, 0x03, 0x00, 0xff, 0xee,
+    ]);
+    assert_matches!(srv.do_work(), Ok(()));
+    // no response
+    assert_eq!(connector.get_written_data().len, 0);
+
+    // WriteCmd { handle: 5, data: [0xff, 0xee] }
+    connector.reset();
+    connector.provide_data_to_read(&[
+        0x02, 0x00, 0x20, 0x09, 0x00, 0x05, 0x00, 0x04, 0x00, 0x52, 0x05, 0x00, 0xff, 0xee,
+    ]);
+    assert_matches!(srv.do_work(), Ok(()));
+    // no response
+    assert_eq!(connector.get_written_data().len, 0);
+
+    assert_eq!(written, [0xff, 0xee]);
+}
+
+/// Builds a Signed Write Command packet as the client would send it.
+fn signed_write_packet(csrk: &[u8; 16], handle: u16, value: &[u8], sign_counter: u32) -> Data {
+    let mut pdu = Data::new(&[0xd2]);
+    pdu.append(&handle.to_le_bytes());
+    pdu.append(value);
+    pdu.append(&sign_counter.to_le_bytes());
+
+    let mut message = pdu;
+    message.data[..message.len].reverse();
+    let mut key = *csrk;
+    key.reverse();
+    let mac = aes_cmac(&key, message.to_slice());
+    let mut signature = [0u8; 8];
+    signature.copy_from_slice(&mac[..8]);
+    signature.reverse();
+    pdu.append(&signature);
+
+    encode_acl_packet(
+        0x0002,
+        BoundaryFlag::FirstAutoFlushable,
+        HostBroadcastFlag::NoBroadcast,
+        encode_l2cap(pdu),
+    )
+}
+
+#[test]
+fn attribute_server_verifies_signed_writes() {
+    let mut written = Vec::<u8>::new();
+
+    let connector = connector();
+    let mut ble = Ble::new(&connector);
+
+    let mut rf = |_| Ok(Data::default());
+    let mut wf = |_, data: Data| {
+        written.extend_from_slice(data.to_slice());
+        Ok(())
+    };
+
+    let characteristics = &mut [Characteristic::new(
+        Uuid::Uuid16(0x2a00),
+        ATT_SIGNED_WRITE,
+        &mut rf,
+        &mut wf,
+    )];
+    let services = &mut [Service::new(Uuid::Uuid16(0x1800), characteristics)];
+    let mut srv = AttributeServer::new(&mut ble, services);
+
+    let csrk = [
+        0x01, 0x23, 0x45, 0x67, 0x89, 0xab, 0xcd, 0xef, 0xfe, 0xdc, 0xba, 0x98, 0x76, 0x54, 0x32,
+        0x10,
+    ];
+    // SignedWriteCmd { handle: 3, value: [0x2a, 0x2b], sign_counter: 5 } signed with the CSRK
+    // above. The signature was calculated independently of this crate, following Core Vol 3
+    // Part H 2.4.5 with the byte order of BlueZ.
+    let signed_write = [
+        0x02, 0x02, 0x20, 0x15, 0x00, 0x11, 0x00, 0x04, 0x00, 0xd2, 0x03, 0x00, 0x2a, 0x2b, 0x05,
+        0x00, 0x00, 0x00, 0x29, 0xf0, 0x6c, 0x39, 0xc2, 0xc5, 0x38, 0x4b,
+    ];
+    // the helper for the rejected packets signs the same way
+    assert_eq!(
+        signed_write_packet(&csrk, 3, &[0x2a, 0x2b], 5).to_slice(),
+        &signed_write
+    );
+
+    // ignored without a key
+    connector.provide_data_to_read(&signed_write);
+    assert_matches!(srv.do_work(), Ok(()));
+
+    srv.set_signing_key(0x0002, csrk);
+
+    // modified value
+    let mut modified = signed_write;
+    modified[12] = 0xff;
+    connector.reset();
+    connector.provide_data_to_read(&modified);
+    assert_matches!(srv.do_work(), Ok(()));
+
+    connector.reset();
+    connector.provide_data_to_read(&signed_write);
+    assert_matches!(srv.do_work(), Ok(()));
+    // no response
+    assert_eq!(connector.get_written_data().len, 0);
+
+    // replayed sign counter
+    connector.reset();
+    connector.provide_data_to_read(&signed_write);
+    assert_matches!(srv.do_work(), Ok(()));
+
+    // lower sign counter
+    connector.reset();
+    connector.provide_data_to_read(signed_write_packet(&csrk, 3, &[0x03], 4).to_slice());
+    assert_matches!(srv.do_work(), Ok(()));
+
+    // wrong key
+    connector.reset();
+    connector.provide_data_to_read(signed_write_packet(&[0u8; 16], 3, &[0x04], 6).to_slice());
+    assert_matches!(srv.do_work(), Ok(()));
+
+    assert_eq!(written, [0x2a, 0x2b]);
+}
+
+#[test]
+fn attribute_server_checks_permissions_of_signed_writes() {
+    let mut written1 = Vec::<u8>::new();
+    let mut written2 = Vec::<u8>::new();
+    let mut written3 = Vec::<u8>::new();
+
+    let connector = connector();
+    let mut ble = Ble::new(&connector);
+
+    let mut rf1 = |_| Ok(Data::default());
+    let mut wf1 = |_, data: Data| {
+        written1.extend_from_slice(data.to_slice());
+        Ok(())
+    };
+    let mut rf2 = |_| Ok(Data::default());
+    let mut wf2 = |_, data: Data| {
+        written2.extend_from_slice(data.to_slice());
+        Ok(())
+    };
+    let mut rf3 = |_| Ok(Data::default());
+    let mut wf3 = |_, data: Data| {
+        written3.extend_from_slice(data.to_slice());
+        Ok(())
+    };
+
+    let mut characteristic1 =
+        Characteristic::new(Uuid::Uuid16(0x2a00), ATT_SIGNED_WRITE, &mut rf1, &mut wf1);
+    characteristic1.permissions = PERMISSION_WRITE | PERMISSION_WRITE_AUTHENTICATED;
+    let mut characteristic2 =
+        Characteristic::new(Uuid::Uuid16(0x2a01), ATT_SIGNED_WRITE, &mut rf2, &mut wf2);
+    characteristic2.permissions = PERMISSION_WRITE | PERMISSION_WRITE_AUTHORIZED;
+    let mut characteristic3 =
+        Characteristic::new(Uuid::Uuid16(0x2a02), ATT_SIGNED_WRITE, &mut rf3, &mut wf3);
+    characteristic3.permissions = PERMISSION_WRITE | PERMISSION_WRITE_ENCRYPTED;
+    characteristic3.min_key_size = 16;
+
+    let characteristics = &mut [characteristic1, characteristic2, characteristic3];
+    let services = &mut [Service::new(Uuid::Uuid16(0x1800), characteristics)];
+    let mut srv = AttributeServer::new(&mut ble, services);
+
+    let csrk = [
+        0x01, 0x23, 0x45, 0x67, 0x89, 0xab, 0xcd, 0xef, 0xfe, 0xdc, 0xba, 0x98, 0x76, 0x54, 0x32,
+        0x10,
+    ];
+
+    // ignored without a key, opens the connection
+    connector.provide_data_to_read(signed_write_packet(&csrk, 3, &[0x01], 1).to_slice());
+    assert_matches!(srv.do_work(), Ok(()));
+
+    srv.set_signing_key(0x0002, csrk);
+
+    // rejected: not authenticated, not authorized, unknown key size
+    connector.reset();
+    connector.provide_data_to_read(signed_write_packet(&csrk, 3, &[0x02], 1).to_slice());
+    connector.provide_data_to_read(signed_write_packet(&csrk, 5, &[0x03], 2).to_slice());
+    connector.provide_data_to_read(signed_write_packet(&csrk, 7, &[0x04], 3).to_slice());
+    for _ in 0..3 {
+        assert_matches!(srv.do_work(), Ok(()));
+    }
+    // no response
+    assert_eq!(connector.get_written_data().len, 0);
+
+    srv.set_security(
+        0x0002,
+        ConnectionSecurity {
+            encrypted: false,
+            authenticated: true,
+            authorized: true,
+            key_size: 16,
+        },
+    );
+
+    connector.reset();
+    connector.provide_data_to_read(signed_write_packet(&csrk, 3, &[0x05], 1).to_slice());
+    connector.provide_data_to_read(signed_write_packet(&csrk, 5, &[0x06], 2).to_slice());
+    connector.provide_data_to_read(signed_write_packet(&csrk, 7, &[0x07], 3).to_slice());
+    for _ in 0..3 {
+        assert_matches!(srv.do_work(), Ok(()));
+    }
+
+    assert_eq!(written1, [0x05]);
+    assert_eq!(written2, [0x06]);
+    assert_eq!(written3, [0x07]);
+}
+
+#[test]
+fn attribute_server_replies_to_read_multiple() {
+    let connector = connector();
+    let mut ble = Ble::new(&connector);
+
+    let mut rf1 = |_| Ok(Data::new(b"ab"));
+    let mut wf1 = |_, _data: Data| Ok(());
+    let mut rf2 = |_| Ok(Data::new(&[0x01]));
+    let mut wf2 = |_, _data: Data| Ok(());
+    let mut rf3 = |_| Ok(Data::new(&[0x02]));
+    let mut wf3 = |_, _data: Data| Ok(());
+
+    let characteristics = &mut [
+        Characteristic::new(Uuid::Uuid16(0x2a6e), ATT_READABLE, &mut rf1, &mut wf1),
+        Characteristic::new(Uuid::Uuid16(0x2a6f), ATT_READABLE, &mut rf2, &mut wf2),
+        Characteristic::new(Uuid::Uuid16(0x2a70), ATT_WRITEABLE, &mut rf3, &mut wf3),
+    ];
+    let services = &mut [Service::new(Uuid::Uuid16(0x181a), characteristics)];
+    let mut srv = AttributeServer::new(&mut ble, services);
+
+    // ReadMultipleReq { handles: [3, 5] }
+    connector.provide_data_to_read(&[
+        0x02, 0x00, 0x20, 0x09, 0x00, 0x05, 0x00, 0x04, 0x00, 0x0e, 0x03, 0x00, 0x05, 0x00,
+    ]);
+    assert_matches!(srv.do_work(), Ok(()));
+    // check response
+    let response_data = connector.get_written_data();
+    assert_eq!(
+        response_data.to_slice(),
+        &[0x02, 0x00, 0x20, 0x08, 0x00, 0x04, 0x00, 0x04, 0x00, 0x0f, b'a', b'b', 0x01]
+    );
+
+    // ReadMultipleVariableReq { handles: [3, 5] }
+    connector.reset();
+    connector.provide_data_to_read(&[
+        0x02, 0x00, 0x20, 0x09, 0x00, 0x05, 0x00, 0x04, 0x00, 0x20, 0x03, 0x00, 0x05, 0x00,
+    ]);
+    assert_matches!(srv.do_work(), Ok(()));
+    // check response
+    let response_data = connector.get_written_data();
+    assert_eq!(
+        response_data.to_slice(),
+        &[
+            0x02, 0x00, 0x20, 0x0c, 0x00, 0x08, 0x00, 0x04, 0x00, 0x21, 0x02, 0x00, b'a', b'b',
+            0x01, 0x00, 0x01
+        ]
+    );
+
+    // ReadMultipleReq { handles: [3, 7] }
+    connector.reset();
+    connector.provide_data_to_read(&[
+        0x02, 0x00, 0x20, 0x09, 0x00, 0x05, 0x00, 0x04, 0x00, 0x0e, 0x03, 0x00, 0x07, 0x00,
+    ]);
+    assert_matches!(srv.do_work(), Ok(()));
+    // check response (read not permitted)
+    let response_data = connector.get_written_data();
+    assert_eq!(
+        response_data.to_slice(),
+        &[0x02, 0x00, 0x20, 0x09, 0x00, 0x05, 0x00, 0x04, 0x00, 0x01, 0x0e, 0x07, 0x00, 0x02]
+    );
+
+    // ReadMultipleVariableReq { handles: [9, 3] }
+    connector.reset();
+    connector.provide_data_to_read(&[
+        0x02, 0x00, 0x20, 0x09, 0x00, 0x05, 0x00, 0x04, 0x00, 0x20, 0x09, 0x00, 0x03, 0x00,
+    ]);
+    assert_matches!(srv.do_work(), Ok(()));
+    // check response (invalid handle)
+    let response_data = connector.get_written_data();
+    assert_eq!(
+        response_data.to_slice(),
+        &[0x02, 0x00, 0x20, 0x09, 0x00, 0x05, 0x00, 0x04, 0x00, 0x01, 0x20, 0x09, 0x00, 0x01]
+    );
+}
+
+#[test]
+fn attribute_server_replies_with_errors() {
+    let connector = connector();
+    let mut ble = Ble::new(&connector);
+
+    let mut rf1 = |_| Ok(Data::new(&[0x01]));
+    let mut wf1 = |_, _data: Data| panic!("not writeable");
+    let mut rf2 = |_| panic!("not readable");
+    let mut wf2 = |_, _data: Data| Ok(());
+
+    let characteristics = &mut [
+        Characteristic::new(Uuid::Uuid16(0x2a19), ATT_READABLE, &mut rf1, &mut wf1),
+        Characteristic::new(Uuid::Uuid16(0x2a1a), ATT_WRITEABLE, &mut rf2, &mut wf2),
+    ];
+    let services = &mut [Service::new(Uuid::Uuid16(0x180f), characteristics)];
+    let mut srv = AttributeServer::new(&mut ble, services);
+
+    // ReadReq { handle: 1 }
+    connector.provide_data_to_read(&[
+        0x02, 0x00, 0x20, 0x07, 0x00, 0x03, 0x00, 0x04, 0x00, 0x0a, 0x01, 0x00,
+    ]);
+    assert_matches!(srv.do_work(), Ok(()));
+    // check response (service declaration)
+    let response_data = connector.get_written_data();
+    assert_eq!(
+        response_data.to_slice(),
+        &[0x02, 0x00, 0x20, 0x07, 0x00, 0x03, 0x00, 0x04, 0x00, 0x0b, 0x0f, 0x18]
+    );
+
+    // ReadReq { handle: 9 }
+    connector.reset();
+    connector.provide_data_to_read(&[
+        0x02, 0x00, 0x20, 0x07, 0x00, 0x03, 0x00, 0x04, 0x00, 0x0a, 0x09, 0x00,
+    ]);
+    assert_matches!(srv.do_work(), Ok(()));
+    // check response (invalid handle)
+    let response_data = connector.get_written_data();
+    assert_eq!(
+        response_data.to_slice(),
+        &[0x02, 0x00, 0x20, 0x09, 0x00, 0x05, 0x00, 0x04, 0x00, 0x01, 0x0a, 0x09, 0x00, 0x01]
+    );
+
+    // ReadReq { handle: 5 }
+    connector.reset();
+    connector.provide_data_to_read(&[
+        0x02, 0x00, 0x20, 0x07, 0x00, 0x03, 0x00, 0x04, 0x00, 0x0a, 0x05, 0x00,
+    ]);
+    assert_matches!(srv.do_work(), Ok(()));
+    // check response (read not permitted)
+    let response_data = connector.get_written_data();
+    assert_eq!(
+        response_data.to_slice(),
+        &[0x02, 0x00, 0x20, 0x09, 0x00, 0x05, 0x00, 0x04, 0x00, 0x01, 0x0a, 0x05, 0x00, 0x02]
+    );
+
+    // WriteReq { handle: 3, data: [0xff] }
+    connector.reset();
+    connector.provide_data_to_read(&[
+        0x02, 0x00, 0x20, 0x08, 0x00, 0x04, 0x00, 0x04, 0x00, 0x12, 0x03, 0x00, 0xff,
+    ]);
+    assert_matches!(srv.do_work(), Ok(()));
+    // check response (write not permitted)
+    let response_data = connector.get_written_data();
+    assert_eq!(
+        response_data.to_slice(),
+        &[0x02, 0x00, 0x20, 0x09, 0x00, 0x05, 0x00, 0x04, 0x00, 0x01, 0x12, 0x03, 0x00, 0x03]
+    );
+
+    // WriteReq { handle: 2, data: [0xff] }
+    connector.reset();
+    connector.provide_data_to_read(&[
+        0x02, 0x00, 0x20, 0x08, 0x00, 0x04, 0x00, 0x04, 0x00, 0x12, 0x02, 0x00, 0xff,
+    ]);
+    assert_matches!(srv.do_work(), Ok(()));
+    // check response (write not permitted)
+    let response_data = connector.get_written_data();
+    assert_eq!(
+        response_data.to_slice(),
+        &[0x02, 0x00, 0x20, 0x09, 0x00, 0x05, 0x00, 0x04, 0x00, 0x01, 0x12, 0x02, 0x00, 0x03]
+    );
+
+    // WriteReq { handle: 9, data: [0xff] }
+    connector.reset();
+    connector.provide_data_to_read(&[
+        0x02, 0x00, 0x20, 0x08, 0x00, 0x04, 0x00, 0x04, 0x00, 0x12, 0x09, 0x00, 0xff,
+    ]);
+    assert_matches!(srv.do_work(), Ok(()));
+    // check response (invalid handle)
+    let response_data = connector.get_written_data();
+    assert_eq!(
+        response_data.to_slice(),
+        &[0x02, 0x00, 0x20, 0x09, 0x00, 0x05, 0x00, 0x04, 0x00, 0x01, 0x12, 0x09, 0x00, 0x01]
+    );
+
+    // ReadByGroupTypeReq { start: 1, end: ffff, group_type: Uuid16(2803) }
+    connector.reset();
+    connector.provide_data_to_read(&[
+        0x02, 0x00, 0x20, 0x0b, 0x00, 0x07, 0x00, 0x04, 0x00, 0x10, 0x01, 0x00, 0xff, 0xff, 0x03,
+        0x28,
+    ]);
+    assert_matches!(srv.do_work(), Ok(()));
+    // check response (unsupported group type)
+    let response_data = connector.get_written_data();
+    assert_eq!(
+        response_data.to_slice(),
+        &[0x02, 0x00, 0x20, 0x09, 0x00, 0x05, 0x00, 0x04, 0x00, 0x01, 0x10, 0x01, 0x00, 0x10]
+    );
+
+    // ReadByTypeReq { start: 5, end: 1, attribute_type: Uuid16(2803) }
+    connector.reset();
+    connector.provide_data_to_read(&[
+        0x02, 0x00, 0x20, 0x0b, 0x00, 0x07, 0x00, 0x04, 0x00, 0x08, 0x05, 0x00, 0x01, 0x00, 0x03,
+        0x28,
+    ]);
+    assert_matches!(srv.do_work(), Ok(()));
+    // check response (invalid handle)
+    let response_data = connector.get_written_data();
+    assert_eq!(
+        response_data.to_slice(),
+        &[0x02, 0x00, 0x20, 0x09, 0x00, 0x05, 0x00, 0x04, 0x00, 0x01, 0x08, 0x05, 0x00, 0x01]
+    );
+}
+
+#[test]
+fn attribute_server_rejects_unsupported_requests() {
+    let connector = connector();
+    let mut ble = Ble::new(&connector);
+
+    let mut rf = |_| Ok(Data::default());
+    let mut wf = |_, _data: Data| Ok(());
+
+    let characteristics = &mut [Characteristic::new(
+        Uuid::Uuid16(0x2a19),
+        ATT_READABLE,
+        &mut rf,
+        &mut wf,
+    )];
+    let services = &mut [Service::new(Uuid::Uuid16(0x180f), characteristics)];
+    let mut srv = AttributeServer::new(&mut ble, services);
+
+    // unknown request opcode 0x14
+    connector.provide_data_to_read(&[
+        0x02, 0x00, 0x20, 0x07, 0x00, 0x03, 0x00, 0x04, 0x00, 0x14, 0x01, 0x00,
+    ]);
+    assert_matches!(srv.do_work(), Ok(()));
+    // check response (request not supported)
+    let response_data = connector.get_written_data();
+    assert_eq!(
+        response_data.to_slice(),
+        &[0x02, 0x00, 0x20, 0x09, 0x00, 0x05, 0x00, 0x04, 0x00, 0x01, 0x14, 0x00, 0x00, 0x06]
+    );
+
+    // ReadReq with a truncated handle
+    connector.reset();
+    connector.provide_data_to_read(&[
+        0x02, 0x00, 0x20, 0x06, 0x00, 0x02, 0x00, 0x04, 0x00, 0x0a, 0x01,
+    ]);
+    assert_matches!(srv.do_work(), Ok(()));
+    // check response (invalid PDU)
+    let response_data = connector.get_written_data();
+    assert_eq!(
+        response_data.to_slice(),
+        &[0x02, 0x00, 0x20, 0x09, 0x00, 0x05, 0x00, 0x04, 0x00, 0x01, 0x0a, 0x00, 0x00, 0x04]
+    );
+
+    // unknown command opcode 0x54
+    connector.reset();
+    connector.provide_data_to_read(&[
+        0x02, 0x00, 0x20, 0x07, 0x00, 0x03, 0x00, 0x04, 0x00, 0x54, 0x01, 0x00,
+    ]);
+    assert_matches!(
+        srv.do_work(),
+        Err(AttributeServerError::AttError(
+            AttParseError::UnknownOpcode(0x54)
+        ))
+    );
+    // commands are never answered
+    assert_eq!(connector.get_written_data().len, 0);
+}
+
+#[test]
+fn attribute_server_enforces_permissions() {
+    let connector = connector();
+    let mut ble = Ble::new(&connector);
+
+    let mut rf1 = |_| Ok(Data::new(&[0x01]));
+    let mut wf1 = |_, _data: Data| Ok(());
+    let mut rf2 = |_| Ok(Data::new(&[0x02]));
+    let mut wf2 = |_, _data: Data| Ok(());
+
+    let mut characteristic1 = Characteristic::new(
+        Uuid::Uuid16(0x2a19),
+        ATT_READABLE | ATT_WRITEABLE,
+        &mut rf1,
+        &mut wf1,
+    );
+    characteristic1.permissions = PERMISSION_READ
+        | PERMISSION_READ_ENCRYPTED
+        | PERMISSION_WRITE
+        | PERMISSION_WRITE_AUTHENTICATED;
+    characteristic1.min_key_size = 16;
+    let mut characteristic2 =
+        Characteristic::new(Uuid::Uuid16(0x2a1a), ATT_READABLE, &mut rf2, &mut wf2);
+    characteristic2.permissions = PERMISSION_READ | PERMISSION_READ_AUTHORIZED;
+
+    let characteristics = &mut [characteristic1, characteristic2];
+    let services = &mut [Service::new(Uuid::Uuid16(0x180f), characteristics)];
+    let mut srv = AttributeServer::new(&mut ble, services);
+
+    // ReadReq { handle: 3 }
+    connector.provide_data_to_read(&[
+        0x02, 0x00, 0x20, 0x07, 0x00, 0x03, 0x00, 0x04, 0x00, 0x0a, 0x03, 0x00,
+    ]);
+    assert_matches!(srv.do_work(), Ok(()));
+    // check response (insufficient encryption)
+    let response_data = connector.get_written_data();
+    assert_eq!(
+        response_data.to_slice(),
+        &[0x02, 0x00, 0x20, 0x09, 0x00, 0x05, 0x00, 0x04, 0x00, 0x01, 0x0a, 0x03, 0x00, 0x0f]
+    );
+
+    // EncryptionChange { status: Okay, handle: 0, enabled: true }
+    connector.reset();
+    connector.provide_data_to_read(&[0x04, 0x08, 0x04, 0x00, 0x00, 0x00, 0x01]);
+    assert_matches!(srv.do_work(), Ok(()));
+    assert_matches!(
+        srv.security(0x0000),
+        Some(ConnectionSecurity {
+            encrypted: true,
+            authenticated: false,
+            key_size: 0,
+            ..
+        })
+    );
+
+    // ReadReq { handle: 3 }
+    connector.reset();
+    connector.provide_data_to_read(&[
+        0x02, 0x00, 0x20, 0x07, 0x00, 0x03, 0x00, 0x04, 0x00, 0x0a, 0x03, 0x00,
+    ]);
+    assert_matches!(srv.do_work(), Ok(()));
+    // check response (insufficient key size, the key size isn't known yet)
+    let response_data = connector.get_written_data();
+    assert_eq!(
+        response_data.to_slice(),
+        &[0x02, 0x00, 0x20, 0x09, 0x00, 0x05, 0x00, 0x04, 0x00, 0x01, 0x0a, 0x03, 0x00, 0x0c]
+    );
+
+    srv.set_security(
+        0x0000,
+        ConnectionSecurity {
+            encrypted: true,
+            authenticated: false,
+            authorized: false,
+            key_size: 16,
+        },
+    );
+
+    // ReadReq { handle: 3 }
+    connector.reset();
+    connector.provide_data_to_read(&[
+        0x02, 0x00, 0x20, 0x07, 0x00, 0x03, 0x00, 0x04, 0x00, 0x0a, 0x03, 0x00,
+    ]);
+    assert_matches!(srv.do_work(), Ok(()));
+    // check response
+    let response_data = connector.get_written_data();
+    assert_eq!(
+        response_data.to_slice(),
+        &[0x02, 0x00, 0x20, 0x06, 0x00, 0x02, 0x00, 0x04, 0x00, 0x0b, 0x01]
+    );
+
+    // WriteReq { handle: 3, data: [0xff] }
+    connector.reset();
+    connector.provide_data_to_read(&[
+        0x02, 0x00, 0x20, 0x08, 0x00, 0x04, 0x00, 0x04, 0x00, 0x12, 0x03, 0x00, 0xff,
+    ]);
+    assert_matches!(srv.do_work(), Ok(()));
+    // check response (insufficient authentication)
+    let response_data = connector.get_written_data();
+    assert_eq!(
+        response_data.to_slice(),
+        &[0x02, 0x00, 0x20, 0x09, 0x00, 0x05, 0x00, 0x04, 0x00, 0x01, 0x12, 0x03, 0x00, 0x05]
+    );
+
+    srv.set_security(
+        0x0000,
+        ConnectionSecurity {
+            encrypted: true,
+            authenticated: true,
+            authorized: false,
+            key_size: 7,
+        },
+    );
+
+    // ReadReq { handle: 3 }
+    connector.reset();
+    connector.provide_data_to_read(&[
+        0x02, 0x00, 0x20, 0x07, 0x00, 0x03, 0x00, 0x04, 0x00, 0x0a, 0x03, 0x00,
+    ]);
+    assert_matches!(srv.do_work(), Ok(()));
+    // check response (insufficient key size)
+    let response_data = connector.get_written_data();
+    assert_eq!(
+        response_data.to_slice(),
+        &[0x02, 0x00, 0x20, 0x09, 0x00, 0x05, 0x00, 0x04, 0x00, 0x01, 0x0a, 0x03, 0x00, 0x0c]
+    );
+
+    // ReadReq { handle: 5 }
+    connector.reset();
+    connector.provide_data_to_read(&[
+        0x02, 0x00, 0x20, 0x07, 0x00, 0x03, 0x00, 0x04, 0x00, 0x0a, 0x05, 0x00,
+    ]);
+    assert_matches!(srv.do_work(), Ok(()));
+    // check response (insufficient authorization)
+    let response_data = connector.get_written_data();
+    assert_eq!(
+        response_data.to_slice(),
+        &[0x02, 0x00, 0x20, 0x09, 0x00, 0x05, 0x00, 0x04, 0x00, 0x01, 0x0a, 0x05, 0x00, 0x08]
+    );
+
+    srv.set_security(
+        0x0000,
+        ConnectionSecurity {
+            encrypted: true,
+            authenticated: true,
+            authorized: true,
+            key_size: 16,
+        },
+    );
+
+    // WriteReq { handle: 3, data: [0xff] }
+    connector.reset();
+    connector.provide_data_to_read(&[
+        0x02, 0x00, 0x20, 0x08, 0x00, 0x04, 0x00, 0x04, 0x00, 0x12, 0x03, 0x00, 0xff,
+    ]);
+    assert_matches!(srv.do_work(), Ok(()));
+    // check response
+    let response_data = connector.get_written_data();
+    assert_eq!(
+        response_data.to_slice(),
+        &[0x02, 0x00, 0x20, 0x05, 0x00, 0x01, 0x00, 0x04, 0x00, 0x13]
+    );
+
+    // ReadReq { handle: 5 }
+    connector.reset();
+    connector.provide_data_to_read(&[
+        0x02, 0x00, 0x20, 0x07, 0x00, 0x03, 0x00, 0x04, 0x00, 0x0a, 0x05, 0x00,
+    ]);
+    assert_matches!(srv.do_work(), Ok(()));
+    // check response
+    let response_data = connector.get_written_data();
+    assert_eq!(
+        response_data.to_slice(),
+        &[0x02, 0x00, 0x20, 0x06, 0x00, 0x02, 0x00, 0x04, 0x00, 0x0b, 0x02]
+    );
+}
+
+#[test]
+fn att_error_code_conversion_works() {
+    assert_eq!(AttErrorCode::from_u8(0x0a), AttErrorCode::AttributeNotFound);
+    assert_eq!(AttErrorCode::AttributeNotFound.to_u8(), 0x0a);
+    assert_eq!(AttErrorCode::from_u8(0x85), AttErrorCode::Application(0x85));
+    assert_eq!(AttErrorCode::Application(0x85).to_u8(), 0x85);
+    assert_eq!(AttErrorCode::from_u8(0xfd), AttErrorCode::Other(0xfd));
+}
+
+#[test]
+fn attribute_server_replies_with_callback_errors() {
+    let mut contexts = Vec::<AccessContext>::new();
+
+    let connector = connector();
+    let mut ble = Ble::new(&connector);
+
+    let mut rf1 = |_| Err(AttErrorCode::Application(0x80));
+    let mut wf1 = |context: AccessContext, data: Data| {
+        contexts.push(context);
+        if data.to_slice() == [0x00] {
+            Err(AttErrorCode::Application(0x81))
+        } else {
+            Ok(())
+        }
+    };
+
+    let characteristics = &mut [Characteristic::new(
+        Uuid::Uuid16(0x2a19),
+        ATT_READABLE | ATT_WRITEABLE,
+        &mut rf1,
+        &mut wf1,
+    )];
+    let services = &mut [Service::new(Uuid::Uuid16(0x180f), characteristics)];
+    let mut srv = AttributeServer::new(&mut ble, services);
+
+    // ReadReq { handle: 3 }
+    connector.provide_data_to_read(&[
+        0x02, 0x00, 0x20, 0x07, 0x00, 0x03, 0x00, 0x04, 0x00, 0x0a, 0x03, 0x00,
+    ]);
+    assert_matches!(srv.do_work(), Ok(()));
+    // check response (application error 0x80)
+    let response_data = connector.get_written_data();
+    assert_eq!(
+        response_data.to_slice(),
+        &[0x02, 0x00, 0x20, 0x09, 0x00, 0x05, 0x00, 0x04, 0x00, 0x01, 0x0a, 0x03, 0x00, 0x80]
+    );
+
+    // WriteReq { handle: 3, data: [0x00] }
+    connector.reset();
+    connector.provide_data_to_read(&[
+        0x02, 0x00, 0x20, 0x08, 0x00, 0x04, 0x00, 0x04, 0x00, 0x12, 0x03, 0x00, 0x00,
+    ]);
+    assert_matches!(srv.do_work(), Ok(()));
+    // check response (application error 0x81)
+    let response_data = connector.get_written_data();
+    assert_eq!(
+        response_data.to_slice(),
+        &[0x02, 0x00, 0x20, 0x09, 0x00, 0x05, 0x00, 0x04, 0x00, 0x01, 0x12, 0x03, 0x00, 0x81]
+    );
+
+    // WriteReq { handle: 3, data: [0x01] }
+    connector.reset();
+    connector.provide_data_to_read(&[
+        0x02, 0x00, 0x20, 0x08, 0x00, 0x04, 0x00, 0x04, 0x00, 0x12, 0x03, 0x00, 0x01,
+    ]);
+    assert_matches!(srv.do_work(), Ok(()));
+    // check response
+    let response_data = connector.get_written_data();
+    assert_eq!(
+        response_data.to_slice(),
+        &[0x02, 0x00, 0x20, 0x05, 0x00, 0x01, 0x00, 0x04, 0x00, 0x13]
+    );
+
+    assert_eq!(contexts.len(), 2);
+    assert_eq!(contexts[0].connection_handle, 0x0000);
+    assert_eq!(contexts[0].opcode, 0x12);
+    assert_eq!(contexts[0].offset, 0);
+}
+
+#[test]
+fn attribute_server_provides_generic_services() {
+    let connector = connector();
+    let mut ble = Ble::new(&connector);
+
+    let mut rf1 = |_| Ok(Data::new(&[0x64]));
+    let mut wf1 = |_, _data: Data| Ok(());
+
+    let characteristics = &mut [Characteristic::new(
+        Uuid::Uuid16(0x2a19),
+        ATT_READABLE,
+        &mut rf1,
+        &mut wf1,
+    )];
+    let services = &mut [Service::new(Uuid::Uuid16(0x180f), characteristics)];
+    let mut srv = AttributeServer::new_with_generic_services(
+        &mut ble,
+        services,
+        GenericAccess::new("sensor", 0x0540),
+    );
+
+    // ReadByGroupTypeReq { start: 1, end: 0xffff, group_type: 0x2800 }
+    connector.provide_data_to_read(&[
+        0x02, 0x00, 0x20, 0x0b, 0x00, 0x07, 0x00, 0x04, 0x00, 0x10, 0x01, 0x00, 0xff, 0xff, 0x00,
+        0x28,
+    ]);
+    assert_matches!(srv.do_work(), Ok(()));
+    // check response (GAP 1-7, GATT 8-15, battery service 16-18)
+    let response_data = connector.get_written_data();
+    assert_eq!(
+        response_data.to_slice(),
+        &[
+            0x02, 0x00, 0x20, 0x18, 0x00, 0x14, 0x00, 0x04, 0x00, 0x11, 0x06, 0x01, 0x00, 0x07,
+            0x00, 0x00, 0x18, 0x08, 0x00, 0x0f, 0x00, 0x01, 0x18, 0x10, 0x00, 0x12, 0x00, 0x0f,
+            0x18
+        ]
+    );
+
+    // ReadByTypeReq { start: 1, end: 7, attribute_type: 0x2803 }
+    connector.reset();
+    connector.provide_data_to_read(&[
+        0x02, 0x00, 0x20, 0x0b, 0x00, 0x07, 0x00, 0x04, 0x00, 0x08, 0x01, 0x00, 0x07, 0x00, 0x03,
+        0x28,
+    ]);
+    assert_matches!(srv.do_work(), Ok(()));
+    // check response (device name, appearance, preferred connection parameters)
+    let response_data = connector.get_written_data();
+    assert_eq!(
+        response_data.to_slice(),
+        &[
+            0x02, 0x00, 0x20, 0x1b, 0x00, 0x17, 0x00, 0x04, 0x00, 0x09, 0x07, 0x02, 0x00, 0x02,
+            0x03, 0x00, 0x00, 0x2a, 0x04, 0x00, 0x02, 0x05, 0x00, 0x01, 0x2a, 0x06, 0x00, 0x02,
+            0x07, 0x00, 0x04, 0x2a
+        ]
+    );
+
+    // ReadReq { handle: 3 }
+    connector.reset();
+    connector.provide_data_to_read(&[
+        0x02, 0x00, 0x20, 0x07, 0x00, 0x03, 0x00, 0x04, 0x00, 0x0a, 0x03, 0x00,
+    ]);
+    assert_matches!(srv.do_work(), Ok(()));
+    // check response (device name)
+    let response_data = connector.get_written_data();
+    assert_eq!(
+        response_data.to_slice(),
+        &[
+            0x02, 0x00, 0x20, 0x0b, 0x00, 0x07, 0x00, 0x04, 0x00, 0x0b, b's', b'e', b'n', b's',
+            b'o', b'r'
+        ]
+    );
+
+    // ReadReq { handle: 5 }
+    connector.reset();
+    connector.provide_data_to_read(&[
+        0x02, 0x00, 0x20, 0x07, 0x00, 0x03, 0x00, 0x04, 0x00, 0x0a, 0x05, 0x00,
+    ]);
+    assert_matches!(srv.do_work(), Ok(()));
+    // check response (appearance)
+    let response_data = connector.get_written_data();
+    assert_eq!(
+        response_data.to_slice(),
+        &[0x02, 0x00, 0x20, 0x07, 0x00, 0x03, 0x00, 0x04, 0x00, 0x0b, 0x40, 0x05]
+    );
+
+    // ReadReq { handle: 7 }
+    connector.reset();
+    connector.provide_data_to_read(&[
+        0x02, 0x00, 0x20, 0x07, 0x00, 0x03, 0x00, 0x04, 0x00, 0x0a, 0x07, 0x00,
+    ]);
+    assert_matches!(srv.do_work(), Ok(()));
+    // check response (no preferred connection parameters)
+    let response_data = connector.get_written_data();
+    assert_eq!(
+        response_data.to_slice(),
+        &[
+            0x02, 0x00, 0x20, 0x0d, 0x00, 0x09, 0x00, 0x04, 0x00, 0x0b, 0xff, 0xff, 0xff, 0xff,
+            0xff, 0xff, 0xff, 0xff
+        ]
+    );
+
+    // ReadReq { handle: 10 }
+    connector.reset();
+    connector.provide_data_to_read(&[
+        0x02, 0x00, 0x20, 0x07, 0x00, 0x03, 0x00, 0x04, 0x00, 0x0a, 0x0a, 0x00,
+    ]);
+    assert_matches!(srv.do_work(), Ok(()));
+    // check response (service changed isn't readable)
+    let response_data = connector.get_written_data();
+    assert_eq!(
+        response_data.to_slice(),
+        &[0x02, 0x00, 0x20, 0x09, 0x00, 0x05, 0x00, 0x04, 0x00, 0x01, 0x0a, 0x0a, 0x00, 0x02]
+    );
+
+    // ReadReq { handle: 18 }
+    connector.reset();
+    connector.provide_data_to_read(&[
+        0x02, 0x00, 0x20, 0x07, 0x00, 0x03, 0x00, 0x04, 0x00, 0x0a, 0x12, 0x00,
+    ]);
+    assert_matches!(srv.do_work(), Ok(()));
+    // check response (battery level)
+    let response_data = connector.get_written_data();
+    assert_eq!(
+        response_data.to_slice(),
+        &[0x02, 0x00, 0x20, 0x06, 0x00, 0x02, 0x00, 0x04, 0x00, 0x0b, 0x64]
+    );
+}
+
+#[test]
+fn attribute_server_indicates_service_changed() {
+    let connector = connector();
+    let mut ble = Ble::new(&connector);
+
+    let services: &mut [Service] = &mut [];
+    let mut srv = AttributeServer::new_with_generic_services(
+        &mut ble,
+        services,
+        GenericAccess::new("sensor", 0x0000),
+    );
+
+    // WriteReq { handle: 11, data: [0x02, 0x00] }
+    connector.provide_data_to_read(&[
+        0x02, 0x00, 0x20, 0x09, 0x00, 0x05, 0x00, 0x04, 0x00, 0x12, 0x0b, 0x00, 0x02, 0x00,
+    ]);
+    assert_matches!(srv.do_work(), Ok(()));
+    // check response
+    let response_data = connector.get_written_data();
+    assert_eq!(
+        response_data.to_slice(),
+        &[0x02, 0x00, 0x20, 0x05, 0x00, 0x01, 0x00, 0x04, 0x00, 0x13]
+    );
+
+    connector.reset();
+    assert_matches!(srv.indicate_service_changed(0x000e, 0x0010), Ok(()));
+    // check indication
+    let response_data = connector.get_written_data();
+    assert_eq!(
+        response_data.to_slice(),
+        &[
+            0x02, 0x00, 0x20, 0x0b, 0x00, 0x07, 0x00, 0x04, 0x00, 0x1d, 0x0a, 0x00, 0x0e, 0x00,
+            0x10, 0x00
+        ]
+    );
+    assert!(srv.is_indication_pending());
+
+    // WriteReq { handle: 13, data: [0x01] }
+    connector.reset();
+    connector.provide_data_to_read(&[
+        0x02, 0x00, 0x20, 0x08, 0x00, 0x04, 0x00, 0x04, 0x00, 0x12, 0x0d, 0x00, 0x01,
+    ]);
+    assert_matches!(srv.do_work(), Ok(()));
+    // check response
+    let response_data = connector.get_written_data();
+    assert_eq!(
+        response_data.to_slice(),
+        &[0x02, 0x00, 0x20, 0x05, 0x00, 0x01, 0x00, 0x04, 0x00, 0x13]
+    );
+
+    // ReadReq { handle: 13 }
+    connector.reset();
+    connector.provide_data_to_read(&[
+        0x02, 0x00, 0x20, 0x07, 0x00, 0x03, 0x00, 0x04, 0x00, 0x0a, 0x0d, 0x00,
+    ]);
+    assert_matches!(srv.do_work(), Ok(()));
+    // check response (robust caching enabled)
+    let response_data = connector.get_written_data();
+    assert_eq!(
+        response_data.to_slice(),
+        &[0x02, 0x00, 0x20, 0x06, 0x00, 0x02, 0x00, 0x04, 0x00, 0x0b, 0x01]
+    );
+
+    // WriteReq { handle: 13, data: [0x00] }
+    connector.reset();
+    connector.provide_data_to_read(&[
+        0x02, 0x00, 0x20, 0x08, 0x00, 0x04, 0x00, 0x04, 0x00, 0x12, 0x0d, 0x00, 0x00,
+    ]);
+    assert_matches!(srv.do_work(), Ok(()));
+    // check response (features can't be disabled)
+    let response_data = connector.get_written_data();
+    assert_eq!(
+        response_data.to_slice(),
+        &[0x02, 0x00, 0x20, 0x09, 0x00, 0x05, 0x00, 0x04, 0x00, 0x01, 0x12, 0x0d, 0x00, 0x13]
+    );
+}
+
+#[test]
+fn attribute_server_serves_database_hash() {
+    let connector = connector();
+    let mut ble = Ble::new(&connector);
+
+    let mut rf1 = |_| Ok(Data::new(&[0x64]));
+    let mut wf1 = |_, _data: Data| Ok(());
+
+    let characteristics = &mut [Characteristic::new(
+        Uuid::Uuid16(0x2a19),
+        ATT_READABLE,
+        &mut rf1,
+        &mut wf1,
+    )];
+    let services = &mut [Service::new(Uuid::Uuid16(0x180f), characteristics)];
+    let mut srv = AttributeServer::new_with_generic_services(
+        &mut ble,
+        services,
+        GenericAccess::new("sensor", 0x0540),
+    );
+
+    // ReadByTypeReq { start: 1, end: 0xffff, attribute_type: 0x2b2a }
+    connector.provide_data_to_read(&[
+        0x02, 0x00, 0x20, 0x0b, 0x00, 0x07, 0x00, 0x04, 0x00, 0x08, 0x01, 0x00, 0xff, 0xff, 0x2a,
+        0x2b,
+    ]);
+    assert_matches!(srv.do_work(), Ok(()));
+
+    // AES-CMAC with a zero key, calculated independently of this crate, over the handle, type
+    // and value of the declarations and the handle and type of the CCCD:
+    //   0x01, 0x00, 0x00, 0x28, 0x00, 0x18, // GAP service
+    //   0x02, 0x00, 0x03, 0x28, 0x02, 0x03, 0x00, 0x00, 0x2a, // device name
+    //   0x04, 0x00, 0x03, 0x28, 0x02, 0x05, 0x00, 0x01, 0x2a, // appearance
+    //   0x06, 0x00, 0x03, 0x28, 0x02, 0x07, 0x00, 0x04, 0x2a, // connection parameters
+    //   0x08, 0x00, 0x00, 0x28, 0x01, 0x18, // GATT service
+    //   0x09, 0x00, 0x03, 0x28, 0x20, 0x0a, 0x00, 0x05, 0x2a, // service changed
+    //   0x0b, 0x00, 0x02, 0x29, // service changed CCCD
+    //   0x0c, 0x00, 0x03, 0x28, 0x0a, 0x0d, 0x00, 0x29, 0x2b, // client supported features
+    //   0x0e, 0x00, 0x03, 0x28, 0x02, 0x0f, 0x00, 0x2a, 0x2b, // database hash
+    //   0x10, 0x00, 0x00, 0x28, 0x0f, 0x18, // battery service
+    //   0x11, 0x00, 0x03, 0x28, 0x02, 0x12, 0x00, 0x19, 0x2a, // battery level
+    let hash = [
+        0x13, 0x66, 0x3e, 0x77, 0xb6, 0x68, 0x98, 0xca, 0xed, 0x96, 0xad, 0xaa, 0xe1, 0x90, 0xb8,
+        0x08,
+    ];
+
+    // check response
+    let response_data = connector.get_written_data();
+    assert_eq!(
+        &response_data.to_slice()[..13],
+        &[0x02, 0x00, 0x20, 0x18, 0x00, 0x14, 0x00, 0x04, 0x00, 0x09, 0x12, 0x0f, 0x00]
+    );
+    assert_eq!(&response_data.to_slice()[13..], &hash);
+}
+
+#[test]
+fn attribute_server_serves_descriptors() {
+    let connector = connector();
+    let mut ble = Ble::new(&connector);
+
+    let mut rf1 = |_| Ok(Data::new(&[0x64]));
+    let mut wf1 = |_, _data: Data| Ok(());
+
+    let custom_value = Cell::new(0u8);
+    let mut rf2 = |_| Ok(Data::new(&[custom_value.get()]));
+    let mut wf2 = |_, data: Data| {
+        custom_value.set(data.to_slice()[0]);
+        Ok(())
+    };
+
+    let descriptors = &mut [
+        Descriptor::extended_properties(EXTENDED_PROPERTY_RELIABLE_WRITE),
+        Descriptor::user_description("Battery"),
+        Descriptor::presentation_format(0x04, 0, 0x27ad, 0x01, 0x0000),
+        Descriptor::new(
+            Uuid::Uuid128([
+                0x00, 0x01, 0x02, 0x03, 0x04, 0x05, 0x06, 0x07, 0x08, 0x09, 0x0a, 0x0b, 0x0c, 0x0d,
+                0x0e, 0x0f,
+            ]),
+            PERMISSION_READ | PERMISSION_WRITE,
+            &mut rf2,
+            &mut wf2,
+        ),
+    ];
+    let mut characteristic = Characteristic::new(
+        Uuid::Uuid16(0x2a19),
+        ATT_READABLE | ATT_NOTIFY,
+        &mut rf1,
+        &mut wf1,
+    );
+    characteristic.descriptors = descriptors;
+    let characteristics = &mut [characteristic];
+    let services = &mut [Service::new(Uuid::Uuid16(0x180f), characteristics)];
+    let mut srv = AttributeServer::new(&mut ble, services);
+
+    // FindInformationReq { start: 5, end: 8 }
+    connector.provide_data_to_read(&[
+        0x02, 0x00, 0x20, 0x09, 0x00, 0x05, 0x00, 0x04, 0x00, 0x04, 0x05, 0x00, 0x08, 0x00,
+    ]);
+    assert_matches!(srv.do_work(), Ok(()));
+    // check response (descriptors after the CCCD at 4)
+    let response_data = connector.get_written_data();
+    assert_eq!(
+        response_data.to_slice(),
+        &[
+            0x02, 0x00, 0x20, 0x12, 0x00, 0x0e, 0x00, 0x04, 0x00, 0x05, 0x01, 0x05, 0x00, 0x00,
+            0x29, 0x06, 0x00, 0x01, 0x29, 0x07, 0x00, 0x04, 0x29
+        ]
+    );
+
+    // FindInformationReq { start: 8, end: 0xffff }
+    connector.reset();
+    connector.provide_data_to_read(&[
+        0x02, 0x00, 0x20, 0x09, 0x00, 0x05, 0x00, 0x04, 0x00, 0x04, 0x08, 0x00, 0xff, 0xff,
+    ]);
+    assert_matches!(srv.do_work(), Ok(()));
+    // check response
+    let response_data = connector.get_written_data();
+    assert_eq!(
+        response_data.to_slice(),
+        &[
+            0x02, 0x00, 0x20, 0x18, 0x00, 0x14, 0x00, 0x04, 0x00, 0x05, 0x02, 0x08, 0x00, 0x0f,
+            0x0e, 0x0d, 0x0c, 0x0b, 0x0a, 0x09, 0x08, 0x07, 0x06, 0x05, 0x04, 0x03, 0x02, 0x01,
+            0x00
+        ]
+    );
+
+    // ReadReq { handle: 2 }
+    connector.reset();
+    connector.provide_data_to_read(&[
+        0x02, 0x00, 0x20, 0x07, 0x00, 0x03, 0x00, 0x04, 0x00, 0x0a, 0x02, 0x00,
+    ]);
+    assert_matches!(srv.do_work(), Ok(()));
+    // check response (declaration announces the extended properties)
+    let response_data = connector.get_written_data();
+    assert_eq!(
+        response_data.to_slice(),
+        &[
+            0x02, 0x00, 0x20, 0x0a, 0x00, 0x06, 0x00, 0x04, 0x00, 0x0b, 0x92, 0x03, 0x00, 0x19,
+            0x2a
+        ]
+    );
+
+    // ReadReq { handle: 6 }
+    connector.reset();
+    connector.provide_data_to_read(&[
+        0x02, 0x00, 0x20, 0x07, 0x00, 0x03, 0x00, 0x04, 0x00, 0x0a, 0x06, 0x00,
+    ]);
+    assert_matches!(srv.do_work(), Ok(()));
+    // check response
+    let response_data = connector.get_written_data();
+    assert_eq!(
+        response_data.to_slice(),
+        &[
+            0x02, 0x00, 0x20, 0x0c, 0x00, 0x08, 0x00, 0x04, 0x00, 0x0b, b'B', b'a', b't', b't',
+            b'e', b'r', b'y'
+        ]
+    );
+
+    // ReadReq { handle: 7 }
+    connector.reset();
+    connector.provide_data_to_read(&[
+        0x02, 0x00, 0x20, 0x07, 0x00, 0x03, 0x00, 0x04, 0x00, 0x0a, 0x07, 0x00,
+    ]);
+    assert_matches!(srv.do_work(), Ok(()));
+    // check response (uint8 percentage)
+    let response_data = connector.get_written_data();
+    assert_eq!(
+        response_data.to_slice(),
+        &[
+            0x02, 0x00, 0x20, 0x0c, 0x00, 0x08, 0x00, 0x04, 0x00, 0x0b, 0x04, 0x00, 0xad, 0x27,
+            0x01, 0x00, 0x00
+        ]
+    );
+
+    // WriteReq { handle: 6, data: [0x41] }
+    connector.reset();
+    connector.provide_data_to_read(&[
+        0x02, 0x00, 0x20, 0x08, 0x00, 0x04, 0x00, 0x04, 0x00, 0x12, 0x06, 0x00, 0x41,
+    ]);
+    assert_matches!(srv.do_work(), Ok(()));
+    // check response (fixed values aren't writable)
+    let response_data = connector.get_written_data();
+    assert_eq!(
+        response_data.to_slice(),
+        &[0x02, 0x00, 0x20, 0x09, 0x00, 0x05, 0x00, 0x04, 0x00, 0x01, 0x12, 0x06, 0x00, 0x03]
+    );
+
+    // WriteReq { handle: 8, data: [0x2a] }
+    connector.reset();
+    connector.provide_data_to_read(&[
+        0x02, 0x00, 0x20, 0x08, 0x00, 0x04, 0x00, 0x04, 0x00, 0x12, 0x08, 0x00, 0x2a,
+    ]);
+    assert_matches!(srv.do_work(), Ok(()));
+    // check response
+    let response_data = connector.get_written_data();
+    assert_eq!(
+        response_data.to_slice(),
+        &[0x02, 0x00, 0x20, 0x05, 0x00, 0x01, 0x00, 0x04, 0x00, 0x13]
+    );
+
+    // ReadReq { handle: 8 }
+    connector.reset();
+    connector.provide_data_to_read(&[
+        0x02, 0x00, 0x20, 0x07, 0x00, 0x03, 0x00, 0x04, 0x00, 0x0a, 0x08, 0x00,
+    ]);
+    assert_matches!(srv.do_work(), Ok(()));
+    // check response
+    let response_data = connector.get_written_data();
+    assert_eq!(
+        response_data.to_slice(),
+        &[0x02, 0x00, 0x20, 0x06, 0x00, 0x02, 0x00, 0x04, 0x00, 0x0b, 0x2a]
+    );
+}
+
+#[test]
+fn attribute_server_includes_secondary_services() {
+    let connector = connector();
+    let mut ble = Ble::new(&connector);
+
+    let mut rf1 = |_| Ok(Data::new(&[0x01]));
+    let mut wf1 = |_, _data: Data| Ok(());
+    let mut rf2 = |_| Ok(Data::new(&[0x64]));
+    let mut wf2 = |_, _data: Data| Ok(());
 
-    let services = &mut [srv];
+    let hid_characteristics = &mut [Characteristic::new(
+        Uuid::Uuid16(0x2a4d),
+        ATT_READABLE,
+        &mut rf1,
+        &mut wf1,
+    )];
+    let battery_characteristics = &mut [Characteristic::new(
+        Uuid::Uuid16(0x2a19),
+        ATT_READABLE,
+        &mut rf2,
+        &mut wf2,
+    )];
+    let mut hid = Service::new(Uuid::Uuid16(0x1812), hid_characteristics);
+    hid.includes = &[1];
+    let mut battery = Service::new(Uuid::Uuid16(0x180f), battery_characteristics);
+    battery.secondary = true;
+    let services = &mut [hid, battery];
     let mut srv = AttributeServer::new(&mut ble, services);
 
-    // ReadByGroupTypeReq { start: 1, end: ffff, group_type: Uuid16(2800) }
+    // ReadByGroupTypeReq { start: 1, end: 0xffff, group_type: 0x2800 }
     connector.provide_data_to_read(&[
         0x02, 0x00, 0x20, 0x0b, 0x00, 0x07, 0x00, 0x04, 0x00, 0x10, 0x01, 0x00, 0xff, 0xff, 0x00,
         0x28,
     ]);
     assert_matches!(srv.do_work(), Ok(()));
-    // check response (1-3, 0x2800)
+    // check response (only the primary service)
     let response_data = connector.get_written_data();
     assert_eq!(
         response_data.to_slice(),
         &[
-            0x02, 0x00, 0x20, 0x0c, 0x00, 0x08, 0x00, 0x04, 0x00, 0x11, 0x06, 0x01, 0x00, 0x03,
-            0x00, 0x00, 0x28
+            0x02, 0x00, 0x20, 0x0c, 0x00, 0x08, 0x00, 0x04, 0x00, 0x11, 0x06, 0x01, 0x00, 0x04,
+            0x00, 0x12, 0x18
         ]
     );
 
-    // ReadByGroupTypeReq { start: 3, end: ffff, group_type: Uuid16(2800) }
+    // ReadByGroupTypeReq { start: 1, end: 0xffff, group_type: 0x2801 }
     connector.reset();
     connector.provide_data_to_read(&[
-        0x02, 0x00, 0x20, 0x0b, 0x00, 0x07, 0x00, 0x04, 0x00, 0x10, 0x03, 0x00, 0xff, 0xff, 0x00,
+        0x02, 0x00, 0x20, 0x0b, 0x00, 0x07, 0x00, 0x04, 0x00, 0x10, 0x01, 0x00, 0xff, 0xff, 0x01,
         0x28,
     ]);
     assert_matches!(srv.do_work(), Ok(()));
-    // check response (not found)
+    // check response (only the secondary service)
     let response_data = connector.get_written_data();
     assert_eq!(
         response_data.to_slice(),
-        &[0x02, 0x00, 0x20, 0x09, 0x00, 0x05, 0x00, 0x04, 0x00, 0x01, 0x10, 0x03, 0x00, 0x0a]
+        &[
+            0x02, 0x00, 0x20, 0x0c, 0x00, 0x08, 0x00, 0x04, 0x00, 0x11, 0x06, 0x05, 0x00, 0x07,
+            0x00, 0x0f, 0x18
+        ]
     );
 
-    // ReadByTypeReq { start: 1, end: 3, attribute_type: Uuid16(2802) }
+    // ReadByTypeReq { start: 1, end: 4, attribute_type: 0x2802 }
     connector.reset();
     connector.provide_data_to_read(&[
-        0x02, 0x00, 0x20, 0x0b, 0x00, 0x07, 0x00, 0x04, 0x00, 0x08, 0x01, 0x00, 0x02, 0x00, 0x02,
+        0x02, 0x00, 0x20, 0x0b, 0x00, 0x07, 0x00, 0x04, 0x00, 0x08, 0x01, 0x00, 0x04, 0x00, 0x02,
         0x28,
     ]);
     assert_matches!(srv.do_work(), Ok(()));
-    // check response (not found)
+    // check response (include at 2 pointing to 5-7)
     let response_data = connector.get_written_data();
     assert_eq!(
         response_data.to_slice(),
-        &[0x02, 0x00, 0x20, 0x09, 0x00, 0x05, 0x00, 0x04, 0x00, 0x01, 0x08, 0x01, 0x00, 0x0a]
+        &[
+            0x02, 0x00, 0x20, 0x0e, 0x00, 0x0a, 0x00, 0x04, 0x00, 0x09, 0x08, 0x02, 0x00, 0x05,
+            0x00, 0x07, 0x00, 0x0f, 0x18
+        ]
     );
 
-    // ReadByTypeReq { start: 1, end: 3, attribute_type: Uuid16(2803) }
+    // FindByTypeValueReq { start: 1, end: 0xffff, att_type: 0x2800, value: 0x180f }
     connector.reset();
     connector.provide_data_to_read(&[
-        0x02, 0x00, 0x20, 0x0b, 0x00, 0x07, 0x00, 0x04, 0x00, 0x08, 0x01, 0x00, 0x03, 0x00, 0x03,
-        0x28,
+        0x02, 0x00, 0x20, 0x0d, 0x00, 0x09, 0x00, 0x04, 0x00, 0x06, 0x01, 0x00, 0xff, 0xff, 0x00,
+        0x28, 0x0f, 0x18,
     ]);
     assert_matches!(srv.do_work(), Ok(()));
-    // check response (not found)
+    // check response (secondary services aren't found)
     let response_data = connector.get_written_data();
     assert_eq!(
         response_data.to_slice(),
-        &[
-            0x02, 0x00, 0x20, 0x1b, 0x00, 0x17, 0x0, 0x4, 0x0, 0x9, 0x15, 0x2, 0x0, 0xa, 0x3, 0x0,
-            0xa8, 0x6a, 0x62, 0xf1, 0x5d, 0x26, 0x45, 0x38, 0xb3, 0x64, 0x56, 0x54, 0x96, 0x15,
-            0x15, 0xc9,
-        ]
+        &[0x02, 0x00, 0x20, 0x09, 0x00, 0x05, 0x00, 0x04, 0x00, 0x01, 0x06, 0x01, 0x00, 0x0a]
     );
 
-    // ReadReq { handle: 3 }
+    // ReadReq { handle: 7 }
     connector.reset();
     connector.provide_data_to_read(&[
-        0x02, 0x00, 0x20, 0x07, 0x00, 0x03, 0x00, 0x04, 0x00, 0x0a, 0x03, 0x00,
+        0x02, 0x00, 0x20, 0x07, 0x00, 0x03, 0x00, 0x04, 0x00, 0x0a, 0x07, 0x00,
     ]);
     assert_matches!(srv.do_work(), Ok(()));
-    // check response (read resp 'Hello')
+    // check response
+    let response_data = connector.get_written_data();
+    assert_eq!(
+        response_data.to_slice(),
+        &[0x02, 0x00, 0x20, 0x06, 0x00, 0x02, 0x00, 0x04, 0x00, 0x0b, 0x64]
+    );
+}
+
+#[test]
+fn gatt_server_macro_lays_out_handles() {
+    let connector = connector();
+    let mut ble = Ble::new(&connector);
+
+    let mut rf1 = |_| Ok(Data::new(&[0x01]));
+    let mut wf1 = |_, _data: Data| Ok(());
+    let mut rf2 = |_| Ok(Data::new(&[0x64]));
+    let mut wf2 = |_, _data: Data| Ok(());
+
+    gatt_server! {
+        first_handle: GENERIC_SERVICES_END_HANDLE + 1,
+        let services = [
+            service HID_SERVICE(Uuid::Uuid16(0x1812)) includes [1] {
+                characteristic REPORT(Uuid::Uuid16(0x2a4d), ATT_READABLE | ATT_NOTIFY, &mut rf1, &mut wf1) {
+                    cccd REPORT_CCCD;
+                    user_description REPORT_DESCRIPTION: "Report";
+                }
+            }
+            secondary_service BATTERY_SERVICE(Uuid::Uuid16(0x180f)) {
+                characteristic BATTERY_LEVEL(Uuid::Uuid16(0x2a19), ATT_READABLE, &mut rf2, &mut wf2) {
+                    presentation_format BATTERY_LEVEL_FORMAT: (0x04, 0, 0x27ad, 0x01, 0x0000);
+                }
+            }
+        ];
+    }
+
+    // the include declaration is at 17, the characteristic declarations before the values
+    assert_eq!(HID_SERVICE, 16);
+    assert_eq!(REPORT, 19);
+    assert_eq!(REPORT_CCCD, 20);
+    assert_eq!(REPORT_DESCRIPTION, 21);
+    assert_eq!(BATTERY_SERVICE, 22);
+    assert_eq!(BATTERY_LEVEL, 24);
+    assert_eq!(BATTERY_LEVEL_FORMAT, 25);
+
+    let mut srv = AttributeServer::new_with_generic_services(
+        &mut ble,
+        services,
+        GenericAccess::new("keyboard", 0x03c1),
+    );
+
+    // FindInformationReq { start: 20, end: 21 }
+    connector.provide_data_to_read(&[
+        0x02, 0x00, 0x20, 0x09, 0x00, 0x05, 0x00, 0x04, 0x00, 0x04, 0x14, 0x00, 0x15, 0x00,
+    ]);
+    assert_matches!(srv.do_work(), Ok(()));
+    // check response (CCCD and user description)
     let response_data = connector.get_written_data();
     assert_eq!(
         response_data.to_slice(),
         &[
-            0x02, 0x00, 0x20, 0x0a, 0x00, 0x06, 0x00, 0x04, 0x00, 0x0b, 0x48, 0x65, 0x6c, 0x6c,
-            0x6f,
+            0x02, 0x00, 0x20, 0x0e, 0x00, 0x0a, 0x00, 0x04, 0x00, 0x05, 0x01, 0x14, 0x00, 0x02,
+            0x29, 0x15, 0x00, 0x01, 0x29
         ]
     );
 
-    // WriteReq { handle: 3, data: [0xab] }
+    // ReadReq { handle: 24 }
     connector.reset();
     connector.provide_data_to_read(&[
-        0x02, 0x00, 0x20, 0x08, 0x00, 0x04, 0x00, 0x04, 0x00, 0x12, 0x03, 0x00, 0xab,
+        0x02, 0x00, 0x20, 0x07, 0x00, 0x03, 0x00, 0x04, 0x00, 0x0a, 0x18, 0x00,
     ]);
     assert_matches!(srv.do_work(), Ok(()));
-    // check response (write resp)
+    // check response
     let response_data = connector.get_written_data();
     assert_eq!(
         response_data.to_slice(),
-        &[0x02, 0x00, 0x20, 0x05, 0x00, 0x01, 0x00, 0x04, 0x00, 0x13]
+        &[0x02, 0x00, 0x20, 0x06, 0x00, 0x02, 0x00, 0x04, 0x00, 0x0b, 0x64]
     );
+}
 
-    assert_eq!(&written[..], &[0xab_u8]);
+#[test]
+fn gatt_client_discovers_services_characteristics_and_descriptors() {
+    let connector = connector();
+    let mut ble = Ble::new(&connector);
+    let mut client = GattClient::new(&mut ble, 0x0000);
+
+    // ReadByGroupTypeRsp { 1-5 0x180f, 6-9 0x180d }
+    connector.provide_data_to_read(&[
+        0x02, 0x00, 0x20, 0x12, 0x00, 0x0e, 0x00, 0x04, 0x00, 0x11, 0x06, 0x01, 0x00, 0x05, 0x00,
+        0x0f, 0x18, 0x06, 0x00, 0x09, 0x00, 0x0d, 0x18,
+    ]);
+    // ErrorRsp { request_opcode: 0x10, handle: 0x000a, code: AttributeNotFound }
+    connector.provide_data_to_read(&[
+        0x02, 0x00, 0x20, 0x09, 0x00, 0x05, 0x00, 0x04, 0x00, 0x01, 0x10, 0x0a, 0x00, 0x0a,
+    ]);
+    let mut services = Vec::new();
+    assert_matches!(
+        client.discover_primary_services(|service| services.push(service)),
+        Ok(())
+    );
+    assert_eq!(
+        services,
+        [
+            DiscoveredService {
+                start_handle: 0x0001,
+                end_handle: 0x0005,
+                uuid: Uuid::Uuid16(0x180f),
+            },
+            DiscoveredService {
+                start_handle: 0x0006,
+                end_handle: 0x0009,
+                uuid: Uuid::Uuid16(0x180d),
+            },
+        ]
+    );
+    // check requests (ReadByGroupTypeReq from 1, then from 10)
+    let written = connector.get_written_data();
+    assert_eq!(
+        written.to_slice(),
+        &[
+            0x02, 0x00, 0x20, 0x0b, 0x00, 0x07, 0x00, 0x04, 0x00, 0x10, 0x01, 0x00, 0xff, 0xff,
+            0x00, 0x28, 0x02, 0x00, 0x20, 0x0b, 0x00, 0x07, 0x00, 0x04, 0x00, 0x10, 0x0a, 0x00,
+            0xff, 0xff, 0x00, 0x28
+        ]
+    );
+
+    // FindByTypeValueRsp { 6-ffff }
+    connector.reset();
+    connector.provide_data_to_read(&[
+        0x02, 0x00, 0x20, 0x09, 0x00, 0x05, 0x00, 0x04, 0x00, 0x07, 0x06, 0x00, 0xff, 0xff,
+    ]);
+    let mut services = Vec::new();
+    assert_matches!(
+        client.discover_primary_services_by_uuid(Uuid::Uuid16(0x180d), |service| services
+            .push(service)),
+        Ok(())
+    );
+    assert_eq!(
+        services,
+        [DiscoveredService {
+            start_handle: 0x0006,
+            end_handle: 0xffff,
+            uuid: Uuid::Uuid16(0x180d),
+        }]
+    );
+    let written = connector.get_written_data();
+    assert_eq!(
+        written.to_slice(),
+        &[
+            0x02, 0x00, 0x20, 0x0d, 0x00, 0x09, 0x00, 0x04, 0x00, 0x06, 0x01, 0x00, 0xff, 0xff,
+            0x00, 0x28, 0x0d, 0x18
+        ]
+    );
+
+    // ReadByTypeRsp { declaration 7 -> value 8, 0x2a37 }
+    connector.reset();
+    connector.provide_data_to_read(&[
+        0x02, 0x00, 0x20, 0x0d, 0x00, 0x09, 0x00, 0x04, 0x00, 0x09, 0x07, 0x07, 0x00, 0x12, 0x08,
+        0x00, 0x37, 0x2a,
+    ]);
+    // ErrorRsp { request_opcode: 0x08, handle: 0x0008, code: AttributeNotFound }
+    connector.provide_data_to_read(&[
+        0x02, 0x00, 0x20, 0x09, 0x00, 0x05, 0x00, 0x04, 0x00, 0x01, 0x08, 0x08, 0x00, 0x0a,
+    ]);
+    let mut characteristics = Vec::new();
+    assert_matches!(
+        client.discover_characteristics(0x0006, 0x0009, |characteristic| characteristics
+            .push(characteristic)),
+        Ok(())
+    );
+    assert_eq!(
+        characteristics,
+        [DiscoveredCharacteristic {
+            declaration_handle: 0x0007,
+            properties: ATT_READABLE | ATT_NOTIFY,
+            value_handle: 0x0008,
+            uuid: Uuid::Uuid16(0x2a37),
+        }]
+    );
+    let written = connector.get_written_data();
+    assert_eq!(
+        written.to_slice(),
+        &[
+            0x02, 0x00, 0x20, 0x0b, 0x00, 0x07, 0x00, 0x04, 0x00, 0x08, 0x06, 0x00, 0x09, 0x00,
+            0x03, 0x28, 0x02, 0x00, 0x20, 0x0b, 0x00, 0x07, 0x00, 0x04, 0x00, 0x08, 0x08, 0x00,
+            0x09, 0x00, 0x03, 0x28
+        ]
+    );
+
+    // FindInformationRsp { 9: 0x2902 } - the end of the range, no further request
+    connector.reset();
+    connector.provide_data_to_read(&[
+        0x02, 0x00, 0x20, 0x0a, 0x00, 0x06, 0x00, 0x04, 0x00, 0x05, 0x01, 0x09, 0x00, 0x02, 0x29,
+    ]);
+    let mut descriptors = Vec::new();
+    assert_matches!(
+        client.discover_descriptors(0x0009, 0x0009, |descriptor| descriptors.push(descriptor)),
+        Ok(())
+    );
+    assert_eq!(
+        descriptors,
+        [DiscoveredDescriptor {
+            handle: 0x0009,
+            uuid: Uuid::Uuid16(0x2902),
+        }]
+    );
+    let written = connector.get_written_data();
+    assert_eq!(
+        written.to_slice(),
+        &[0x02, 0x00, 0x20, 0x09, 0x00, 0x05, 0x00, 0x04, 0x00, 0x04, 0x09, 0x00, 0x09, 0x00]
+    );
+
+    // ErrorRsp { request_opcode: 0x04, handle: 0x0001, code: ReadNotPermitted }
+    connector.reset();
+    connector.provide_data_to_read(&[
+        0x02, 0x00, 0x20, 0x09, 0x00, 0x05, 0x00, 0x04, 0x00, 0x01, 0x04, 0x01, 0x00, 0x02,
+    ]);
+    assert_matches!(
+        client.discover_descriptors(0x0001, 0x0005, |_| ()),
+        Err(GattClientError::ErrorResponse {
+            handle: 0x0001,
+            code: AttErrorCode::ReadNotPermitted,
+        })
+    );
 }
 
 #[test]
-fn attribute_server_discover_two_services() {
+fn gatt_client_ignores_unknown_pdus() {
     let connector = connector();
     let mut ble = Ble::new(&connector);
+    let mut client = GattClient::new(&mut ble, 0x0000);
+
+    // ReadByGroupTypeRsp { 1-5 0x180f }
+    connector.provide_data_to_read(&[
+        0x02, 0x00, 0x20, 0x0c, 0x00, 0x08, 0x00, 0x04, 0x00, 0x11, 0x06, 0x01, 0x00, 0x05, 0x00,
+        0x0f, 0x18,
+    ]);
+    // unknown opcode 0x7f
+    connector.provide_data_to_read(&[
+        0x02, 0x00, 0x20, 0x07, 0x00, 0x03, 0x00, 0x04, 0x00, 0x7f, 0x01, 0x02,
+    ]);
+    // truncated HandleValueNotification
+    connector.provide_data_to_read(&[
+        0x02, 0x00, 0x20, 0x06, 0x00, 0x02, 0x00, 0x04, 0x00, 0x1b, 0x01,
+    ]);
+    // ErrorRsp { request_opcode: 0x10, handle: 0x0006, code: AttributeNotFound }
+    connector.provide_data_to_read(&[
+        0x02, 0x00, 0x20, 0x09, 0x00, 0x05, 0x00, 0x04, 0x00, 0x01, 0x10, 0x06, 0x00, 0x0a,
+    ]);
+    let mut services = Vec::new();
+    assert_matches!(
+        client.discover_primary_services(|service| services.push(service)),
+        Ok(())
+    );
+    assert_eq!(
+        services,
+        [DiscoveredService {
+            start_handle: 0x0001,
+            end_handle: 0x0005,
+            uuid: Uuid::Uuid16(0x180f),
+        }]
+    );
 
-    let mut rf1 = || Data::default();
-    let mut wf1 = |_data: Data| {};
+    // truncated ReadByGroupTypeRsp
+    connector.reset();
+    connector.provide_data_to_read(&[
+        0x02, 0x00, 0x20, 0x08, 0x00, 0x04, 0x00, 0x04, 0x00, 0x11, 0x06, 0x01, 0x00,
+    ]);
+    assert_matches!(
+        client.discover_primary_services(|_| ()),
+        Err(GattClientError::AttError(_))
+    );
+}
 
-    let srv1 = Service::new(
-        Uuid::Uuid128([
-            0xC9, 0x15, 0x15, 0x96, 0x54, 0x56, 0x64, 0xB3, 0x38, 0x45, 0x26, 0x5D, 0xF1, 0x62,
-            0x6A, 0xA8,
-        ]),
-        ATT_READABLE | ATT_WRITEABLE,
-        &mut rf1,
-        &mut wf1,
+#[test]
+fn gatt_client_discovers_included_services() {
+    let connector = connector();
+    let mut ble = Ble::new(&connector);
+    let mut client = GattClient::new(&mut ble, 0x0001);
+
+    // data of another connection, dropped while waiting
+    connector.provide_data_to_read(&[
+        0x02, 0x00, 0x20, 0x08, 0x00, 0x04, 0x00, 0x04, 0x00, 0x1b, 0x03, 0x00, 0x61,
+    ]);
+    // ExchangeMtuRsp { mtu: 0x30 }
+    connector.provide_data_to_read(&[
+        0x02, 0x01, 0x20, 0x07, 0x00, 0x03, 0x00, 0x04, 0x00, 0x03, 0x30, 0x00,
+    ]);
+    assert_matches!(client.exchange_mtu(0x0040), Ok(0x0030));
+    assert_eq!(client.mtu(), 0x0030);
+    let written = connector.get_written_data();
+    assert_eq!(
+        written.to_slice(),
+        &[0x02, 0x01, 0x20, 0x07, 0x00, 0x03, 0x00, 0x04, 0x00, 0x02, 0x40, 0x00]
     );
 
-    let mut rf2 = || Data::default();
-    let mut wf2 = |_data: Data| {};
+    // ReadByTypeRsp { include 2 -> 6-8 without UUID }
+    connector.reset();
+    connector.provide_data_to_read(&[
+        0x02, 0x01, 0x20, 0x0c, 0x00, 0x08, 0x00, 0x04, 0x00, 0x09, 0x06, 0x02, 0x00, 0x06, 0x00,
+        0x08, 0x00,
+    ]);
+    // ReadRsp { 128-bit UUID of the service at 6 }
+    connector.provide_data_to_read(&[
+        0x02, 0x01, 0x20, 0x15, 0x00, 0x11, 0x00, 0x04, 0x00, 0x0b, 0x0f, 0x0e, 0x0d, 0x0c, 0x0b,
+        0x0a, 0x09, 0x08, 0x07, 0x06, 0x05, 0x04, 0x03, 0x02, 0x01, 0x00,
+    ]);
+    // ReadByTypeRsp { include 3 -> 9-10 0x180f }
+    connector.provide_data_to_read(&[
+        0x02, 0x01, 0x20, 0x0e, 0x00, 0x0a, 0x00, 0x04, 0x00, 0x09, 0x08, 0x03, 0x00, 0x09, 0x00,
+        0x0a, 0x00, 0x0f, 0x18,
+    ]);
+    // ErrorRsp { request_opcode: 0x08, handle: 0x0004, code: AttributeNotFound }
+    connector.provide_data_to_read(&[
+        0x02, 0x01, 0x20, 0x09, 0x00, 0x05, 0x00, 0x04, 0x00, 0x01, 0x08, 0x04, 0x00, 0x0a,
+    ]);
+    let mut included = Vec::new();
+    assert_matches!(
+        client.discover_included_services(0x0001, 0x0005, |service| included.push(service)),
+        Ok(())
+    );
+    assert_eq!(
+        included,
+        [
+            IncludedService {
+                handle: 0x0002,
+                start_handle: 0x0006,
+                end_handle: 0x0008,
+                uuid: Uuid::Uuid128([
+                    0x00, 0x01, 0x02, 0x03, 0x04, 0x05, 0x06, 0x07, 0x08, 0x09, 0x0a, 0x0b, 0x0c,
+                    0x0d, 0x0e, 0x0f
+                ]),
+            },
+            IncludedService {
+                handle: 0x0003,
+                start_handle: 0x0009,
+                end_handle: 0x000a,
+                uuid: Uuid::Uuid16(0x180f),
+            },
+        ]
+    );
+    // check requests (include declarations from 1, read of 6, include declarations from 3 and 4)
+    let written = connector.get_written_data();
+    assert_eq!(
+        written.to_slice(),
+        &[
+            0x02, 0x01, 0x20, 0x0b, 0x00, 0x07, 0x00, 0x04, 0x00, 0x08, 0x01, 0x00, 0x05, 0x00,
+            0x02, 0x28, 0x02, 0x01, 0x20, 0x07, 0x00, 0x03, 0x00, 0x04, 0x00, 0x0a, 0x06, 0x00,
+            0x02, 0x01, 0x20, 0x0b, 0x00, 0x07, 0x00, 0x04, 0x00, 0x08, 0x03, 0x00, 0x05, 0x00,
+            0x02, 0x28, 0x02, 0x01, 0x20, 0x0b, 0x00, 0x07, 0x00, 0x04, 0x00, 0x08, 0x04, 0x00,
+            0x05, 0x00, 0x02, 0x28
+        ]
+    );
 
-    let srv2 = Service::new(
-        Uuid::Uuid128([
-            0xC8, 0x15, 0x15, 0x96, 0x54, 0x56, 0x64, 0xB3, 0x38, 0x45, 0x26, 0x5D, 0xF1, 0x62,
-            0x6A, 0xA8,
-        ]),
-        ATT_READABLE | ATT_WRITEABLE,
-        &mut rf2,
-        &mut wf2,
+    // Disconnection Complete { handle: 0x0001 }
+    connector.reset();
+    connector.provide_data_to_read(&[0x04, 0x05, 0x04, 0x00, 0x01, 0x00, 0x13]);
+    assert_matches!(
+        client.discover_primary_services(|_| ()),
+        Err(GattClientError::Disconnected)
     );
+}
 
-    let services = &mut [srv1, srv2];
-    let mut srv = AttributeServer::new(&mut ble, services);
+#[test]
+fn gatt_client_reads_and_writes_values() {
+    let connector = connector();
+    let mut ble = Ble::new(&connector);
 
-    // ReadByGroupTypeReq { start: 1, end: ffff, group_type: Uuid16(2800) }
+    let notifications = RefCell::new(Vec::<Notification>::new());
+    let mut handler = |notification: Notification| notifications.borrow_mut().push(notification);
+
+    let mut client = GattClient::new(&mut ble, 0x0000);
+    client.set_notification_handler(&mut handler);
+
+    // HandleValueNotification { handle: 3, value: [0x61] } while waiting for the response
     connector.provide_data_to_read(&[
-        0x02, 0x00, 0x20, 0x0b, 0x00, 0x07, 0x00, 0x04, 0x00, 0x10, 0x01, 0x00, 0xff, 0xff, 0x00,
-        0x28,
+        0x02, 0x00, 0x20, 0x08, 0x00, 0x04, 0x00, 0x04, 0x00, 0x1b, 0x03, 0x00, 0x61,
     ]);
-    assert_matches!(srv.do_work(), Ok(()));
-    // check response (1-3, 0x2800)
-    let response_data = connector.get_written_data();
+    // ReadRsp with MTU - 1 bytes
+    connector.provide_data_to_read(&[0x02, 0x00, 0x20, 0x1b, 0x00, 0x17, 0x00, 0x04, 0x00, 0x0b]);
+    connector.provide_data_to_read(b"0123456789abcdefghijkl");
+    // ReadBlobRsp with the rest
+    connector.provide_data_to_read(&[0x02, 0x00, 0x20, 0x09, 0x00, 0x05, 0x00, 0x04, 0x00, 0x0d]);
+    connector.provide_data_to_read(b"mnop");
+
+    let value = client.read(0x0003).unwrap();
+    assert_eq!(value.to_slice(), b"0123456789abcdefghijklmnop");
+    // check requests (ReadReq { handle: 3 }, ReadBlobReq { handle: 3, offset: 22 })
+    let written = connector.get_written_data();
     assert_eq!(
-        response_data.to_slice(),
+        written.to_slice(),
         &[
-            0x02, 0x00, 0x20, 0x0c, 0x00, 0x08, 0x00, 0x04, 0x00, 0x11, 0x06, 0x01, 0x00, 0x03,
-            0x00, 0x00, 0x28
+            0x02, 0x00, 0x20, 0x07, 0x00, 0x03, 0x00, 0x04, 0x00, 0x0a, 0x03, 0x00, 0x02, 0x00,
+            0x20, 0x09, 0x00, 0x05, 0x00, 0x04, 0x00, 0x0c, 0x03, 0x00, 0x16, 0x00
         ]
     );
+    assert_eq!(notifications.borrow().len(), 1);
+    assert_eq!(notifications.borrow()[0].handle, 0x0003);
+    assert_eq!(notifications.borrow()[0].value.to_slice(), &[0x61]);
 
-    // ReadByGroupTypeReq { start: 4, end: ffff, group_type: Uuid16(2800) }
+    // WriteRsp
+    connector.reset();
+    connector.provide_data_to_read(&[0x02, 0x00, 0x20, 0x05, 0x00, 0x01, 0x00, 0x04, 0x00, 0x13]);
+    assert_matches!(client.write(0x0005, &Data::new(&[0x01, 0x02])), Ok(()));
+    client.write_without_response(0x0005, &Data::new(&[0x01, 0x02]));
+    // check requests (WriteReq and WriteCmd { handle: 5, data: [0x01, 0x02] })
+    let written = connector.get_written_data();
+    assert_eq!(
+        written.to_slice(),
+        &[
+            0x02, 0x00, 0x20, 0x09, 0x00, 0x05, 0x00, 0x04, 0x00, 0x12, 0x05, 0x00, 0x01, 0x02,
+            0x02, 0x00, 0x20, 0x09, 0x00, 0x05, 0x00, 0x04, 0x00, 0x52, 0x05, 0x00, 0x01, 0x02
+        ]
+    );
+
+    // PrepareWriteRsp echoes for offsets 0 and 18, ExecuteWriteRsp
     connector.reset();
     connector.provide_data_to_read(&[
-        0x02, 0x00, 0x20, 0x0b, 0x00, 0x07, 0x00, 0x04, 0x00, 0x10, 0x04, 0x00, 0xff, 0xff, 0x00,
-        0x28,
+        0x02, 0x00, 0x20, 0x1b, 0x00, 0x17, 0x00, 0x04, 0x00, 0x17, 0x05, 0x00, 0x00, 0x00,
     ]);
-    assert_matches!(srv.do_work(), Ok(()));
-    // check response (4-6, 0x2800)
-    let response_data = connector.get_written_data();
+    connector.provide_data_to_read(b"0123456789abcdefgh");
+    connector.provide_data_to_read(&[
+        0x02, 0x00, 0x20, 0x0b, 0x00, 0x07, 0x00, 0x04, 0x00, 0x17, 0x05, 0x00, 0x12, 0x00, b'i',
+        b'j',
+    ]);
+    connector.provide_data_to_read(&[0x02, 0x00, 0x20, 0x05, 0x00, 0x01, 0x00, 0x04, 0x00, 0x19]);
+    assert_matches!(
+        client.write_long(0x0005, &Data::new(b"0123456789abcdefghij")),
+        Ok(())
+    );
+    let written = connector.get_written_data();
     assert_eq!(
-        response_data.to_slice(),
+        written.to_slice(),
+        [
+            &[0x02, 0x00, 0x20, 0x1b, 0x00, 0x17, 0x00, 0x04, 0x00, 0x16, 0x05, 0x00, 0x00, 0x00][..],
+            b"0123456789abcdefgh",
+            &[
+                0x02, 0x00, 0x20, 0x0b, 0x00, 0x07, 0x00, 0x04, 0x00, 0x16, 0x05, 0x00, 0x12,
+                0x00, b'i', b'j'
+            ],
+            &[0x02, 0x00, 0x20, 0x06, 0x00, 0x02, 0x00, 0x04, 0x00, 0x18, 0x01],
+        ]
+        .concat()
+    );
+
+    // PrepareWriteRsp with a wrong echo, ExecuteWriteRsp for the cancel
+    connector.reset();
+    connector.provide_data_to_read(&[
+        0x02, 0x00, 0x20, 0x0b, 0x00, 0x07, 0x00, 0x04, 0x00, 0x17, 0x05, 0x00, 0x00, 0x00, b'a',
+        b'x',
+    ]);
+    connector.provide_data_to_read(&[0x02, 0x00, 0x20, 0x05, 0x00, 0x01, 0x00, 0x04, 0x00, 0x19]);
+    assert_matches!(
+        client.write_long(0x0005, &Data::new(b"ab")),
+        Err(GattClientError::UnexpectedResponse)
+    );
+    let written = connector.get_written_data();
+    assert_eq!(
+        written.to_slice(),
         &[
-            0x02, 0x00, 0x20, 0x0c, 0x00, 0x08, 0x00, 0x04, 0x00, 0x11, 0x06, 0x04, 0x00, 0x06,
-            0x00, 0x00, 0x28
+            0x02, 0x00, 0x20, 0x0b, 0x00, 0x07, 0x00, 0x04, 0x00, 0x16, 0x05, 0x00, 0x00, 0x00,
+            b'a', b'b', 0x02, 0x00, 0x20, 0x06, 0x00, 0x02, 0x00, 0x04, 0x00, 0x18, 0x00
         ]
     );
 
-    // ReadByGroupTypeReq { start: 7, end: ffff, group_type: Uuid16(2800) }
+    // ErrorRsp { request_opcode: 0x0a, handle: 0x0003, code: ReadNotPermitted }
     connector.reset();
     connector.provide_data_to_read(&[
-        0x02, 0x00, 0x20, 0x0b, 0x00, 0x07, 0x00, 0x04, 0x00, 0x10, 0x07, 0x00, 0xff, 0xff, 0x00,
-        0x28,
+        0x02, 0x00, 0x20, 0x09, 0x00, 0x05, 0x00, 0x04, 0x00, 0x01, 0x0a, 0x03, 0x00, 0x02,
     ]);
-    assert_matches!(srv.do_work(), Ok(()));
-    // check response (not found)
-    let response_data = connector.get_written_data();
+    assert_matches!(
+        client.read(0x0003),
+        Err(GattClientError::ErrorResponse {
+            handle: 0x0003,
+            code: AttErrorCode::ReadNotPermitted,
+        })
+    );
+}
+
+#[test]
+fn gatt_client_subscribes_and_confirms_indications() {
+    let connector = connector();
+    let mut ble = Ble::new(&connector);
+
+    let notifications = RefCell::new(Vec::<Notification>::new());
+    let mut handler = |notification: Notification| notifications.borrow_mut().push(notification);
+
+    let mut client = GattClient::new(&mut ble, 0x0000);
+    client.set_notification_handler(&mut handler);
+
+    // WriteRsp
+    connector.provide_data_to_read(&[0x02, 0x00, 0x20, 0x05, 0x00, 0x01, 0x00, 0x04, 0x00, 0x13]);
+    assert_matches!(client.subscribe(0x0004, true), Ok(()));
+    // check request (WriteReq { handle: 4, data: [0x02, 0x00] })
+    let written = connector.get_written_data();
     assert_eq!(
-        response_data.to_slice(),
-        &[0x02, 0x00, 0x20, 0x09, 0x00, 0x05, 0x00, 0x04, 0x00, 0x01, 0x10, 0x07, 0x00, 0x0a]
+        written.to_slice(),
+        &[0x02, 0x00, 0x20, 0x09, 0x00, 0x05, 0x00, 0x04, 0x00, 0x12, 0x04, 0x00, 0x02, 0x00]
+    );
+
+    // nothing received
+    connector.reset();
+    assert_matches!(client.do_work(), Ok(()));
+    assert_eq!(notifications.borrow().len(), 0);
+
+    // HandleValueIndication { handle: 3, value: [0x62] }
+    connector.reset();
+    connector.provide_data_to_read(&[
+        0x02, 0x00, 0x20, 0x08, 0x00, 0x04, 0x00, 0x04, 0x00, 0x1d, 0x03, 0x00, 0x62,
+    ]);
+    assert_matches!(client.do_work(), Ok(()));
+    assert_eq!(notifications.borrow().len(), 1);
+    assert_eq!(notifications.borrow()[0].handle, 0x0003);
+    assert_eq!(notifications.borrow()[0].value.to_slice(), &[0x62]);
+    assert!(notifications.borrow()[0].indication);
+    // check confirmation
+    let written = connector.get_written_data();
+    assert_eq!(
+        written.to_slice(),
+        &[0x02, 0x00, 0x20, 0x05, 0x00, 0x01, 0x00, 0x04, 0x00, 0x1e]
+    );
+
+    // WriteRsp
+    connector.reset();
+    connector.provide_data_to_read(&[0x02, 0x00, 0x20, 0x05, 0x00, 0x01, 0x00, 0x04, 0x00, 0x13]);
+    assert_matches!(client.unsubscribe(0x0004), Ok(()));
+    let written = connector.get_written_data();
+    assert_eq!(
+        written.to_slice(),
+        &[0x02, 0x00, 0x20, 0x09, 0x00, 0x05, 0x00, 0x04, 0x00, 0x12, 0x04, 0x00, 0x00, 0x00]
     );
+
+    // Disconnection Complete { handle: 0x0000 }
+    connector.reset();
+    connector.provide_data_to_read(&[0x04, 0x05, 0x04, 0x00, 0x00, 0x00, 0x13]);
+    assert_matches!(client.do_work(), Err(GattClientError::Disconnected));
 }